@@ -1,5 +1,16 @@
+mod clarification;
 mod classification;
+mod correction;
+mod dictation;
 mod model;
 
+pub use clarification::{
+    ClarificationAnswer, clarification_request, is_confirmation, resolve_unconfirmed,
+};
 pub use classification::IntentClassifier;
+pub use correction::{
+    AppliedCorrection, Correction, CorrectionLog, WordChange, correct_last_user_message,
+    correct_text, detect_correction,
+};
+pub use dictation::{Dictation, DictationStep, DictationTarget, dictation_command, run_dictation};
 pub use model::*;
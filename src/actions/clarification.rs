@@ -0,0 +1,184 @@
+//! When the transcription model is unsure about what the user said, the
+//! assistant should rather ask than act on a possibly wrong command.
+
+use super::correction::{AppliedCorrection, correct_text, detect_correction};
+use crate::speech::input::Confidence;
+
+/// Returns the question the assistant should ask the user, if the average
+/// confidence of the transcription falls below `threshold`.
+#[must_use]
+pub fn clarification_request(
+    text: &str,
+    confidence: Option<&Confidence>,
+    threshold: f64,
+) -> Option<String> {
+    let confidence = confidence?;
+    if confidence.average()? >= threshold {
+        return None;
+    }
+
+    let uncertain_words = confidence.uncertain_words(threshold);
+    let question = if uncertain_words.is_empty() {
+        format!(
+            "I'm not sure I understood you correctly. Did you say \"{text}\"? \
+            Please confirm or repeat."
+        )
+    } else {
+        let words = uncertain_words
+            .iter()
+            .map(|w| format!("\"{w}\""))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "I'm not sure I understood you correctly. Did you say \"{text}\"? \
+            I'm unsure about {words}. Please confirm or repeat."
+        )
+    };
+    Some(question)
+}
+
+/// Whether the user's answer to a clarification request confirms the
+/// transcription.
+#[must_use]
+pub fn is_confirmation(text: &str) -> bool {
+    let answer = text
+        .trim_matches(|c: char| !c.is_alphanumeric())
+        .to_lowercase();
+    matches!(
+        answer.as_str(),
+        "yes" | "yeah" | "yep" | "correct" | "right" | "exactly" | "confirm" | "confirmed"
+    ) || answer.starts_with("yes ")
+        || answer.starts_with("yes,")
+}
+
+/// How the user answered a clarification request
+#[derive(Debug)]
+pub enum ClarificationAnswer {
+    /// The transcription was right
+    Confirmed(String),
+    /// The transcription was fixed, e.g. with "no, I said X"
+    Corrected(AppliedCorrection),
+}
+
+/// Applies the user's `answer` to the `unconfirmed` transcription they have
+/// been asked about. Returns `None` if the answer neither confirms nor
+/// corrects it, so it counts as a new utterance.
+#[must_use]
+pub fn resolve_unconfirmed(unconfirmed: &str, answer: &str) -> Option<ClarificationAnswer> {
+    if is_confirmation(answer) {
+        return Some(ClarificationAnswer::Confirmed(unconfirmed.to_owned()));
+    }
+    let correction = detect_correction(answer)?;
+    correct_text(unconfirmed, &correction).map(ClarificationAnswer::Corrected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::speech::input::TokenConfidence;
+
+    fn confidence(tokens: &[(&str, f64)]) -> Confidence {
+        Confidence {
+            tokens: tokens
+                .iter()
+                .map(|(token, probability)| TokenConfidence {
+                    token: (*token).to_owned(),
+                    probability: *probability,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn asks_below_the_threshold() {
+        // Averages 0.75
+        let open_the_file = confidence(&[("Open", 1.0), (" the", 0.5), (" file", 0.75)]);
+        let cases = [
+            (Some(&open_the_file), 0.6, None),
+            (Some(&open_the_file), 0.75, None),
+            (
+                Some(&open_the_file),
+                0.8,
+                Some(
+                    "I'm not sure I understood you correctly. Did you say \"Open the file\"? \
+                    I'm unsure about \"the\", \"file\". Please confirm or repeat.",
+                ),
+            ),
+            (None, 0.8, None),
+        ];
+
+        for (confidence, threshold, expected) in cases {
+            assert_eq!(
+                clarification_request("Open the file", confidence, threshold).as_deref(),
+                expected,
+                "threshold: {threshold}"
+            );
+        }
+    }
+
+    #[test]
+    fn asks_without_uncertain_words_if_none_can_be_named() {
+        let cases = [
+            (confidence(&[]), None),
+            (
+                confidence(&[("...", 0.2)]),
+                Some(
+                    "I'm not sure I understood you correctly. Did you say \"...\"? \
+                    Please confirm or repeat.",
+                ),
+            ),
+        ];
+
+        for (confidence, expected) in cases {
+            assert_eq!(
+                clarification_request("...", Some(&confidence), 0.6).as_deref(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn recognizes_confirmations() {
+        let cases = [
+            ("yes", true),
+            ("Yes.", true),
+            ("YEAH!", true),
+            ("  Correct. ", true),
+            ("Yes, open it", true),
+            ("yes please", true),
+            ("Exactly...", true),
+            ("no", false),
+            ("yesterday", false),
+            ("Open the file", false),
+            ("", false),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(is_confirmation(text), expected, "text: {text:?}");
+        }
+    }
+
+    #[test]
+    fn applies_answers_to_the_unconfirmed_transcription() {
+        let cases = [
+            ("Yes", Some("open mane.rs")),
+            ("No, I said open main.rs", Some("open main.rs")),
+            ("no, replace mane with main", Some("open main.rs")),
+            // Nothing to replace
+            ("no, replace foo with bar", None),
+            ("I said open main.rs", None),
+            ("Open main.rs", None),
+        ];
+
+        for (answer, expected) in cases {
+            let resolved = resolve_unconfirmed("open mane.rs", answer).map(|answer| match answer {
+                ClarificationAnswer::Confirmed(text) => text,
+                ClarificationAnswer::Corrected(correction) => {
+                    assert_eq!(correction.original, "open mane.rs");
+                    correction.corrected
+                }
+            });
+            assert_eq!(resolved.as_deref(), expected, "answer: {answer}");
+        }
+    }
+}
@@ -3,11 +3,15 @@
 
 use std::marker::PhantomData;
 
+use serde::Deserialize;
+
 use crate::session::{
-    AIModel, Author, ConversationContext, InputModes, ModelInput, ModelOutput, OutputModes,
-    TextImageMessage, TextMessage,
+    AIModel, Author, CancellationToken, ConversationContext, InputModes, ModelInput, Outcome,
+    OutputModes, StructuredOutput, TextImageMessage, TextMessage, send_structured,
 };
 
+use crate::speech::input::language_name;
+
 use super::Intent;
 
 pub struct IntentClassifier<I: InputModes, O: OutputModes, M: AIModel<I, O>> {
@@ -52,11 +56,10 @@ pub struct IntentClassifier<I: InputModes, O: OutputModes, M: AIModel<I, O>> {
 
 // TODO is it an issue that RenderForExcerpt is more private than IntentClassifier?
 #[allow(private_bounds)]
-impl<I, O, M> IntentClassifier<I, O, M>
+impl<I, M> IntentClassifier<I, TextMessage, M>
 where
-    I: InputModes + RenderForExcerpt + Clone,
-    O: OutputModes + HasResponseText<O>,
-    M: AIModel<I, O>,
+    I: InputModes + RenderForExcerpt + Clone + From<TextMessage>,
+    M: AIModel<I, TextMessage>,
 {
     pub fn new(model: M) -> Self {
         Self {
@@ -66,9 +69,12 @@ where
         }
     }
 
+    /// Fails with [`crate::session::Cancelled`] if `cancellation` is
+    /// cancelled before the model has answered.
     pub async fn classify_intent(
         &self,
         recent_context: &ConversationContext<I>,
+        cancellation: &CancellationToken,
     ) -> anyhow::Result<Intent> {
         let intents = Intent::variants()
             .iter()
@@ -115,28 +121,57 @@ where
             {excerpt}\n\
             \"\"\"
             \n\
-            Return only the name of the classification.\n\
+            Return the name of the classification as the intent.\n\
             \n\
             Example:\n\
             Excerpt: <assistant>We can use XYZ<assistant><user>What is XYZ?</user>\n\
-            Your response: ask\n\
+            Your response: {{\"intent\": \"ask\"}}\n\
             "
         )
         .to_owned();
 
+        let instructions = match &recent_context.language {
+            Some(language) if language != "en" => format!(
+                "{instructions}\n\
+                The user speaks {}. Whenever you write text for the user, \
+                write it in {0}, but always return the name of the \
+                classification in English as listed above.\n",
+                language_name(language)
+            ),
+            _ => instructions,
+        };
+
         let model_input = ModelInput {
             instructions,
             log: recent_context.log.clone(),
+            tools: Vec::new(),
+            tool_results: Vec::new(),
+            json_format: None,
         };
 
-        let output = self.model.send(model_input).await?;
-        let response = O::get_assistant_text_response(&output)?;
-
-        println!("Response: {response}");
-        todo!()
+        let StructuredOutput { value, output } =
+            send_structured::<Classification, _, _>(&self.model, model_input, cancellation).await?;
+        match (output.outcome, value) {
+            (Outcome::Completed, Some(classification)) => Ok(classification.intent),
+            (Outcome::Completed, None) => anyhow::bail!("The model output appears to be empty"),
+            // The user is asked what they meant then
+            (Outcome::Refused { .. }, _) => Ok(Intent::Unclear),
+            (Outcome::Incomplete(reason), _) => {
+                anyhow::bail!("The intent could not be classified, {reason}")
+            }
+            (Outcome::Failed { error }, _) => {
+                anyhow::bail!("The intent could not be classified: {error}")
+            }
+        }
     }
 }
 
+/// The answer of the model
+#[derive(Deserialize)]
+struct Classification {
+    intent: Intent,
+}
+
 trait RenderForExcerpt: InputModes {
     fn render(&self) -> String;
 }
@@ -168,17 +203,3 @@ impl RenderForExcerpt for TextImageMessage {
         format!("<{author}>{text}</{author}>")
     }
 }
-
-trait HasResponseText<O: OutputModes> {
-    fn get_assistant_text_response(output: &ModelOutput<O>) -> anyhow::Result<String>;
-}
-
-impl HasResponseText<TextMessage> for TextMessage {
-    fn get_assistant_text_response(output: &ModelOutput<TextMessage>) -> anyhow::Result<String> {
-        output
-            .items
-            .last()
-            .ok_or(anyhow::anyhow!("The model output appears to be empty"))
-            .map(|text_item| text_item.parts.join("\n\n"))
-    }
-}
@@ -0,0 +1,374 @@
+//! Lets the user fix a misheard command by voice, e.g. "no, I said X" or
+//! "no, replace foo with bar". The previous user message is edited in place
+//! instead of adding a new turn, and the correction is logged, so frequently
+//! misheard words can be added to the transcription vocabulary later.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::session::{Author, ConversationContext, TextMessage};
+
+/// Phrases after which the user restates the whole previous message
+const RESTATEMENT_PREFIXES: &[&str] = &["i said", "i meant", "i actually said", "what i said was"];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Correction {
+    /// The previous message should be replaced completely
+    Restatement(String),
+    /// Occurrences of `from` in the previous message should be replaced
+    Replacement { from: String, to: String },
+}
+
+/// Checks whether `text` corrects the previous message.
+///
+/// "replace foo with bar" and "change foo to bar" are also regular commands
+/// for editing code, so they only count as corrections after a "no", and if
+/// the previous message actually contains "foo", see
+/// [`correct_last_user_message`].
+#[must_use]
+pub fn detect_correction(text: &str) -> Option<Correction> {
+    let text = text.trim();
+    let (text, has_no) = match strip_prefix_ignore_case(text, "no") {
+        Some(rest) if rest.starts_with([',', '.', '!', ' ']) => {
+            (rest.trim_start_matches([',', '.', '!', ' ']), true)
+        }
+        _ => (text, false),
+    };
+
+    for prefix in RESTATEMENT_PREFIXES {
+        // Without a "no" in front, "I said ..." may as well be a regular remark
+        if !has_no && *prefix != "i meant" {
+            continue;
+        }
+        if let Some(rest) = strip_prefix_ignore_case(text, prefix)
+            .filter(|rest| !rest.starts_with(char::is_alphanumeric))
+        {
+            let restated = trim_quotes(rest.trim_start_matches([',', ':', ' ']));
+            if !restated.is_empty() {
+                return Some(Correction::Restatement(restated.to_owned()));
+            }
+        }
+    }
+
+    if !has_no {
+        return None;
+    }
+    for (verb, separator) in [("replace ", " with "), ("change ", " to ")] {
+        let Some(rest) = strip_prefix_ignore_case(text, verb) else {
+            continue;
+        };
+        let lower = rest.to_ascii_lowercase();
+        if let Some(pos) = lower.find(separator) {
+            let from = trim_quotes(&rest[..pos]);
+            let to = trim_quotes(rest[pos + separator.len()..].trim_end_matches(['.', '!']));
+            if !from.is_empty() && !to.is_empty() {
+                return Some(Correction::Replacement {
+                    from: from.to_owned(),
+                    to: to.to_owned(),
+                });
+            }
+        }
+    }
+
+    None
+}
+
+/// A misheard part of a message and what the user meant instead
+#[derive(Debug, Serialize)]
+pub struct WordChange {
+    pub heard: String,
+    pub meant: String,
+}
+
+#[derive(Debug)]
+pub struct AppliedCorrection {
+    pub original: String,
+    pub corrected: String,
+    pub changes: Vec<WordChange>,
+}
+
+/// Applies `correction` to the last message of the user in `context`.
+/// Returns `None` if there is no such message, or a replacement doesn't match
+/// anything in it.
+pub fn correct_last_user_message(
+    context: &mut ConversationContext<TextMessage>,
+    correction: &Correction,
+) -> Option<AppliedCorrection> {
+    let message = context
+        .log
+        .iter_mut()
+        .rev()
+        .find(|msg| matches!(msg.author, Author::User))?;
+    let applied = correct_text(&message.parts.join(" "), correction)?;
+    message.parts = vec![applied.corrected.clone()];
+    Some(applied)
+}
+
+/// Applies `correction` to `text`. Returns `None` if it's a replacement that
+/// doesn't match anything in `text`.
+#[must_use]
+pub fn correct_text(text: &str, correction: &Correction) -> Option<AppliedCorrection> {
+    let corrected = match correction {
+        Correction::Restatement(restated) => restated.clone(),
+        Correction::Replacement { from, to } => replace_words(text, from, to)?,
+    };
+    Some(AppliedCorrection {
+        changes: word_changes(text, &corrected),
+        original: text.to_owned(),
+        corrected,
+    })
+}
+
+/// Appends corrections as JSON lines to a file.
+pub struct CorrectionLog {
+    /// Without a file, corrections are not recorded
+    file: Option<PathBuf>,
+}
+
+#[derive(Serialize)]
+struct CorrectionLogEntry<'a> {
+    /// Seconds since the Unix epoch
+    timestamp: u64,
+    original: &'a str,
+    corrected: &'a str,
+    changes: &'a [WordChange],
+}
+
+impl CorrectionLog {
+    #[must_use]
+    pub fn new(file: Option<PathBuf>) -> Self {
+        Self { file }
+    }
+
+    pub fn record(&self, correction: &AppliedCorrection) -> anyhow::Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let entry = CorrectionLogEntry {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            original: &correction.original,
+            corrected: &correction.corrected,
+            changes: &correction.changes,
+        };
+        let line = serde_json::to_string(&entry).context("Failed to serialize correction")?;
+
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir)
+                .context(format!("Failed to create directory {}", dir.display()))?;
+        }
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file)
+            .context(format!("Failed to open correction log {}", file.display()))?;
+        writeln!(log, "{line}")
+            .context(format!("Failed to write correction log {}", file.display()))
+    }
+}
+
+/// Replaces whole-word occurrences of `from` in `text`, ignoring case.
+fn replace_words(text: &str, from: &str, to: &str) -> Option<String> {
+    let lower_text = text.to_ascii_lowercase();
+    let lower_from = from.to_ascii_lowercase();
+    let is_boundary = |c: Option<char>| c.is_none_or(|c| !c.is_alphanumeric());
+
+    let mut result = String::new();
+    let mut copied = 0;
+    let mut search_from = 0;
+    while let Some(pos) = lower_text[search_from..].find(&lower_from) {
+        let start = search_from + pos;
+        let end = start + lower_from.len();
+        search_from = end;
+        if is_boundary(text[..start].chars().next_back()) && is_boundary(text[end..].chars().next())
+        {
+            result.push_str(&text[copied..start]);
+            result.push_str(to);
+            copied = end;
+        }
+    }
+    if copied == 0 {
+        return None;
+    }
+    result.push_str(&text[copied..]);
+    Some(result)
+}
+
+/// Compares the words of `old` and `new` and returns the differing parts,
+/// based on their longest common subsequence.
+fn word_changes(old: &str, new: &str) -> Vec<WordChange> {
+    let old: Vec<&str> = old.split_whitespace().collect();
+    let new: Vec<&str> = new.split_whitespace().collect();
+    let key = |w: &str| {
+        w.trim_matches(|c: char| !c.is_alphanumeric())
+            .to_lowercase()
+    };
+
+    // lcs[i][j] is the length of the common subsequence of old[i..], new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if key(old[i]) == key(new[j]) {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut changes = Vec::new();
+    let (mut heard, mut meant) = (Vec::new(), Vec::new());
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && key(old[i]) == key(new[j]) {
+            push_change(&mut changes, &mut heard, &mut meant);
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            meant.push(new[j]);
+            j += 1;
+        } else {
+            heard.push(old[i]);
+            i += 1;
+        }
+    }
+    push_change(&mut changes, &mut heard, &mut meant);
+
+    changes
+}
+
+fn push_change(changes: &mut Vec<WordChange>, heard: &mut Vec<&str>, meant: &mut Vec<&str>) {
+    if heard.is_empty() && meant.is_empty() {
+        return;
+    }
+    changes.push(WordChange {
+        heard: heard.join(" "),
+        meant: meant.join(" "),
+    });
+    heard.clear();
+    meant.clear();
+}
+
+fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = text.get(..prefix.len())?;
+    head.eq_ignore_ascii_case(prefix)
+        .then(|| &text[prefix.len()..])
+}
+
+fn trim_quotes(text: &str) -> &str {
+    text.trim().trim_matches(['"', '\'', '“', '”']).trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_corrections() {
+        let restated = |text: &str| Some(Correction::Restatement(text.to_owned()));
+        let replaced = |from: &str, to: &str| {
+            Some(Correction::Replacement {
+                from: from.to_owned(),
+                to: to.to_owned(),
+            })
+        };
+        let cases = [
+            ("No, I said open main.rs", restated("open main.rs")),
+            (
+                "no. what I said was \"open main.rs\"",
+                restated("open main.rs"),
+            ),
+            ("I meant: open lib.rs", restated("open lib.rs")),
+            // Without a "no", these may be regular remarks or commands
+            ("I said open main.rs", None),
+            ("Replace foo with bar", None),
+            ("change the color to red", None),
+            ("No, replace 'foo' with bar.", replaced("foo", "bar")),
+            (
+                "no change the colour TO color",
+                replaced("the colour", "color"),
+            ),
+            ("No, ändere Größe to size", None),
+            ("No, replace Größe with size", replaced("Größe", "size")),
+            ("Nothing else", None),
+            ("No, I saidnothing", None),
+            ("No, I said", None),
+            ("No", None),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(detect_correction(text), expected, "text: {text}");
+        }
+    }
+
+    #[test]
+    fn replaces_whole_words() {
+        let cases = [
+            ("open the file", "file", "folder", Some("open the folder")),
+            ("Open File.rs", "file", "lib", Some("Open lib.rs")),
+            (
+                "profile the file",
+                "file",
+                "folder",
+                Some("profile the folder"),
+            ),
+            ("a b a b", "a b", "c", Some("c c")),
+            (
+                "öffne die Größe",
+                "größe",
+                "Breite",
+                Some("öffne die Breite"),
+            ),
+            // Non-ASCII letters aren't word boundaries
+            ("die Grüße", "ße", "x", None),
+            ("über über", "über", "unter", Some("unter unter")),
+            ("profile", "file", "folder", None),
+        ];
+
+        for (text, from, to, expected) in cases {
+            assert_eq!(
+                replace_words(text, from, to).as_deref(),
+                expected,
+                "{from} in {text}"
+            );
+        }
+    }
+
+    #[test]
+    fn finds_the_changed_words() {
+        let cases = [
+            ("open main", "open main", vec![]),
+            ("open mane.rs", "open main.rs", vec![("mane.rs", "main.rs")]),
+            ("Open the file.", "open the file", vec![]),
+            (
+                "the fall and the fall",
+                "the file and the fall",
+                vec![("fall", "file")],
+            ),
+            ("a b c", "a x b y c", vec![("", "x"), ("", "y")]),
+            ("a x y c", "a c", vec![("x y", "")]),
+            ("öffne Straße", "öffne STRASSE", vec![("Straße", "STRASSE")]),
+            ("Öffne Über", "öffne über", vec![]),
+            ("", "open", vec![("", "open")]),
+        ];
+
+        for (old, new, expected) in cases {
+            let changes: Vec<(String, String)> = word_changes(old, new)
+                .into_iter()
+                .map(|c| (c.heard, c.meant))
+                .collect();
+            let expected: Vec<(String, String)> = expected
+                .into_iter()
+                .map(|(heard, meant)| (heard.to_owned(), meant.to_owned()))
+                .collect();
+            assert_eq!(changes, expected, "{old} -> {new}");
+        }
+    }
+}
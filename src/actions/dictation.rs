@@ -0,0 +1,391 @@
+//! Dictating prose like commit messages, doc comments or notes, instead of
+//! giving commands.
+//!
+//! After e.g. "dictate to file notes.md", transcripts are appended to the
+//! target as they come in, with spoken punctuation like "comma" or "new
+//! paragraph" turned into the actual characters, until "stop dictation" is
+//! said.
+
+use std::fmt::Display;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::logger::Logger;
+use crate::session::ScratchBuffer;
+use crate::speech::input::{SpeechListener, Transcription};
+
+/// Phrases starting dictation into the scratch buffer
+const START_PHRASES: &[&str] = &[
+    "start dictation",
+    "start dictating",
+    "dictation mode",
+    "take a note",
+    "take note",
+];
+
+/// Phrases followed by the target of the dictation
+const START_WITH_TARGET_PREFIXES: &[&str] = &[
+    "dictate to",
+    "dictate into",
+    "start dictation to",
+    "start dictation into",
+];
+
+const EXIT_PHRASES: &[&[&str]] = &[
+    &["stop", "dictation"],
+    &["stop", "dictating"],
+    &["end", "dictation"],
+    &["exit", "dictation"],
+    &["finish", "dictation"],
+];
+
+/// Spoken punctuation and what it's written as
+const PUNCTUATION_COMMANDS: &[(&[&str], &str)] = &[
+    (&["new", "paragraph"], "\n\n"),
+    (&["new", "line"], "\n"),
+    (&["newline"], "\n"),
+    (&["full", "stop"], "."),
+    (&["period"], "."),
+    (&["comma"], ","),
+    (&["question", "mark"], "?"),
+    (&["exclamation", "mark"], "!"),
+    (&["exclamation", "point"], "!"),
+    (&["colon"], ":"),
+    (&["semicolon"], ";"),
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DictationTarget {
+    /// Appended to, and created if it doesn't exist
+    File(PathBuf),
+    Stdout,
+    Scratch,
+}
+
+impl Display for DictationTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "{}", path.display()),
+            Self::Stdout => f.write_str("the terminal"),
+            Self::Scratch => f.write_str("the scratch buffer"),
+        }
+    }
+}
+
+/// Checks whether `text` asks to start dictating, and if so, returns the
+/// target. File paths are resolved against `project_dir`, and must not lead
+/// out of it.
+#[must_use]
+pub fn dictation_command(text: &str, project_dir: &Path) -> Option<DictationTarget> {
+    let text = text.trim().trim_end_matches(['.', '!']);
+    let lower = text.to_lowercase();
+
+    if START_PHRASES.contains(&lower.as_str()) {
+        return Some(DictationTarget::Scratch);
+    }
+
+    let prefix = START_WITH_TARGET_PREFIXES
+        .iter()
+        .find(|prefix| lower.starts_with(&format!("{prefix} ")))?;
+    let target = text.get(prefix.len()..)?.trim();
+    let target = target
+        .strip_prefix("the ")
+        .or_else(|| target.strip_prefix("The "))
+        .unwrap_or(target);
+    match target.to_lowercase().as_str() {
+        "terminal" | "stdout" | "standard out" | "screen" => Some(DictationTarget::Stdout),
+        "scratch buffer" | "scratch" | "buffer" => Some(DictationTarget::Scratch),
+        lower_target => {
+            let file = lower_target.strip_prefix("file ")?;
+            let file = target.get(target.len() - file.len()..)?;
+            project_file(project_dir, file.trim()).map(DictationTarget::File)
+        }
+    }
+}
+
+/// Joins `file` to `project_dir`, unless it's absolute or contains `..`
+fn project_file(project_dir: &Path, file: &str) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in Path::new(file).components() {
+        match component {
+            Component::Normal(name) => relative.push(name),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    if relative.as_os_str().is_empty() {
+        return None;
+    }
+    let path = project_dir.join(relative);
+    path.starts_with(project_dir).then_some(path)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DictationStep {
+    Continue,
+    /// The exit phrase has been said
+    Finished,
+}
+
+pub struct Dictation {
+    target: DictationTarget,
+    /// The last character written, to decide about spaces and capitalization
+    last_char: Option<char>,
+}
+
+impl Dictation {
+    #[must_use]
+    pub fn new(target: DictationTarget) -> Self {
+        Self {
+            target,
+            last_char: None,
+        }
+    }
+
+    #[must_use]
+    pub fn target(&self) -> &DictationTarget {
+        &self.target
+    }
+
+    /// Formats and writes the next transcript, up to an exit phrase.
+    pub fn dictate(
+        &mut self,
+        transcript: &str,
+        scratch: &mut ScratchBuffer,
+    ) -> anyhow::Result<DictationStep> {
+        let tokens: Vec<&str> = transcript.split_whitespace().collect();
+        let keys: Vec<String> = tokens
+            .iter()
+            .map(|t| {
+                t.trim_matches(|c: char| !c.is_alphanumeric())
+                    .to_lowercase()
+            })
+            .collect();
+
+        let mut text = String::new();
+        let mut step = DictationStep::Continue;
+        let mut i = 0;
+        while i < tokens.len() {
+            if phrase_len(&keys[i..], EXIT_PHRASES.iter().copied()).is_some() {
+                step = DictationStep::Finished;
+                break;
+            }
+            let command = PUNCTUATION_COMMANDS
+                .iter()
+                .find_map(|(phrase, symbol)| Some((phrase_len(&keys[i..], [*phrase])?, *symbol)));
+            if let Some((len, symbol)) = command {
+                self.push_symbol(&mut text, symbol);
+                i += len;
+            } else {
+                self.push_word(&mut text, tokens[i]);
+                i += 1;
+            }
+        }
+        if step == DictationStep::Finished && self.last_char.is_some_and(|c| c != '\n') {
+            text.push('\n');
+        }
+
+        self.write(&text, scratch)?;
+        Ok(step)
+    }
+
+    fn push_word(&mut self, text: &mut String, word: &str) {
+        let Some(last_char) = self.last_char else {
+            text.push_str(&capitalize(word));
+            self.last_char = word.chars().last();
+            return;
+        };
+        if last_char != '\n' {
+            text.push(' ');
+        }
+        if matches!(last_char, '.' | '!' | '?' | '\n') {
+            text.push_str(&capitalize(word));
+        } else {
+            text.push_str(word);
+        }
+        self.last_char = word.chars().last();
+    }
+
+    fn push_symbol(&mut self, text: &mut String, symbol: &str) {
+        if !symbol.starts_with('\n') {
+            // The transcription model may already have added punctuation
+            // where the user said it
+            let trimmed_len = text.trim_end_matches(['.', ',', '!', '?', ':', ';']).len();
+            text.truncate(trimmed_len);
+        }
+        text.push_str(symbol);
+        self.last_char = symbol.chars().last();
+    }
+
+    fn write(&self, text: &str, scratch: &mut ScratchBuffer) -> anyhow::Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        match &self.target {
+            DictationTarget::File(path) => {
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir)
+                        .context(format!("Failed to create directory {}", dir.display()))?;
+                }
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .context(format!("Failed to open {}", path.display()))?;
+                file.write_all(text.as_bytes())
+                    .context(format!("Failed to write to {}", path.display()))
+            }
+            DictationTarget::Stdout => {
+                let mut stdout = std::io::stdout();
+                stdout
+                    .write_all(text.as_bytes())
+                    .and_then(|()| stdout.flush())
+                    .context("Failed to write to stdout")
+            }
+            DictationTarget::Scratch => {
+                scratch.append(text);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Appends everything the user says to the target of `dictation`, until the
+/// exit phrase is said.
+pub async fn run_dictation(
+    listener: &mut SpeechListener,
+    mut dictation: Dictation,
+    scratch: &mut ScratchBuffer,
+    logger: Logger,
+) -> anyhow::Result<()> {
+    logger.info(format!(
+        "Dictating to {}, say \"stop dictation\" when you're done",
+        dictation.target()
+    ));
+    listener.set_code_normalization(false);
+
+    let result = async {
+        loop {
+            let Transcription::Some { text, .. } = listener.listen_to_input().await? else {
+                continue;
+            };
+            if dictation.dictate(&text, scratch)? == DictationStep::Finished {
+                return anyhow::Ok(());
+            }
+        }
+    }
+    .await;
+
+    listener.set_code_normalization(true);
+    logger.info("Stopped dictating");
+    result
+}
+
+/// The number of words of the first phrase `keys` starts with, if any
+fn phrase_len<'a>(
+    keys: &[String],
+    phrases: impl IntoIterator<Item = &'a [&'a str]>,
+) -> Option<usize> {
+    phrases
+        .into_iter()
+        .find(|phrase| keys.len() >= phrase.len() && phrase.iter().zip(keys).all(|(p, k)| p == k))
+        .map(<[&str]>::len)
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_dictation_commands() {
+        let project_dir = Path::new("/home/user/project");
+        let file = |path: &str| Some(DictationTarget::File(project_dir.join(path)));
+        let cases = [
+            ("Start dictation.", Some(DictationTarget::Scratch)),
+            ("take a note", Some(DictationTarget::Scratch)),
+            ("Dictate to the terminal", Some(DictationTarget::Stdout)),
+            (
+                "dictate into the scratch buffer!",
+                Some(DictationTarget::Scratch),
+            ),
+            ("Dictate to file notes.md", file("notes.md")),
+            ("dictate into the file docs/Notes.md", file("docs/Notes.md")),
+            ("Dictate to file ./docs/./notes.md", file("docs/notes.md")),
+            ("Dictate to file ../notes.md", None),
+            ("Dictate to file docs/../../notes.md", None),
+            ("Dictate to file /etc/passwd", None),
+            ("Dictate to file .", None),
+            ("Dictate to the moon", None),
+            ("dictate", None),
+            ("Open the file notes.md", None),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(
+                dictation_command(text, project_dir),
+                expected,
+                "text: {text}"
+            );
+        }
+    }
+
+    #[test]
+    fn formats_dictated_text() {
+        let cases = [
+            (vec!["hello world"], "Hello world", DictationStep::Continue),
+            (
+                vec!["hello comma world full stop how are you question mark"],
+                "Hello, world. How are you?",
+                DictationStep::Continue,
+            ),
+            // The transcription model already added some punctuation
+            (
+                vec!["Hello, comma world. Period."],
+                "Hello, world.",
+                DictationStep::Continue,
+            ),
+            (
+                vec!["first line new line second", "new paragraph third"],
+                "First line\nSecond\n\nThird",
+                DictationStep::Continue,
+            ),
+            (
+                vec!["fix the parser", "it drops tokens"],
+                "Fix the parser it drops tokens",
+                DictationStep::Continue,
+            ),
+            (
+                vec!["done period stop dictation and more"],
+                "Done.\n",
+                DictationStep::Finished,
+            ),
+            (vec!["Stop dictation."], "", DictationStep::Finished),
+        ];
+
+        for (transcripts, expected_text, expected_step) in cases {
+            let mut dictation = Dictation::new(DictationTarget::Scratch);
+            let mut scratch = ScratchBuffer::new();
+            let mut step = DictationStep::Continue;
+            for transcript in &transcripts {
+                step = dictation.dictate(transcript, &mut scratch).unwrap();
+            }
+            assert_eq!(
+                scratch.text(),
+                expected_text,
+                "transcripts: {transcripts:?}"
+            );
+            assert_eq!(step, expected_step, "transcripts: {transcripts:?}");
+        }
+    }
+}
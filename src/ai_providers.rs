@@ -1 +1,25 @@
+pub mod anthropic;
+pub mod chat_completions;
+pub mod http;
 pub mod openai;
+pub mod registry;
+mod sse;
+
+/// Splits a base64 encoded image into its media type and data. Accepts both
+/// data URLs and plain base64 data, whose media type is guessed from the
+/// first bytes of the image.
+fn split_base64_image(image: &str) -> (&str, &str) {
+    if let Some(parts) = image
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(";base64,"))
+    {
+        return parts;
+    }
+    let media_type = match image.get(..4) {
+        Some("/9j/") => "image/jpeg",
+        Some("R0lG") => "image/gif",
+        Some("UklG") => "image/webp",
+        _ => "image/png",
+    };
+    (media_type, image)
+}
@@ -0,0 +1,256 @@
+use anyhow::Context;
+
+use crate::session::{
+    AIModel, Author, IncompleteReason, ModelInput, ModelOutput, Outcome, StreamingAIModel,
+    TextImage, TextImageMessage, TextMessage, TokenUsage,
+};
+
+use super::http::HttpClient;
+use super::split_base64_image;
+
+use messages_api::request::{Body, ContentBlock, ImageSource, Message, Role};
+use messages_api::response::{Body as ResponseBody, OutputContentBlock};
+
+pub const ANTHROPIC_API_URL: &str = "https://api.anthropic.com";
+
+const API_VERSION: &str = "2023-06-01";
+
+const PROVIDER: &str = "Anthropic API";
+
+/// The Messages API requires a limit on the output
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+/// A model served by the Anthropic Messages API
+pub struct AnthropicModel {
+    api_key: String,
+    /// e.g. "claude-sonnet-4-0"
+    model: String,
+    base_url: String,
+    max_tokens: u32,
+    http: HttpClient,
+}
+
+impl AnthropicModel {
+    #[must_use]
+    pub fn new(api_key: String, model: String) -> Self {
+        Self {
+            api_key,
+            model,
+            base_url: ANTHROPIC_API_URL.to_owned(),
+            max_tokens: DEFAULT_MAX_TOKENS,
+            http: HttpClient::default(),
+        }
+    }
+
+    /// Sends requests to `base_url` instead of the Anthropic API, e.g. to a
+    /// proxy or a local stub
+    #[must_use]
+    pub fn with_base_url(mut self, base_url: String) -> Self {
+        self.base_url = base_url;
+        self
+    }
+
+    #[must_use]
+    pub fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    /// Shares the connections of `http` with other models
+    #[must_use]
+    pub fn with_http_client(mut self, http: HttpClient) -> Self {
+        self.http = http;
+        self
+    }
+
+    #[must_use]
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+}
+
+impl AIModel<TextImageMessage, TextMessage> for AnthropicModel {
+    async fn send(
+        &self,
+        input: ModelInput<TextImageMessage>,
+    ) -> anyhow::Result<ModelOutput<TextMessage>> {
+        if !input.tools.is_empty() {
+            anyhow::bail!("Tool calling is not supported by the Anthropic provider yet");
+        }
+        // The Messages API has no option for the format of the answer
+        let instructions = match input.json_format {
+            Some(format) => format!(
+                "{}\n\nAnswer with JSON only, matching this JSON schema:\n{}",
+                input.instructions, format.schema
+            ),
+            None => input.instructions,
+        };
+        let body = Body {
+            model: self.model.clone(),
+            max_tokens: self.max_tokens,
+            system: Some(instructions).filter(|s| !s.is_empty()),
+            messages: input.log.into_iter().map(Message::from).collect(),
+        };
+        let url = format!("{}/v1/messages", self.base_url.trim_end_matches('/'));
+        let resp = self
+            .http
+            .send(PROVIDER, |client| {
+                client
+                    .post(&url)
+                    .header("x-api-key", &self.api_key)
+                    .header("anthropic-version", API_VERSION)
+                    .json(&body)
+            })
+            .await?;
+
+        let text = resp
+            .text()
+            .await
+            .context("Failed to gather response body as text")?;
+        let resp: ResponseBody = serde_json::from_str(&text)
+            .context(format!("Failed to parse Anthropic API response: {text}"))?;
+
+        let parts: Vec<String> = resp
+            .content
+            .into_iter()
+            .filter_map(|block| match block {
+                OutputContentBlock::Text { text } => Some(text),
+                OutputContentBlock::Other => None,
+            })
+            .collect();
+        let outcome = match resp.stop_reason.as_deref() {
+            None | Some("end_turn" | "stop_sequence" | "tool_use") => Outcome::Completed,
+            Some("max_tokens") => Outcome::Incomplete(IncompleteReason::MaxTokens),
+            Some("refusal") => Outcome::Refused {
+                reason: parts.join("\n\n"),
+            },
+            Some(reason) => Outcome::Incomplete(IncompleteReason::Other(reason.to_owned())),
+        };
+        let items = if parts.is_empty() {
+            Vec::new()
+        } else {
+            vec![TextMessage {
+                author: Author::Assistant,
+                parts,
+            }]
+        };
+
+        Ok(ModelOutput {
+            items,
+            tool_calls: Vec::new(),
+            outcome,
+            usage: Some(TokenUsage {
+                input_tokens: resp.usage.input_tokens
+                    + resp.usage.cache_creation_input_tokens.unwrap_or_default()
+                    + resp.usage.cache_read_input_tokens.unwrap_or_default(),
+                output_tokens: resp.usage.output_tokens,
+                cached_input_tokens: resp.usage.cache_read_input_tokens.unwrap_or_default(),
+            }),
+        })
+    }
+}
+
+impl StreamingAIModel<TextImageMessage, TextMessage> for AnthropicModel {}
+
+impl From<TextImageMessage> for Message {
+    fn from(message: TextImageMessage) -> Self {
+        let role = match message.author {
+            Author::User => Role::User,
+            Author::Assistant => Role::Assistant,
+        };
+        let content = message
+            .parts
+            .into_iter()
+            .map(|part| match part {
+                TextImage::Text(text) => ContentBlock::Text { text },
+                TextImage::Base64Image(image) => {
+                    let (media_type, data) = split_base64_image(&image);
+                    ContentBlock::Image {
+                        source: ImageSource::Base64 {
+                            media_type: media_type.to_owned(),
+                            data: data.to_owned(),
+                        },
+                    }
+                }
+                TextImage::ImageUrl(url) => ContentBlock::Image {
+                    source: ImageSource::Url { url },
+                },
+            })
+            .collect();
+        Message { role, content }
+    }
+}
+
+mod messages_api {
+    pub mod request {
+        use serde::Serialize;
+
+        #[derive(Debug, Serialize)]
+        pub struct Body {
+            pub model: String,
+            pub max_tokens: u32,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            pub system: Option<String>,
+            pub messages: Vec<Message>,
+        }
+
+        #[derive(Debug, Serialize)]
+        pub struct Message {
+            pub role: Role,
+            pub content: Vec<ContentBlock>,
+        }
+
+        #[derive(Debug, Serialize)]
+        #[serde(rename_all = "snake_case")]
+        pub enum Role {
+            User,
+            Assistant,
+        }
+
+        #[derive(Debug, Serialize)]
+        #[serde(tag = "type", rename_all = "snake_case")]
+        pub enum ContentBlock {
+            Text { text: String },
+            Image { source: ImageSource },
+        }
+
+        #[derive(Debug, Serialize)]
+        #[serde(tag = "type", rename_all = "snake_case")]
+        pub enum ImageSource {
+            Base64 { media_type: String, data: String },
+            Url { url: String },
+        }
+    }
+
+    pub mod response {
+        use serde::Deserialize;
+
+        #[derive(Debug, Deserialize)]
+        pub struct Body {
+            pub content: Vec<OutputContentBlock>,
+            pub stop_reason: Option<String>,
+            pub usage: Usage,
+        }
+
+        #[derive(Debug, Deserialize)]
+        #[serde(tag = "type", rename_all = "snake_case")]
+        pub enum OutputContentBlock {
+            Text {
+                text: String,
+            },
+            /// Tool use and thinking blocks are not used yet
+            #[serde(other)]
+            Other,
+        }
+
+        #[allow(clippy::struct_field_names)]
+        #[derive(Debug, Deserialize)]
+        pub struct Usage {
+            /// Excludes the tokens written to or read from the cache
+            pub input_tokens: u64,
+            pub output_tokens: u64,
+            pub cache_creation_input_tokens: Option<u64>,
+            pub cache_read_input_tokens: Option<u64>,
+        }
+    }
+}
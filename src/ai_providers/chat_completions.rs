@@ -0,0 +1,315 @@
+//! Models behind the `/chat/completions` endpoint, which most self-hosted
+//! model servers like llama.cpp, vLLM and Ollama offer in addition to OpenAI.
+
+use anyhow::Context;
+
+use crate::session::{
+    AIModel, Author, ImageDetail, IncompleteReason, ModelInput, ModelOutput, Outcome,
+    StreamingAIModel, TextImage, TextImageMessage, TextMessage, TokenUsage,
+};
+
+use super::http::HttpClient;
+use super::split_base64_image;
+
+use chat_api::request::{
+    Body, ContentPart, ImageUrl, JsonSchema, Message, MessageContent, ResponseFormat,
+};
+use chat_api::response::Body as ResponseBody;
+
+pub struct ChatCompletionsModel {
+    /// Including the version, e.g. "http://localhost:8080/v1"
+    base_url: String,
+    model: String,
+    /// Local servers usually don't need one
+    api_key: Option<String>,
+    image_detail: ImageDetail,
+    http: HttpClient,
+}
+
+const PROVIDER: &str = "model server";
+
+impl ChatCompletionsModel {
+    #[must_use]
+    pub fn new(base_url: String, model: String) -> Self {
+        Self {
+            base_url,
+            model,
+            api_key: None,
+            image_detail: ImageDetail::default(),
+            http: HttpClient::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_api_key(mut self, api_key: Option<String>) -> Self {
+        self.api_key = api_key;
+        self
+    }
+
+    /// Only understood by servers that handle the detail like OpenAI does
+    #[must_use]
+    pub fn with_image_detail(mut self, image_detail: ImageDetail) -> Self {
+        self.image_detail = image_detail;
+        self
+    }
+
+    /// Shares the connections of `http` with other models
+    #[must_use]
+    pub fn with_http_client(mut self, http: HttpClient) -> Self {
+        self.http = http;
+        self
+    }
+
+    #[must_use]
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+}
+
+impl AIModel<TextImageMessage, TextMessage> for ChatCompletionsModel {
+    async fn send(
+        &self,
+        input: ModelInput<TextImageMessage>,
+    ) -> anyhow::Result<ModelOutput<TextMessage>> {
+        if !input.tools.is_empty() {
+            anyhow::bail!("Tool calling is not supported by the Chat Completions provider yet");
+        }
+        let mut messages = Vec::with_capacity(input.log.len() + 1);
+        if !input.instructions.is_empty() {
+            messages.push(Message::System {
+                content: input.instructions,
+            });
+        }
+        messages.extend(
+            input
+                .log
+                .into_iter()
+                .map(|message| Message::from_message(message, self.image_detail)),
+        );
+        let body = Body {
+            model: self.model.clone(),
+            messages,
+            response_format: input.json_format.map(|format| ResponseFormat::JsonSchema {
+                json_schema: JsonSchema {
+                    name: format.name,
+                    schema: format.schema,
+                    strict: true,
+                },
+            }),
+        };
+
+        let url = format!("{}/chat/completions", self.base_url.trim_end_matches('/'));
+        let resp = self
+            .http
+            .send(PROVIDER, |client| {
+                let request = client.post(&url).json(&body);
+                match &self.api_key {
+                    Some(api_key) => request.bearer_auth(api_key),
+                    None => request,
+                }
+            })
+            .await?;
+
+        let text = resp
+            .text()
+            .await
+            .context("Failed to gather response body as text")?;
+        let resp: ResponseBody = serde_json::from_str(&text)
+            .context(format!("Failed to parse chat completion: {text}"))?;
+
+        let choice = resp
+            .choices
+            .into_iter()
+            .next()
+            .context("The chat completion contains no choices")?;
+        let items = match choice.message.content {
+            Some(content) if !content.is_empty() => vec![TextMessage {
+                author: Author::Assistant,
+                parts: vec![content],
+            }],
+            _ => Vec::new(),
+        };
+
+        Ok(ModelOutput {
+            items,
+            tool_calls: Vec::new(),
+            outcome: match choice.finish_reason.as_deref() {
+                // Not every server reports why it stopped
+                None | Some("stop" | "tool_calls" | "function_call") => Outcome::Completed,
+                Some("length") => Outcome::Incomplete(IncompleteReason::MaxTokens),
+                Some("content_filter") => Outcome::Incomplete(IncompleteReason::ContentFilter),
+                Some(reason) => Outcome::Incomplete(IncompleteReason::Other(reason.to_owned())),
+            },
+            usage: resp.usage.map(|usage| TokenUsage {
+                input_tokens: usage.prompt_tokens,
+                output_tokens: usage.completion_tokens,
+                cached_input_tokens: usage
+                    .prompt_tokens_details
+                    .and_then(|details| details.cached_tokens)
+                    .unwrap_or_default(),
+            }),
+        })
+    }
+}
+
+impl StreamingAIModel<TextImageMessage, TextMessage> for ChatCompletionsModel {}
+
+impl Message {
+    fn from_message(message: TextImageMessage, image_detail: ImageDetail) -> Self {
+        match message.author {
+            Author::User => {
+                let content = if message
+                    .parts
+                    .iter()
+                    .all(|part| matches!(part, TextImage::Text(_)))
+                {
+                    // Plain text is understood by servers without vision
+                    // support, too
+                    MessageContent::Text(text_parts(message.parts))
+                } else {
+                    MessageContent::Parts(
+                        message
+                            .parts
+                            .into_iter()
+                            .map(|part| match part {
+                                TextImage::Text(text) => ContentPart::Text { text },
+                                TextImage::Base64Image(image) => {
+                                    let (media_type, data) = split_base64_image(&image);
+                                    ContentPart::ImageUrl {
+                                        image_url: ImageUrl {
+                                            url: format!("data:{media_type};base64,{data}"),
+                                            detail: detail_name(image_detail),
+                                        },
+                                    }
+                                }
+                                TextImage::ImageUrl(url) => ContentPart::ImageUrl {
+                                    image_url: ImageUrl {
+                                        url,
+                                        detail: detail_name(image_detail),
+                                    },
+                                },
+                            })
+                            .collect(),
+                    )
+                };
+                Message::User { content }
+            }
+            Author::Assistant => Message::Assistant {
+                content: text_parts(message.parts),
+            },
+        }
+    }
+}
+
+/// Left out for [`ImageDetail::Auto`], as the default of servers that
+/// understand it
+fn detail_name(image_detail: ImageDetail) -> Option<&'static str> {
+    match image_detail {
+        ImageDetail::Low => Some("low"),
+        ImageDetail::High => Some("high"),
+        ImageDetail::Auto => None,
+    }
+}
+
+/// Joins the text parts of a message, leaving out images
+fn text_parts(parts: Vec<TextImage>) -> String {
+    parts
+        .into_iter()
+        .filter_map(|part| match part {
+            TextImage::Text(text) => Some(text),
+            TextImage::Base64Image(_) | TextImage::ImageUrl(_) => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+mod chat_api {
+    pub mod request {
+        use serde::Serialize;
+
+        #[derive(Debug, Serialize)]
+        pub struct Body {
+            pub model: String,
+            pub messages: Vec<Message>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            pub response_format: Option<ResponseFormat>,
+        }
+
+        #[derive(Debug, Serialize)]
+        #[serde(tag = "type", rename_all = "snake_case")]
+        pub enum ResponseFormat {
+            JsonSchema { json_schema: JsonSchema },
+        }
+
+        #[derive(Debug, Serialize)]
+        pub struct JsonSchema {
+            pub name: String,
+            pub schema: serde_json::Value,
+            pub strict: bool,
+        }
+
+        #[derive(Debug, Serialize)]
+        #[serde(tag = "role", rename_all = "snake_case")]
+        pub enum Message {
+            System { content: String },
+            User { content: MessageContent },
+            Assistant { content: String },
+        }
+
+        #[derive(Debug, Serialize)]
+        #[serde(untagged)]
+        pub enum MessageContent {
+            Text(String),
+            Parts(Vec<ContentPart>),
+        }
+
+        #[derive(Debug, Serialize)]
+        #[serde(tag = "type", rename_all = "snake_case")]
+        pub enum ContentPart {
+            Text { text: String },
+            ImageUrl { image_url: ImageUrl },
+        }
+
+        #[derive(Debug, Serialize)]
+        pub struct ImageUrl {
+            /// A URL or a data URL
+            pub url: String,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            pub detail: Option<&'static str>,
+        }
+    }
+
+    pub mod response {
+        use serde::Deserialize;
+
+        #[derive(Debug, Deserialize)]
+        pub struct Body {
+            pub choices: Vec<Choice>,
+            /// Not reported by every server
+            pub usage: Option<Usage>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        pub struct Choice {
+            pub message: ResponseMessage,
+            pub finish_reason: Option<String>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        pub struct ResponseMessage {
+            pub content: Option<String>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        pub struct Usage {
+            pub prompt_tokens: u64,
+            pub completion_tokens: u64,
+            pub prompt_tokens_details: Option<PromptTokensDetails>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        pub struct PromptTokensDetails {
+            pub cached_tokens: Option<u64>,
+        }
+    }
+}
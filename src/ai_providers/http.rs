@@ -0,0 +1,526 @@
+//! Calling the HTTP APIs of model providers: shared connections, timeouts,
+//! retries of transient failures, and errors the user can be told about.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context as TaskContext, Poll};
+use std::time::Duration;
+
+use anyhow::Context;
+use reqwest::header::HeaderMap;
+use reqwest::{Certificate, Client as ReqwestClient, Proxy, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// Connections, timeouts and retries of model calls
+#[derive(Clone, Debug)]
+pub struct HttpOptions {
+    pub connect_timeout: Duration,
+    /// For the whole response, or for each chunk of a streamed response
+    pub request_timeout: Duration,
+    pub retry: RetryPolicy,
+    /// How long unused connections are kept open for the next call
+    pub pool_idle_timeout: Duration,
+    /// Speaks HTTP/2 without negotiating it first, for local servers
+    /// without TLS. Over TLS, HTTP/2 is used whenever the server offers it.
+    pub http2_prior_knowledge: bool,
+    /// Overrides the proxy of the system, e.g. `http://proxy.local:3128`
+    pub proxy: Option<String>,
+    /// PEM file with a certificate trusted in addition to the built-in
+    /// ones, e.g. of a company proxy
+    pub ca_certificate: Option<PathBuf>,
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            request_timeout: Duration::from_secs(60),
+            retry: RetryPolicy::default(),
+            pool_idle_timeout: Duration::from_secs(90),
+            http2_prior_knowledge: false,
+            proxy: None,
+            ca_certificate: None,
+        }
+    }
+}
+
+/// How rate limited and failed requests are retried, with exponentially
+/// growing delays
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    /// Before the first retry, doubled for each following one
+    pub initial_backoff: Duration,
+    /// Delays aren't longer than this. A provider asking to wait longer is
+    /// not retried at all.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(20),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before retry number `attempt` (starting with 0), or
+    /// `None` to give up. The delay is randomized between half and all of
+    /// the backoff, so clients failing together don't retry together.
+    fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if attempt >= self.max_retries {
+            return None;
+        }
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2_u32.saturating_pow(attempt))
+            .min(self.max_backoff);
+        let jittered = backoff.mul_f64(0.5 + random_fraction() / 2.0);
+        match retry_after {
+            Some(retry_after) if retry_after > self.max_backoff => None,
+            Some(retry_after) => Some(retry_after.max(jittered)),
+            None => Some(jittered),
+        }
+    }
+}
+
+/// Between 0 and 1
+#[allow(clippy::cast_precision_loss)]
+fn random_fraction() -> f64 {
+    // Each `RandomState` is seeded randomly
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1_u64 << 53) as f64
+}
+
+/// An HTTP client shared by the models, so that a call can reuse the
+/// connection of an earlier one instead of setting up a new one
+#[derive(Clone)]
+pub struct HttpClient {
+    client: ReqwestClient,
+    request_timeout: Duration,
+    retry: RetryPolicy,
+    stats: Arc<ConnectionStats>,
+}
+
+impl HttpClient {
+    pub fn new(options: &HttpOptions) -> anyhow::Result<Self> {
+        let stats = Arc::new(ConnectionStats::default());
+        let mut builder = ReqwestClient::builder()
+            .connect_timeout(options.connect_timeout)
+            .pool_idle_timeout(options.pool_idle_timeout)
+            .tcp_keepalive(TCP_KEEPALIVE)
+            .http2_keep_alive_interval(TCP_KEEPALIVE)
+            .http2_keep_alive_while_idle(true)
+            .http2_adaptive_window(true)
+            .connector_layer(CountConnections(Arc::clone(&stats)));
+        if options.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+        if let Some(proxy) = &options.proxy {
+            builder = builder
+                .proxy(Proxy::all(proxy).context(format!("Could not use the proxy '{proxy}'"))?);
+        }
+        if let Some(path) = &options.ca_certificate {
+            let pem = std::fs::read(path).context(format!(
+                "Could not read the CA certificate {}",
+                path.display()
+            ))?;
+            let certificate = Certificate::from_pem(&pem).context(format!(
+                "Could not parse the CA certificate {}",
+                path.display()
+            ))?;
+            builder = builder.add_root_certificate(certificate);
+        }
+        Ok(Self {
+            client: builder
+                .build()
+                .context("Could not set up the HTTP client")?,
+            request_timeout: options.request_timeout,
+            retry: options.retry,
+            stats,
+        })
+    }
+
+    /// How long to wait for a response, or for the next chunk of a streamed
+    /// one
+    #[must_use]
+    pub fn request_timeout(&self) -> Duration {
+        self.request_timeout
+    }
+
+    /// Sends the request built by `request`, retrying it on rate limits and
+    /// server errors. Responses with other statuses than success are
+    /// returned as [`ApiError`].
+    pub async fn send(
+        &self,
+        provider: &'static str,
+        request: impl Fn(&ReqwestClient) -> RequestBuilder,
+    ) -> Result<Response, ApiError> {
+        self.send_with_timeout(provider, Some(self.request_timeout), request)
+            .await
+    }
+
+    /// Like [`Self::send`], but without a timeout for the whole response,
+    /// which takes as long as the model takes to generate it
+    pub async fn send_streaming(
+        &self,
+        provider: &'static str,
+        request: impl Fn(&ReqwestClient) -> RequestBuilder,
+    ) -> Result<Response, ApiError> {
+        self.send_with_timeout(provider, None, request).await
+    }
+
+    async fn send_with_timeout(
+        &self,
+        provider: &'static str,
+        timeout: Option<Duration>,
+        request: impl Fn(&ReqwestClient) -> RequestBuilder,
+    ) -> Result<Response, ApiError> {
+        let mut attempt = 0;
+        loop {
+            let mut builder = request(&self.client);
+            if let Some(timeout) = timeout {
+                builder = builder.timeout(timeout);
+            }
+            self.stats.requests.fetch_add(1, Ordering::Relaxed);
+            let response = builder
+                .send()
+                .await
+                .map_err(|err| ApiError::from_reqwest(provider, &err))?;
+            if response.status().is_success() {
+                return Ok(response);
+            }
+
+            let retry_after = retry_after(response.headers());
+            let error = ApiError::from_response(provider, response).await;
+            match self.retry.delay(attempt, retry_after) {
+                Some(delay) if error.is_transient() => {
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                _ => return Err(error),
+            }
+        }
+    }
+
+    /// Opens a connection to the host of `url`, for the next call to skip
+    /// the setup. Whatever the server answers doesn't matter.
+    pub async fn prewarm(&self, url: &str) -> anyhow::Result<()> {
+        self.stats.requests.fetch_add(1, Ordering::Relaxed);
+        self.client
+            .head(url)
+            .timeout(self.request_timeout)
+            .send()
+            .await
+            .context(format!("Failed to connect to {url}"))?;
+        Ok(())
+    }
+
+    /// How many requests were sent over how many connections so far
+    #[must_use]
+    pub fn stats(&self) -> ConnectionReuse {
+        ConnectionReuse {
+            requests: self.stats.requests.load(Ordering::Relaxed),
+            connections: self.stats.connections.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        Self::new(&HttpOptions::default()).expect("The default HTTP client can be set up")
+    }
+}
+
+/// Keeps idle connections from being dropped by routers and servers
+const TCP_KEEPALIVE: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+struct ConnectionStats {
+    requests: AtomicU64,
+    /// Opened, or attempted to be opened
+    connections: AtomicU64,
+}
+
+/// Counts the connections the client opens
+#[derive(Clone)]
+struct CountConnections(Arc<ConnectionStats>);
+
+impl<S> Layer<S> for CountConnections {
+    type Service = CountingConnector<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CountingConnector {
+            inner,
+            stats: Arc::clone(&self.0),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct CountingConnector<S> {
+    inner: S,
+    stats: Arc<ConnectionStats>,
+}
+
+impl<S: Service<R>, R> Service<R> for CountingConnector<S> {
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        self.stats.connections.fetch_add(1, Ordering::Relaxed);
+        self.inner.call(request)
+    }
+}
+
+/// Requests sent by an [`HttpClient`], and the connections they needed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectionReuse {
+    /// Including retries and pre-warming
+    pub requests: u64,
+    pub connections: u64,
+}
+
+impl ConnectionReuse {
+    /// Requests that went over a connection opened for an earlier one
+    #[must_use]
+    pub fn reused(&self) -> u64 {
+        self.requests.saturating_sub(self.connections)
+    }
+}
+
+impl std::fmt::Display for ConnectionReuse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} requests over {} connections, {} reused",
+            self.requests,
+            self.connections,
+            self.reused()
+        )
+    }
+}
+
+/// The delay a provider asks for. OpenAI sends milliseconds in a header of
+/// its own, the standard header is in seconds.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name| headers.get(name)?.to_str().ok()?.trim().parse::<f64>().ok();
+    header("retry-after-ms")
+        .map(|ms| ms / 1000.0)
+        .or_else(|| header("retry-after"))
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApiErrorKind {
+    /// The API key is missing, wrong or lacks permissions
+    Authentication,
+    /// Too many requests, even after retrying
+    RateLimited,
+    /// The account has run out of credit
+    QuotaExceeded,
+    /// E.g. the model doesn't exist or the input is too long
+    InvalidRequest,
+    /// The provider failed, even after retrying
+    Server,
+    Timeout,
+    /// The provider couldn't be reached
+    Connection,
+}
+
+/// A failed call of a provider's API
+#[derive(Debug)]
+pub struct ApiError {
+    pub kind: ApiErrorKind,
+    /// e.g. "OpenAI API"
+    pub provider: &'static str,
+    /// `None` if there was no response
+    pub status: Option<StatusCode>,
+    /// As reported by the provider, e.g. "invalid_request_error"
+    pub type_: Option<String>,
+    /// As reported by the provider, e.g. "insufficient_quota"
+    pub code: Option<String>,
+    pub message: String,
+}
+
+impl ApiError {
+    async fn from_response(provider: &'static str, response: Response) -> Self {
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        let (type_, code, message) = match serde_json::from_str::<ErrorBody>(&text) {
+            Ok(body) => (
+                body.error.type_,
+                body.error.code.map(|code| match code {
+                    serde_json::Value::String(code) => code,
+                    code => code.to_string(),
+                }),
+                body.error.message,
+            ),
+            Err(_) => (None, None, text),
+        };
+        let is_quota = [&type_, &code]
+            .into_iter()
+            .any(|s| s.as_deref() == Some("insufficient_quota"));
+        let kind = match status.as_u16() {
+            401 | 403 => ApiErrorKind::Authentication,
+            429 if is_quota => ApiErrorKind::QuotaExceeded,
+            429 => ApiErrorKind::RateLimited,
+            408 => ApiErrorKind::Timeout,
+            400..=499 => ApiErrorKind::InvalidRequest,
+            _ => ApiErrorKind::Server,
+        };
+        Self {
+            kind,
+            provider,
+            status: Some(status),
+            type_,
+            code,
+            message,
+        }
+    }
+
+    fn from_reqwest(provider: &'static str, err: &reqwest::Error) -> Self {
+        let kind = if err.is_timeout() {
+            ApiErrorKind::Timeout
+        } else {
+            ApiErrorKind::Connection
+        };
+        Self {
+            kind,
+            provider,
+            status: err.status(),
+            type_: None,
+            code: None,
+            message: format!("{err:#}"),
+        }
+    }
+
+    /// For a streamed response that stopped coming
+    pub(super) fn idle_timeout(provider: &'static str, timeout: Duration) -> Self {
+        Self {
+            kind: ApiErrorKind::Timeout,
+            provider,
+            status: None,
+            type_: None,
+            code: None,
+            message: format!("Received nothing for {timeout:?}"),
+        }
+    }
+
+    /// Whether the same request may succeed later
+    #[must_use]
+    pub fn is_transient(&self) -> bool {
+        matches!(self.kind, ApiErrorKind::RateLimited | ApiErrorKind::Server)
+    }
+
+    /// What to tell the user
+    #[must_use]
+    pub fn user_message(&self) -> String {
+        let provider = self.provider;
+        match self.kind {
+            ApiErrorKind::Authentication => {
+                format!("The {provider} did not accept the API key, please check it.")
+            }
+            ApiErrorKind::RateLimited => {
+                format!("The {provider} is rate limiting requests, please try again in a moment.")
+            }
+            ApiErrorKind::QuotaExceeded => {
+                format!(
+                    "The quota of the {provider} is used up, please check your plan and billing details."
+                )
+            }
+            ApiErrorKind::InvalidRequest => {
+                format!("The {provider} rejected the request: {}", self.message)
+            }
+            ApiErrorKind::Server => {
+                format!("The {provider} is having problems, please try again later.")
+            }
+            ApiErrorKind::Timeout => format!("The {provider} took too long to answer."),
+            ApiErrorKind::Connection => {
+                format!("Could not reach the {provider}, please check the network connection.")
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.status {
+            Some(status) => write!(f, "{} responded with {status}", self.provider)?,
+            None => write!(f, "Failed to call the {}", self.provider)?,
+        }
+        if let Some(type_) = self.type_.as_ref().or(self.code.as_ref()) {
+            write!(f, " ({type_})")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// The error format of OpenAI, which Anthropic and most other providers
+/// follow as well
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorDetail {
+    #[serde(rename = "type")]
+    type_: Option<String>,
+    /// A string at OpenAI, a number at some local servers
+    code: Option<serde_json::Value>,
+    message: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grows_retry_delays_exponentially() {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(3),
+        };
+        let cases = [
+            (0, None, Some((500, 1000))),
+            (1, None, Some((1000, 2000))),
+            (2, None, Some((1500, 3000))),
+            (3, None, None),
+            (0, Some(Duration::from_secs(2)), Some((2000, 2000))),
+            (0, Some(Duration::from_secs(10)), None),
+        ];
+
+        for (attempt, retry_after, expected) in cases {
+            let delay = policy
+                .delay(attempt, retry_after)
+                .map(|delay| delay.as_millis());
+            match expected {
+                Some((min, max)) => {
+                    let delay = delay.unwrap();
+                    assert!(
+                        (min..=max).contains(&delay),
+                        "attempt {attempt}, retry after {retry_after:?}: {delay}"
+                    );
+                }
+                None => assert_eq!(delay, None, "attempt {attempt}"),
+            }
+        }
+    }
+}
@@ -1,76 +1,315 @@
+use std::collections::VecDeque;
+
 use anyhow::Context;
-use reqwest::Client as ReqwestClient;
-use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use futures_util::{StreamExt, stream};
+use serde::{Serialize, de::DeserializeOwned};
 
 use crate::session::{
-    AIModel, Author, InputModes, ModelInput, ModelOutput, OutputModes, TextImageMessage,
-    TextMessage,
+    AIModel, Author, ImageDetail, IncompleteReason, InputModes, ModelInput, ModelOutput,
+    ModelStream, Outcome, OutputModes, StreamEvent, StreamingAIModel, TextImageMessage,
+    TextMessage, TokenUsage, ToolCall,
 };
 
+use super::http::{ApiError, HttpClient};
+use super::sse::SseParser;
+
 use responses_api::model_response::request::{
     AssistantMessageContentItem, AssistantMessageTextItem, Body as ResponseApiRequestBody,
-    InputData, UserMessageContentItem, UserMessageTextImageItem,
+    InputData, InputItem, UserMessageContentItem, UserMessageTextImageItem,
 };
 use responses_api::model_response::response::{
-    Body as ResponsesApiResponseBody, MessageOutputContent, Output,
+    Body as ResponsesApiResponseBody, MessageOutputContent, Output, Status,
+    StreamEvent as ResponsesApiStreamEvent,
 };
 
-pub struct Gpt4_1Nano {
+pub const OPENAI_API_URL: &str = "https://api.openai.com/v1";
+
+const PROVIDER: &str = "OpenAI API";
+
+/// A model served by the OpenAI Responses API
+pub struct ResponsesModel {
     api_key: String,
+    /// e.g. "gpt-4.1-nano-2025-04-14"
+    model: String,
+    base_url: String,
+    image_detail: ImageDetail,
+    http: HttpClient,
 }
 
-impl Gpt4_1Nano {
+impl ResponsesModel {
     #[must_use]
-    pub fn new(api_key: String) -> Self {
-        Self { api_key }
+    pub fn new(api_key: String, model: String) -> Self {
+        Self {
+            api_key,
+            model,
+            base_url: OPENAI_API_URL.to_owned(),
+            image_detail: ImageDetail::default(),
+            http: HttpClient::default(),
+        }
     }
-}
 
-impl AIModel<TextImageMessage, TextMessage> for Gpt4_1Nano {
-    async fn send(
+    /// Sends requests to `base_url` instead of the OpenAI API, e.g. to a
+    /// proxy or a local stub
+    #[must_use]
+    pub fn with_base_url(mut self, base_url: String) -> Self {
+        self.base_url = base_url;
+        self
+    }
+
+    #[must_use]
+    pub fn with_image_detail(mut self, image_detail: ImageDetail) -> Self {
+        self.image_detail = image_detail;
+        self
+    }
+
+    /// Shares the connections of `http` with other models
+    #[must_use]
+    pub fn with_http_client(mut self, http: HttpClient) -> Self {
+        self.http = http;
+        self
+    }
+
+    #[must_use]
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    async fn post(
         &self,
         input: ModelInput<TextImageMessage>,
-    ) -> anyhow::Result<ModelOutput<TextMessage>> {
-        let api_input: InputData<Modality_TextImage_Text> =
-            InputData::Multiple(input.log.iter().map(Clone::clone).map(Into::into).collect());
+        stream: bool,
+    ) -> anyhow::Result<reqwest::Response> {
+        let api_input: InputData<Modality_TextImage_Text> = InputData::Multiple(
+            input
+                .log
+                .into_iter()
+                .map(|message| InputItem::from_message(message, self.image_detail.into()))
+                .chain(
+                    input
+                        .tool_results
+                        .into_iter()
+                        .flat_map(InputItem::from_tool_result),
+                )
+                .collect(),
+        );
         let api_input = ResponseApiRequestBody {
             include: None,
             input: Some(api_input),
             instructions: Some(input.instructions),
-            model: OpenAIModelName::Gpt4_1Nano,
+            model: self.model.clone(),
+            stream: stream.then_some(true),
+            text: input.json_format.map(Into::into),
+            tool_choice: None,
+            tools: input.tools.into_iter().map(Into::into).collect(),
         };
-        let client = ReqwestClient::new();
-        let resp: ResponsesApiResponseBody<Modality_TextImage_Text> = client
-            .post("https://api.openai.com/v1/responses")
-            .bearer_auth(self.api_key.clone())
-            .json(&api_input)
-            .send()
-            .await
-            .context("Failed to reach OpenAI API")?
+        let url = format!("{}/responses", self.base_url.trim_end_matches('/'));
+        let request = |client: &reqwest::Client| {
+            client
+                .post(&url)
+                .bearer_auth(&self.api_key)
+                .json(&api_input)
+        };
+        let resp = if stream {
+            self.http.send_streaming(PROVIDER, request).await?
+        } else {
+            self.http.send(PROVIDER, request).await?
+        };
+        Ok(resp)
+    }
+}
+
+impl AIModel<TextImageMessage, TextMessage> for ResponsesModel {
+    async fn send(
+        &self,
+        input: ModelInput<TextImageMessage>,
+    ) -> anyhow::Result<ModelOutput<TextMessage>> {
+        let resp: ResponsesApiResponseBody<Modality_TextImage_Text> = self
+            .post(input, false)
+            .await?
             .json()
             .await
             .context("Failed to gather response body as text")?;
 
-        let items = resp
-            .output
-            .iter()
-            .filter_map(|it| match it {
-                Output::Message(msg) => Some(TextMessage {
-                    author: Author::Assistant,
-                    parts: msg
-                        .content
-                        .iter()
-                        .map(|c| match c {
-                            MessageOutputContent::OutputText(txt) => txt.text.clone(),
-                            MessageOutputContent::Refusal(_no) => todo!(),
-                        })
-                        .collect(),
-                }),
-                _ => None,
-            })
-            .collect();
-
-        Ok(ModelOutput { items })
+        Ok(model_output(resp))
+    }
+}
+
+impl StreamingAIModel<TextImageMessage, TextMessage> for ResponsesModel {
+    async fn send_streaming(
+        &self,
+        input: ModelInput<TextImageMessage>,
+    ) -> anyhow::Result<ModelStream<TextMessage>> {
+        let resp = self.post(input, true).await?;
+
+        let state = ResponseStreamState {
+            bytes: Box::pin(resp.bytes_stream()),
+            parser: SseParser::new(),
+            pending: VecDeque::new(),
+            has_ended: false,
+            idle_timeout: self.http.request_timeout(),
+        };
+        let events = stream::unfold(state, |mut state| async move {
+            loop {
+                if let Some(event) = state.pending.pop_front() {
+                    return Some((event, state));
+                }
+                if state.has_ended {
+                    return None;
+                }
+                let Ok(next) = tokio::time::timeout(state.idle_timeout, state.bytes.next()).await
+                else {
+                    state.has_ended = true;
+                    state.pending.push_back(Err(ApiError::idle_timeout(
+                        PROVIDER,
+                        state.idle_timeout,
+                    )
+                    .into()));
+                    continue;
+                };
+                match next {
+                    Some(Ok(chunk)) => {
+                        for sse in state.parser.push(&chunk) {
+                            let event = match serde_json::from_str(&sse.data) {
+                                Ok(event) => stream_event(event),
+                                Err(err) => Some(Err(anyhow::Error::new(err).context(format!(
+                                    "Failed to parse streamed event: {}",
+                                    sse.data
+                                )))),
+                            };
+                            if let Some(event) = event {
+                                state.has_ended |=
+                                    matches!(event, Ok(StreamEvent::Completed(_)) | Err(_));
+                                state.pending.push_back(event);
+                            }
+                        }
+                    }
+                    Some(Err(err)) => {
+                        state.has_ended = true;
+                        state.pending.push_back(Err(
+                            anyhow::Error::new(err).context("Failed to receive the response")
+                        ));
+                    }
+                    None => {
+                        state.has_ended = true;
+                        state.pending.push_back(Err(anyhow::anyhow!(
+                            "The response stream ended before the response was completed"
+                        )));
+                    }
+                }
+            }
+        });
+
+        Ok(ModelStream::new(events))
+    }
+}
+
+struct ResponseStreamState<B> {
+    bytes: std::pin::Pin<Box<B>>,
+    parser: SseParser,
+    pending: VecDeque<anyhow::Result<StreamEvent<TextMessage>>>,
+    /// Set after the last event
+    has_ended: bool,
+    /// How long to wait for the next chunk
+    idle_timeout: std::time::Duration,
+}
+
+/// Maps a streamed event, `None` for events that don't matter here
+fn stream_event(
+    event: ResponsesApiStreamEvent<Modality_TextImage_Text>,
+) -> Option<anyhow::Result<StreamEvent<TextMessage>>> {
+    match event {
+        ResponsesApiStreamEvent::OutputTextDelta { delta } => {
+            Some(Ok(StreamEvent::TextDelta(delta)))
+        }
+        ResponsesApiStreamEvent::Completed { response }
+        | ResponsesApiStreamEvent::Incomplete { response }
+        | ResponsesApiStreamEvent::Failed { response } => {
+            Some(Ok(StreamEvent::Completed(model_output(response))))
+        }
+        ResponsesApiStreamEvent::Error { message } => {
+            Some(Err(anyhow::anyhow!("OpenAI API sent an error: {message}")))
+        }
+        ResponsesApiStreamEvent::Other => None,
+    }
+}
+
+fn model_output(
+    resp: ResponsesApiResponseBody<Modality_TextImage_Text>,
+) -> ModelOutput<TextMessage> {
+    let mut refusals = Vec::new();
+    let items = resp
+        .output
+        .iter()
+        .filter_map(|it| match it {
+            Output::Message(msg) => Some(TextMessage {
+                author: Author::Assistant,
+                parts: msg
+                    .content
+                    .iter()
+                    .filter_map(|c| match c {
+                        MessageOutputContent::OutputText(txt) => Some(txt.text.clone()),
+                        MessageOutputContent::Refusal(no) => {
+                            refusals.push(no.refusal.clone());
+                            None
+                        }
+                    })
+                    .collect(),
+            }),
+            _ => None,
+        })
+        .filter(|message| !message.parts.is_empty())
+        .collect();
+    let tool_calls: Vec<ToolCall> = resp
+        .output
+        .iter()
+        .filter_map(|it| match it {
+            Output::FunctionToolCall(call) => Some(ToolCall {
+                call_id: call.call_id.clone(),
+                name: call.name.clone(),
+                arguments: call.arguments.clone(),
+            }),
+            _ => None,
+        })
+        .collect();
+
+    let outcome = match resp.status {
+        Status::Completed if refusals.is_empty() => Outcome::Completed,
+        Status::Completed => Outcome::Refused {
+            reason: refusals.join("\n\n"),
+        },
+        Status::Incomplete => Outcome::Incomplete(resp.incomplete_details.map_or_else(
+            || IncompleteReason::Other("unknown".to_owned()),
+            |details| match details.reason.as_str() {
+                "max_output_tokens" => IncompleteReason::MaxTokens,
+                "content_filter" => IncompleteReason::ContentFilter,
+                _ => IncompleteReason::Other(details.reason),
+            },
+        )),
+        Status::Failed => Outcome::Failed {
+            error: resp
+                .error
+                .map_or_else(|| "unknown error".to_owned(), |err| err.message),
+        },
+        Status::Cancelled => Outcome::Failed {
+            error: "The response was cancelled".to_owned(),
+        },
+        // Only happens for responses generated in the background
+        Status::InProgress | Status::Queued => Outcome::Failed {
+            error: "The response has not been generated yet".to_owned(),
+        },
+    };
+    let usage = resp.usage.map(|usage| TokenUsage {
+        input_tokens: u64::try_from(usage.input_tokens).unwrap_or_default(),
+        output_tokens: u64::try_from(usage.output_tokens).unwrap_or_default(),
+        cached_input_tokens: u64::try_from(usage.input_tokens_details.cached_tokens)
+            .unwrap_or_default(),
+    });
+
+    ModelOutput {
+        items,
+        tool_calls,
+        outcome,
+        usage,
     }
 }
 
@@ -96,13 +335,6 @@ impl Modality for Modality_TextImage_Text {
     type AssistantMsg = AssistantMessageTextItem;
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-enum OpenAIModelName {
-    // TODO this string should probably come from config
-    #[serde(rename = "gpt-4.1-nano-2025-04-14")]
-    Gpt4_1Nano,
-}
-
 mod responses_api {
     pub mod model_response {
         pub mod request {
@@ -110,11 +342,13 @@ mod responses_api {
 
             use serde::{Deserialize, Serialize};
 
+            use crate::ai_providers::split_base64_image;
             use crate::session::{
-                Author, InputModes, OutputModes, TextImage, TextImageMessage, TextMessage,
+                self, Author, InputModes, JsonFormat, OutputModes, TextImage, TextImageMessage,
+                TextMessage, ToolDefinition, ToolResult,
             };
 
-            use super::super::super::{Modality, OpenAIModelName};
+            use super::super::super::Modality;
 
             #[derive(Debug, Serialize, Deserialize)]
             #[serde(rename_all = "snake_case")]
@@ -135,7 +369,7 @@ mod responses_api {
                 //
                 // metadata: Option<HashMap<String, String>>,
                 //
-                pub model: OpenAIModelName,
+                pub model: String,
                 //
                 // #[serde(default)]
                 // parallel_tool_calls: Option<bool>, // Defaults to true if None
@@ -155,16 +389,19 @@ mod responses_api {
                 // #[serde(default)]
                 // store: Option<bool>, // Defaults to true
                 //
-                // #[serde(default)]
-                // stream: Option<bool>, // Defaults to false
+                #[serde(default, skip_serializing_if = "Option::is_none")]
+                pub stream: Option<bool>, // Defaults to false
                 //
                 // temperature: Option<f32>, // 0.0 to 2.0
                 //
-                // text: Option<HashMap<String, serde_json::Value>>,
+                #[serde(default, skip_serializing_if = "Option::is_none")]
+                pub text: Option<TextOptions>,
                 //
-                // tool_choice: Option<ToolChoice>, // String or object
+                #[serde(default, skip_serializing_if = "Option::is_none")]
+                pub tool_choice: Option<ToolChoice>, // String or object
                 //
-                // tools: Option<Vec<serde_json::Value>>,
+                #[serde(default, skip_serializing_if = "Vec::is_empty")]
+                pub tools: Vec<Tool>,
                 //
                 // top_logprobs: Option<u8>, // 0–20
                 //
@@ -190,9 +427,37 @@ mod responses_api {
                 Reference(InputItemReference),
                 #[serde(rename = "message")]
                 Message(Message<M>),
+                /// A call of a function made by the model earlier, which has
+                /// to precede its output
+                #[serde(rename = "function_call")]
+                FunctionCall {
+                    call_id: String,
+                    name: String,
+                    arguments: String,
+                },
+                #[serde(rename = "function_call_output")]
+                FunctionCallOutput { call_id: String, output: String },
+            }
+
+            impl<M: Modality> InputItem<M> {
+                /// The call and its output
+                pub fn from_tool_result(result: ToolResult) -> [Self; 2] {
+                    let call_id = result.call.call_id;
+                    [
+                        InputItem::FunctionCall {
+                            call_id: call_id.clone(),
+                            name: result.call.name,
+                            arguments: result.call.arguments,
+                        },
+                        InputItem::FunctionCallOutput {
+                            call_id,
+                            output: result.output,
+                        },
+                    ]
+                }
             }
 
-            impl<M> From<TextImageMessage> for InputItem<M>
+            impl<M> InputItem<M>
             where
                 M: Modality<
                         In = TextImageMessage,
@@ -200,18 +465,33 @@ mod responses_api {
                         AssistantMsg = AssistantMessageTextItem,
                     >,
             {
-                fn from(message: TextImageMessage) -> Self {
+                pub fn from_message(message: TextImageMessage, image_detail: ImageDetail) -> Self {
                     let api_message = match message.author {
                         Author::User => {
                             let content = message
                                 .parts
-                                .iter()
+                                .into_iter()
                                 .map(|text_image| match text_image {
                                     TextImage::Text(text) => {
-                                        UserMessageTextImageItem::InputText { text: text.clone() }
+                                        UserMessageTextImageItem::InputText { text }
+                                    }
+                                    TextImage::Base64Image(image) => {
+                                        let (media_type, data) = split_base64_image(&image);
+                                        UserMessageTextImageItem::InputImage {
+                                            detail: image_detail,
+                                            file_id: None,
+                                            image_url: Some(format!(
+                                                "data:{media_type};base64,{data}"
+                                            )),
+                                        }
+                                    }
+                                    TextImage::ImageUrl(url) => {
+                                        UserMessageTextImageItem::InputImage {
+                                            detail: image_detail,
+                                            file_id: None,
+                                            image_url: Some(url),
+                                        }
                                     }
-                                    TextImage::Base64Image(_) => todo!(),
-                                    TextImage::ImageUrl(_) => todo!(),
                                 })
                                 .collect();
                             Message::UserMessage {
@@ -223,15 +503,17 @@ mod responses_api {
                         Author::Assistant => {
                             let content = message
                                 .parts
-                                .iter()
-                                .map(|text_image| match text_image {
-                                    TextImage::Text(text) => AssistantMessageTextItem::OutputText {
-                                        text: text.clone(),
-                                        annotations: Vec::new(),
-                                    },
-                                    // TODO this shouldn't be required!
-                                    TextImage::Base64Image(_) => todo!(),
-                                    TextImage::ImageUrl(_) => todo!(),
+                                .into_iter()
+                                .filter_map(|text_image| match text_image {
+                                    TextImage::Text(text) => {
+                                        Some(AssistantMessageTextItem::OutputText {
+                                            text,
+                                            annotations: Vec::new(),
+                                        })
+                                    }
+                                    // The API takes no images from the
+                                    // assistant
+                                    TextImage::Base64Image(_) | TextImage::ImageUrl(_) => None,
                                 })
                                 .collect();
                             // TODO fields like status and ID are not tracked in the
@@ -300,19 +582,33 @@ mod responses_api {
                     text: String,
                 },
                 InputImage {
-                    #[serde(default = "ImageDetail::Auto")]
+                    #[serde(default)]
                     detail: ImageDetail,
+                    #[serde(skip_serializing_if = "Option::is_none")]
                     file_id: Option<String>,
+                    /// A URL or a data URL
+                    #[serde(skip_serializing_if = "Option::is_none")]
                     image_url: Option<String>,
                 },
             }
 
-            #[derive(Debug, Serialize, Deserialize)]
+            #[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
             #[serde(rename_all = "snake_case")]
             pub enum ImageDetail {
                 High,
                 Low,
-                Auto(), // parentheses for serde default annotation
+                #[default]
+                Auto,
+            }
+
+            impl From<session::ImageDetail> for ImageDetail {
+                fn from(detail: session::ImageDetail) -> Self {
+                    match detail {
+                        session::ImageDetail::High => ImageDetail::High,
+                        session::ImageDetail::Low => ImageDetail::Low,
+                        session::ImageDetail::Auto => ImageDetail::Auto,
+                    }
+                }
             }
 
             impl UserMessageContentItem<TextImageMessage> for UserMessageTextImageItem {}
@@ -372,6 +668,34 @@ mod responses_api {
                 pub id: String,
             }
 
+            #[derive(Debug, Serialize, Deserialize)]
+            pub struct TextOptions {
+                pub format: TextFormat,
+            }
+
+            #[derive(Debug, Serialize, Deserialize)]
+            #[serde(tag = "type", rename_all = "snake_case")]
+            pub enum TextFormat {
+                Text,
+                JsonSchema {
+                    name: String,
+                    schema: serde_json::Value,
+                    strict: bool,
+                },
+            }
+
+            impl From<JsonFormat> for TextOptions {
+                fn from(format: JsonFormat) -> Self {
+                    TextOptions {
+                        format: TextFormat::JsonSchema {
+                            name: format.name,
+                            schema: format.schema,
+                            strict: true,
+                        },
+                    }
+                }
+            }
+
             #[derive(Debug, Serialize, Deserialize)]
             #[serde(untagged)]
             pub enum ToolChoice {
@@ -379,6 +703,30 @@ mod responses_api {
                 Config(HashMap<String, serde_json::Value>),
             }
 
+            #[derive(Debug, Serialize, Deserialize)]
+            #[serde(tag = "type", rename_all = "snake_case")]
+            pub enum Tool {
+                Function {
+                    name: String,
+                    description: String,
+                    parameters: serde_json::Value,
+                    /// Strict mode requires every property to be required,
+                    /// which not every schema does
+                    strict: bool,
+                },
+            }
+
+            impl From<ToolDefinition> for Tool {
+                fn from(definition: ToolDefinition) -> Self {
+                    Tool::Function {
+                        name: definition.name,
+                        description: definition.description,
+                        parameters: definition.parameters,
+                        strict: false,
+                    }
+                }
+            }
+
             #[derive(Debug, Serialize, Deserialize)]
             pub enum OutputOptions {
                 #[serde(rename = "code_interpreter_call.outputs")]
@@ -409,7 +757,14 @@ mod responses_api {
                 pub id: String,
                 pub output: Vec<Output>,
                 pub status: Status,
-                pub usage: Usage,
+                #[serde(default)]
+                pub incomplete_details: Option<IncompleteDetails>,
+                /// Set if the status is failed
+                #[serde(default)]
+                pub error: Option<ResponseError>,
+                /// Missing if the response failed
+                #[serde(default)]
+                pub usage: Option<Usage>,
 
                 #[serde(skip)]
                 m: PhantomData<M>,
@@ -420,6 +775,7 @@ mod responses_api {
             pub enum Output {
                 Message(MessageOutput),
                 FileSearchToolCall(FileSearchToolCallOutput),
+                #[serde(rename = "function_call")]
                 FunctionToolCall(FunctionToolCallOutput),
                 WebSearchToolCall(WebSearchToolCallOutput),
                 ComputerToolCall(ComputerToolCallOutput),
@@ -511,7 +867,10 @@ mod responses_api {
             #[derive(Debug, Serialize, Deserialize)]
             #[serde(rename_all = "snake_case")]
             pub struct FunctionToolCallOutput {
-                // TODO
+                pub call_id: String,
+                pub name: String,
+                /// JSON encoded
+                pub arguments: String,
             }
 
             #[derive(Debug, Serialize, Deserialize)]
@@ -565,7 +924,10 @@ mod responses_api {
             #[derive(Debug, Serialize, Deserialize)]
             #[serde(rename_all = "snake_case")]
             pub struct CustomToolCallOutput {
-                // TODO
+                pub call_id: String,
+                pub name: String,
+                /// Free-form text, custom tools don't take JSON
+                pub input: String,
             }
 
             #[derive(Debug, Serialize, Deserialize)]
@@ -579,6 +941,38 @@ mod responses_api {
                 Incomplete,
             }
 
+            /// Events of a streamed response, apart from those about
+            /// the progress of individual output items
+            #[derive(Debug, Deserialize)]
+            #[serde(tag = "type", bound(deserialize = ""))]
+            pub enum StreamEvent<M: Modality> {
+                #[serde(rename = "response.output_text.delta")]
+                OutputTextDelta { delta: String },
+                #[serde(rename = "response.completed")]
+                Completed { response: Body<M> },
+                #[serde(rename = "response.incomplete")]
+                Incomplete { response: Body<M> },
+                #[serde(rename = "response.failed")]
+                Failed { response: Body<M> },
+                #[serde(rename = "error")]
+                Error { message: String },
+                #[serde(other)]
+                Other,
+            }
+
+            #[derive(Debug, Serialize, Deserialize)]
+            pub struct ResponseError {
+                pub code: Option<String>,
+                pub message: String,
+            }
+
+            #[derive(Debug, Serialize, Deserialize)]
+            #[serde(rename_all = "snake_case")]
+            pub struct IncompleteDetails {
+                /// "max_output_tokens" or "content_filter"
+                pub reason: String,
+            }
+
             #[derive(Debug, Serialize, Deserialize)]
             #[serde(rename_all = "snake_case")]
             pub struct InputTokenDetails {
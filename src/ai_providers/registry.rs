@@ -0,0 +1,136 @@
+//! Building the models configured for each role.
+
+use crate::config::{Config, ModelProvider, ModelSpec};
+use crate::session::{
+    AIModel, ModelInput, ModelOutput, ModelStream, StreamingAIModel, TextImageMessage, TextMessage,
+};
+
+use super::anthropic::AnthropicModel;
+use super::chat_completions::ChatCompletionsModel;
+use super::http::HttpClient;
+use super::openai::ResponsesModel;
+
+/// Where llama.cpp's server listens by default
+const DEFAULT_CHAT_COMPLETIONS_URL: &str = "http://localhost:8080/v1";
+
+/// A model of any of the supported providers
+pub enum AIModelImpl {
+    OpenAI(Box<ResponsesModel>),
+    Anthropic(Box<AnthropicModel>),
+    ChatCompletions(Box<ChatCompletionsModel>),
+}
+
+impl AIModelImpl {
+    /// Where requests are sent to
+    #[must_use]
+    pub fn base_url(&self) -> &str {
+        match self {
+            AIModelImpl::OpenAI(m) => m.base_url(),
+            AIModelImpl::Anthropic(m) => m.base_url(),
+            AIModelImpl::ChatCompletions(m) => m.base_url(),
+        }
+    }
+}
+
+impl AIModel<TextImageMessage, TextMessage> for AIModelImpl {
+    async fn send(
+        &self,
+        input: ModelInput<TextImageMessage>,
+    ) -> anyhow::Result<ModelOutput<TextMessage>> {
+        match self {
+            AIModelImpl::OpenAI(m) => m.send(input).await,
+            AIModelImpl::Anthropic(m) => m.send(input).await,
+            AIModelImpl::ChatCompletions(m) => m.send(input).await,
+        }
+    }
+}
+
+impl StreamingAIModel<TextImageMessage, TextMessage> for AIModelImpl {
+    async fn send_streaming(
+        &self,
+        input: ModelInput<TextImageMessage>,
+    ) -> anyhow::Result<ModelStream<TextMessage>> {
+        match self {
+            AIModelImpl::OpenAI(m) => m.send_streaming(input).await,
+            AIModelImpl::Anthropic(m) => m.send_streaming(input).await,
+            AIModelImpl::ChatCompletions(m) => m.send_streaming(input).await,
+        }
+    }
+}
+
+/// Builds the model for `spec`, with the credentials and URLs of its
+/// provider from `config`, sending requests with `http`.
+pub fn build_model(
+    spec: &ModelSpec,
+    config: &Config,
+    http: &HttpClient,
+) -> anyhow::Result<AIModelImpl> {
+    let model = spec.model.clone();
+    Ok(match spec.provider {
+        ModelProvider::OpenAI => {
+            let Some(api_key) = config.openai_key.clone() else {
+                anyhow::bail!(
+                    "The model 'openai:{model}' requires an API key, set JARVIS_CODE__OPENAI_KEY"
+                );
+            };
+            AIModelImpl::OpenAI(Box::new(
+                ResponsesModel::new(api_key, model)
+                    .with_image_detail(config.image_detail)
+                    .with_http_client(http.clone()),
+            ))
+        }
+        ModelProvider::Anthropic => {
+            let Some(api_key) = config.anthropic_key.clone() else {
+                anyhow::bail!(
+                    "The model 'anthropic:{model}' requires an API key, set JARVIS_CODE__ANTHROPIC_KEY"
+                );
+            };
+            let mut anthropic = AnthropicModel::new(api_key, model).with_http_client(http.clone());
+            if let Some(url) = &config.anthropic_url {
+                anthropic = anthropic.with_base_url(url.clone());
+            }
+            AIModelImpl::Anthropic(Box::new(anthropic))
+        }
+        ModelProvider::ChatCompletions => AIModelImpl::ChatCompletions(Box::new(
+            ChatCompletionsModel::new(
+                config
+                    .chat_completions_url
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_CHAT_COMPLETIONS_URL.to_owned()),
+                model,
+            )
+            .with_api_key(config.chat_completions_key.clone())
+            .with_image_detail(config.image_detail)
+            .with_http_client(http.clone()),
+        )),
+    })
+}
+
+/// The models for each role, see [`crate::config::ModelRoles`]. Only the
+/// classifier is built up front, so a bad spec for another role doesn't keep
+/// the assistant from starting.
+pub struct RoleModels {
+    pub classifier: AIModelImpl,
+    /// Shared by all models
+    pub http: HttpClient,
+}
+
+impl RoleModels {
+    pub fn from_config(config: &Config) -> anyhow::Result<Self> {
+        let http = HttpClient::new(&config.model_http)?;
+        Ok(Self {
+            classifier: build_model(&config.models.classifier, config, &http)?,
+            http,
+        })
+    }
+
+    /// Builds the model answering questions and brainstorming.
+    pub fn answering(&self, config: &Config) -> anyhow::Result<AIModelImpl> {
+        build_model(&config.models.answering, config, &self.http)
+    }
+
+    /// Builds the model editing code.
+    pub fn code_change(&self, config: &Config) -> anyhow::Result<AIModelImpl> {
+        build_model(&config.models.code_change, config, &self.http)
+    }
+}
@@ -0,0 +1,134 @@
+//! Parsing server-sent events, the format streamed responses come in.
+
+/// One event of the stream
+#[derive(Debug, PartialEq, Eq)]
+pub struct SseEvent {
+    /// The `event:` field, if any
+    pub event: Option<String>,
+    /// The `data:` fields, joined by newlines
+    pub data: String,
+}
+
+/// Splits a byte stream into events. Chunks may end anywhere, even within a
+/// UTF-8 character.
+#[derive(Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    /// How much of the buffer is known not to contain the end of an event
+    scanned: usize,
+}
+
+impl SseParser {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes the next chunk and returns the events it completes.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = Vec::new();
+        while let Some((end, separator_len)) = find_event_end(&self.buffer, self.scanned) {
+            let block: Vec<u8> = self.buffer.drain(..end + separator_len).collect();
+            self.scanned = 0;
+            if let Some(event) = parse_event(&String::from_utf8_lossy(&block[..end])) {
+                events.push(event);
+            }
+        }
+        // A separator may be split across chunks, so its beginning is
+        // scanned again with the next one
+        self.scanned = self.buffer.len().saturating_sub(MAX_SEPARATOR_LEN - 1);
+        events
+    }
+}
+
+const MAX_SEPARATOR_LEN: usize = 4;
+
+/// The position and length of the first blank line ending an event, starting
+/// at `from`
+fn find_event_end(buffer: &[u8], from: usize) -> Option<(usize, usize)> {
+    (from..buffer.len()).find_map(|i| {
+        [&b"\r\n\r\n"[..], b"\n\n", b"\r\r"]
+            .into_iter()
+            .find(|separator| buffer[i..].starts_with(separator))
+            .map(|separator| (i, separator.len()))
+    })
+}
+
+fn parse_event(block: &str) -> Option<SseEvent> {
+    let mut event = None;
+    let mut data: Option<String> = None;
+    for line in block.lines() {
+        // Lines starting with a colon are comments, e.g. to keep the
+        // connection alive
+        if line.is_empty() || line.starts_with(':') {
+            continue;
+        }
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "event" => event = Some(value.to_owned()),
+            "data" => match &mut data {
+                Some(data) => {
+                    data.push('\n');
+                    data.push_str(value);
+                }
+                None => data = Some(value.to_owned()),
+            },
+            _ => (),
+        }
+    }
+    data.map(|data| SseEvent { event, data })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(event: Option<&str>, data: &str) -> SseEvent {
+        SseEvent {
+            event: event.map(ToOwned::to_owned),
+            data: data.to_owned(),
+        }
+    }
+
+    #[test]
+    fn splits_chunks_into_events() {
+        let cases = [
+            (
+                vec!["event: a\ndata: {\"x\":1}\n\nevent: b\ndata: 2\n\n"],
+                vec![event(Some("a"), "{\"x\":1}"), event(Some("b"), "2")],
+            ),
+            (
+                vec!["event: delta\nda", "ta: hel", "lo\n", "\n"],
+                vec![event(Some("delta"), "hello")],
+            ),
+            (
+                vec!["data: line 1\r\ndata: line 2\r\n\r\n"],
+                vec![event(None, "line 1\nline 2")],
+            ),
+            (
+                vec!["data: a\r\n\r", "\n", "data: b\r", "\n\r", "\n"],
+                vec![event(None, "a"), event(None, "b")],
+            ),
+            (
+                vec!["data: a\n", "data: b\n", "\ndata: c", "\n\n"],
+                vec![event(None, "a\nb"), event(None, "c")],
+            ),
+            (
+                vec![": keep-alive\n\n", "data: [DONE]\n\n"],
+                vec![event(None, "[DONE]")],
+            ),
+        ];
+
+        for (chunks, expected) in cases {
+            let mut parser = SseParser::new();
+            let events: Vec<SseEvent> = chunks
+                .iter()
+                .flat_map(|chunk| parser.push(chunk.as_bytes()))
+                .collect();
+            assert_eq!(events, expected, "chunks: {chunks:?}");
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Context;
+use jarvis_code::logger::Logger;
+use jarvis_code::speech::audio::AudioRecorder;
+use jarvis_code::speech::audio::format::{PCMFormat, SoundSpec};
+use jarvis_code::speech::audio::silence::{DEFAULT_THRESHOLD_DBFS, EnergyDetector};
+use jarvis_code::speech::wake_word::{WakeWordModel, peak_level_dbfs};
+
+// Creates a wake word model from a few utterances of the wake phrase.
+//
+//     enroll_wake_word <phrase> <model.json> [recording.pcm ...]
+//
+// Recordings are raw s16le, 24 kHz, mono, e.g. made with record_sample. If
+// none are given, the phrase is recorded from the microphone a few times.
+// Point JARVIS_CODE__WAKE_WORD_MODEL at the model file to use it.
+
+const NUM_RECORDINGS: usize = 4;
+const RECORDING_LENGTH: Duration = Duration::from_millis(2500);
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let usage = "Usage: enroll_wake_word <phrase> <model.json> [recording.pcm ...]";
+    let phrase = args.next().context(usage)?;
+    let model_path = PathBuf::from(args.next().context(usage)?);
+    let recording_paths: Vec<PathBuf> = args.map(PathBuf::from).collect();
+
+    let recordings = if recording_paths.is_empty() {
+        record(&phrase)?
+    } else {
+        recording_paths
+            .iter()
+            .map(|path| std::fs::read(path).context(format!("Failed to read {}", path.display())))
+            .collect::<anyhow::Result<_>>()?
+    };
+
+    for (i, recording) in recordings.iter().enumerate() {
+        let peak = peak_level_dbfs(recording);
+        println!("Recording {}: peak level {peak:.1} dBFS", i + 1);
+        if peak < DEFAULT_THRESHOLD_DBFS {
+            println!("  This is below the speech threshold, so it will be rejected");
+        }
+    }
+
+    let model = WakeWordModel::enroll(
+        phrase,
+        &recordings,
+        EnergyDetector::new(DEFAULT_THRESHOLD_DBFS),
+    )?;
+    model.save(&model_path)?;
+    println!(
+        "Saved {} templates with threshold {:.2} to {}",
+        model.num_templates(),
+        model.threshold,
+        model_path.display()
+    );
+
+    Ok(())
+}
+
+fn record(phrase: &str) -> anyhow::Result<Vec<Vec<u8>>> {
+    let logger = Logger::new();
+    let mut recorder = AudioRecorder::new(logger, None)?;
+    let sound_spec = SoundSpec::PCM {
+        format: PCMFormat::S16LE,
+        sample_rate_hz: 24000,
+        num_channels: 1,
+    };
+
+    let mut recordings = Vec::new();
+    for i in 1..=NUM_RECORDINGS {
+        print!("Press enter, then say '{phrase}' ({i}/{NUM_RECORDINGS})");
+        io::stdout().flush()?;
+        io::stdin().lock().read_line(&mut String::new())?;
+
+        let (receiver, stop, _) = recorder.listen(Some(sound_spec.clone()))?;
+        std::thread::sleep(RECORDING_LENGTH);
+        stop.stop();
+        recordings.push(receiver.into_iter().flatten().collect());
+    }
+
+    Ok(recordings)
+}
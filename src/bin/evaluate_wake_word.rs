@@ -0,0 +1,91 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use jarvis_code::speech::audio::silence::{DEFAULT_THRESHOLD_DBFS, EnergyDetector};
+use jarvis_code::speech::wake_word::{WakeWordModel, WakeWordSpotter};
+
+// Measures how well a wake word model separates the wake phrase from other
+// audio.
+//
+//     evaluate_wake_word <model.json> <positive dir> <negative dir>
+//
+// The directories contain raw s16le, 24 kHz, mono recordings (*.pcm). Each
+// positive recording contains the wake phrase, negative ones don't. Prints
+// the false reject and false accept rates at the model's threshold and at a
+// range of thresholds around it.
+
+/// Bytes fed to the spotter at once, like the chunks from the recorder
+const CHUNK_BYTES: usize = 4800;
+
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let usage = "Usage: evaluate_wake_word <model.json> <positive dir> <negative dir>";
+    let model = WakeWordModel::from_file(Path::new(&args.next().context(usage)?))?;
+    let positive_dir = PathBuf::from(args.next().context(usage)?);
+    let negative_dir = PathBuf::from(args.next().context(usage)?);
+
+    println!("Positive recordings (should contain '{}'):", model.phrase);
+    let positive = best_distances(&model, &positive_dir)?;
+    println!("Negative recordings:");
+    let negative = best_distances(&model, &negative_dir)?;
+    if positive.is_empty() || negative.is_empty() {
+        anyhow::bail!("Both directories need to contain recordings");
+    }
+
+    println!();
+    println!("threshold  false rejects  false accepts");
+    let steps = [0.7, 0.8, 0.9, 1.0, 1.1, 1.2, 1.3];
+    for threshold in steps.map(|factor| model.threshold * factor) {
+        let false_rejects = positive.iter().filter(|d| **d >= threshold).count();
+        let false_accepts = negative.iter().filter(|d| **d < threshold).count();
+        let marker = if (threshold - model.threshold).abs() < f32::EPSILON {
+            " (model)"
+        } else {
+            ""
+        };
+        println!(
+            "{threshold:>9.2}  {:>12.1}%  {:>12.1}%{marker}",
+            percent(false_rejects, positive.len()),
+            percent(false_accepts, negative.len()),
+        );
+    }
+
+    Ok(())
+}
+
+/// The lowest distance to the model for each recording in `dir`, which is
+/// detected at any threshold above it.
+fn best_distances(model: &WakeWordModel, dir: &Path) -> anyhow::Result<Vec<f32>> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .context(format!("Failed to read directory {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "pcm"))
+        .collect();
+    paths.sort();
+
+    paths
+        .iter()
+        .map(|path| {
+            let recording =
+                std::fs::read(path).context(format!("Failed to read {}", path.display()))?;
+            // Nothing should be detected during the evaluation, only the
+            // distances are of interest
+            let mut spotter = WakeWordSpotter::new(
+                model.clone(),
+                Some(f32::NEG_INFINITY),
+                EnergyDetector::new(DEFAULT_THRESHOLD_DBFS),
+            );
+            for chunk in recording.chunks(CHUNK_BYTES) {
+                spotter.push(chunk);
+            }
+            let distance = spotter.best_distance();
+            println!("  {distance:>8.2}  {}", path.display());
+            Ok(distance)
+        })
+        .collect()
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn percent(count: usize, total: usize) -> f32 {
+    count as f32 * 100.0 / total as f32
+}
@@ -1,26 +1,362 @@
-use std::{env, path::PathBuf, str::FromStr};
+use std::{env, path::PathBuf, str::FromStr, time::Duration};
 
 use anyhow::Context;
 
+use crate::ai_providers::http::HttpOptions;
+use crate::session::ImageDetail;
+use crate::speech::audio::silence;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum InputMode {
+    Voice,
+    Keyboard,
+}
+
+/// The service or program turning recorded speech into text
+#[derive(Clone)]
+pub enum SpeechBackend {
+    OpenAI,
+    /// A local program, e.g. whisper.cpp
+    Command(TranscriptionCommand),
+}
+
+#[derive(Clone)]
+pub struct TranscriptionCommand {
+    /// Run by `sh -c`, with `{wav}` replaced by the path of the recorded
+    /// utterance
+    pub command: String,
+    pub output: CommandOutputFormat,
+}
+
+/// How the transcript is printed to stdout by a transcription command
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CommandOutputFormat {
+    Text,
+    /// Either `{"text": "...", "language": "en"}`, or the format of
+    /// whisper.cpp with `{"transcription": [{"text": "..."}, ...]}`. The
+    /// language is optional.
+    Json,
+}
+
+/// Parameters for holding back silence instead of streaming it to the
+/// transcription service
+#[derive(Clone, Copy)]
+pub struct SilenceTrimming {
+    /// Level above which audio is considered speech
+    pub threshold_dbfs: f64,
+    /// Silence right before speech that is sent nevertheless
+    pub pre_roll_ms: u32,
+    /// Silence after speech that is sent before streaming pauses
+    pub hangover_ms: u32,
+}
+
+impl Default for SilenceTrimming {
+    fn default() -> Self {
+        Self {
+            threshold_dbfs: silence::DEFAULT_THRESHOLD_DBFS,
+            pre_roll_ms: 300,
+            hangover_ms: 1000,
+        }
+    }
+}
+
+/// The API a model is served by
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModelProvider {
+    /// The OpenAI Responses API
+    OpenAI,
+    /// The Anthropic Messages API
+    Anthropic,
+    /// Any server with an OpenAI compatible `/chat/completions` endpoint,
+    /// like llama.cpp, vLLM or Ollama
+    ChatCompletions,
+}
+
+/// A model of a provider, written like `openai:gpt-4.1-mini`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModelSpec {
+    pub provider: ModelProvider,
+    /// The id the provider knows the model by
+    pub model: String,
+}
+
+impl FromStr for ModelSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((provider, model)) = s.split_once(':') else {
+            anyhow::bail!("Invalid model '{s}', expected '<provider>:<model>'");
+        };
+        let provider = match provider.trim() {
+            "openai" => ModelProvider::OpenAI,
+            "anthropic" => ModelProvider::Anthropic,
+            "chat-completions" => ModelProvider::ChatCompletions,
+            other => anyhow::bail!(
+                "Invalid model provider '{other}', expected one of 'openai', 'anthropic' or 'chat-completions'"
+            ),
+        };
+        let model = model.trim();
+        if model.is_empty() {
+            anyhow::bail!("Invalid model '{s}', the model id is missing");
+        }
+        Ok(Self {
+            provider,
+            model: model.to_owned(),
+        })
+    }
+}
+
+impl Default for ModelSpec {
+    fn default() -> Self {
+        Self {
+            provider: ModelProvider::OpenAI,
+            model: "gpt-4.1-nano-2025-04-14".to_owned(),
+        }
+    }
+}
+
+/// Which model is used for what. A small model is usually good enough for
+/// classification, while code changes benefit from a larger one.
+#[derive(Clone, Debug, Default)]
+pub struct ModelRoles {
+    /// Classifies the intent of the user
+    pub classifier: ModelSpec,
+    /// Answers questions and brainstorms
+    pub answering: ModelSpec,
+    /// Edits code
+    pub code_change: ModelSpec,
+}
+
+#[derive(Clone)]
 pub struct Config {
-    pub openai_key: String,
+    /// Required if a model of OpenAI or the OpenAI speech backend is used
+    pub openai_key: Option<String>,
     pub recording_file: Option<PathBuf>,
+    pub speech_backend: SpeechBackend,
+    /// Root of the repository the user is working on
+    pub project_dir: PathBuf,
+    /// JSON file with additional rules for turning spoken code into text
+    pub spoken_code_rules: Option<PathBuf>,
+    /// ISO 639-1 codes of the languages the user speaks. With more than
+    /// one, the language of each utterance is detected.
+    pub languages: Vec<String>,
+    /// Overrides the URL of the realtime transcription API, e.g. to use a
+    /// local mock server
+    pub realtime_url: Option<String>,
+    /// Transcriptions with a lower average token probability are not acted
+    /// upon, instead the user is asked to confirm or repeat
+    pub min_transcription_confidence: f64,
+    /// Whether to start listening to the microphone or the keyboard
+    pub input_mode: InputMode,
+    pub keyboard_history_file: Option<PathBuf>,
+    /// JSON file accumulating the transcription usage per day. Without it,
+    /// usage is only tracked for the session.
+    pub usage_ledger_file: Option<PathBuf>,
+    /// JSON file with the prices used to estimate transcription costs
+    pub transcription_price_table: Option<PathBuf>,
+    /// JSON lines file recording corrections of misheard commands. Without
+    /// it, corrections are not recorded.
+    pub corrections_log_file: Option<PathBuf>,
+    /// `None` if all audio should be streamed. Backends that detect the end
+    /// of an utterance themselves fall back to the defaults.
+    pub silence_trimming: Option<SilenceTrimming>,
+    /// Model file created by `enroll_wake_word`. Without it, transcription
+    /// starts right away instead of waiting for the wake phrase.
+    pub wake_word_model: Option<PathBuf>,
+    /// Overrides the detection threshold stored in the wake word model
+    pub wake_word_threshold: Option<f32>,
+    /// Whether talking while the assistant is busy interrupts it
+    pub barge_in: bool,
+    pub models: ModelRoles,
+    /// Required if a model of Anthropic is used
+    pub anthropic_key: Option<String>,
+    /// Overrides the URL of the Anthropic API
+    pub anthropic_url: Option<String>,
+    /// Base URL of the server for `chat-completions` models, including the
+    /// version, e.g. `http://localhost:8080/v1`
+    pub chat_completions_url: Option<String>,
+    pub chat_completions_key: Option<String>,
+    /// How closely models look at images, for providers that support it
+    pub image_detail: ImageDetail,
+    /// Connections, timeouts and retries of model calls
+    pub model_http: HttpOptions,
+    /// Whether to connect to the classifier's provider as soon as the user
+    /// starts speaking, so the first call after the utterance is quicker
+    pub prewarm_connections: bool,
 }
 
 const ENV_PREFIX: &str = "JARVIS_CODE__";
 
+const DEFAULT_MIN_TRANSCRIPTION_CONFIDENCE: f64 = 0.6;
+
 pub fn from_env() -> anyhow::Result<Config> {
-    let openai_key = get_env("OPENAI_KEY")?;
     let recording_file = get_opt_env("RECORDING_FILE")
         .map(|s| PathBuf::from_str(&s).context("Could not parse provided recording file path"))
         .map_or(Ok(None), |v| v.map(Some))?;
+    let speech_backend = match get_opt_env("SPEECH_BACKEND").as_deref() {
+        None | Some("openai") => SpeechBackend::OpenAI,
+        Some("command") => SpeechBackend::Command(TranscriptionCommand {
+            command: get_env("TRANSCRIPTION_COMMAND")?,
+            output: match get_opt_env("TRANSCRIPTION_COMMAND_OUTPUT").as_deref() {
+                None | Some("text") => CommandOutputFormat::Text,
+                Some("json") => CommandOutputFormat::Json,
+                Some(other) => anyhow::bail!(
+                    "Invalid transcription command output '{other}', expected one of 'text' or 'json'"
+                ),
+            },
+        }),
+        Some(other) => {
+            anyhow::bail!("Invalid speech backend '{other}', expected one of 'openai' or 'command'")
+        }
+    };
+    let project_dir = match get_opt_env("PROJECT_DIR") {
+        Some(s) => PathBuf::from_str(&s).context("Could not parse provided project directory")?,
+        None => env::current_dir().context("Could not determine the current directory")?,
+    };
+    let spoken_code_rules = get_opt_env("SPOKEN_CODE_RULES")
+        .map(|s| PathBuf::from_str(&s).context("Could not parse provided spoken code rules path"))
+        .map_or(Ok(None), |v| v.map(Some))?;
+    let languages = match get_opt_env("LANGUAGES") {
+        Some(s) => s
+            .split(',')
+            .map(|code| {
+                let code = code.trim().to_lowercase();
+                if code.len() != 2 || !code.chars().all(|c| c.is_ascii_lowercase()) {
+                    anyhow::bail!(
+                        "Invalid language '{code}', expected an ISO 639-1 code like 'en'"
+                    );
+                }
+                Ok(code)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?,
+        None => vec!["en".to_owned()],
+    };
+    let realtime_url = get_opt_env("REALTIME_URL");
+    let min_transcription_confidence = parse_opt_env("MIN_TRANSCRIPTION_CONFIDENCE")?
+        .unwrap_or(DEFAULT_MIN_TRANSCRIPTION_CONFIDENCE);
+    let input_mode = match get_opt_env("INPUT_MODE").as_deref() {
+        None | Some("voice") => InputMode::Voice,
+        Some("keyboard") => InputMode::Keyboard,
+        Some(other) => {
+            anyhow::bail!("Invalid input mode '{other}', expected one of 'voice' or 'keyboard'")
+        }
+    };
+    let keyboard_history_file = get_opt_env("KEYBOARD_HISTORY_FILE")
+        .map(|s| PathBuf::from_str(&s).context("Could not parse provided keyboard history path"))
+        .map_or(Ok(None), |v| v.map(Some))?;
+    let usage_ledger_file = get_opt_env("USAGE_LEDGER_FILE")
+        .map(|s| PathBuf::from_str(&s).context("Could not parse provided usage ledger path"))
+        .map_or(Ok(None), |v| v.map(Some))?;
+    let corrections_log_file = get_opt_env("CORRECTIONS_LOG_FILE")
+        .map(|s| PathBuf::from_str(&s).context("Could not parse provided corrections log path"))
+        .map_or(Ok(None), |v| v.map(Some))?;
+    let transcription_price_table = get_opt_env("TRANSCRIPTION_PRICE_TABLE")
+        .map(|s| PathBuf::from_str(&s).context("Could not parse provided price table path"))
+        .map_or(Ok(None), |v| v.map(Some))?;
+    let silence_trimming = match get_opt_env("TRIM_SILENCE").as_deref() {
+        Some("false") => None,
+        None | Some("true") => {
+            let defaults = SilenceTrimming::default();
+            Some(SilenceTrimming {
+                threshold_dbfs: parse_opt_env("SILENCE_THRESHOLD_DBFS")?
+                    .unwrap_or(defaults.threshold_dbfs),
+                pre_roll_ms: parse_opt_env("SILENCE_PRE_ROLL_MS")?.unwrap_or(defaults.pre_roll_ms),
+                hangover_ms: parse_opt_env("SILENCE_HANGOVER_MS")?.unwrap_or(defaults.hangover_ms),
+            })
+        }
+        Some(other) => {
+            anyhow::bail!("Invalid value '{other}' for TRIM_SILENCE, expected 'true' or 'false'")
+        }
+    };
+
+    let wake_word_model = get_opt_env("WAKE_WORD_MODEL")
+        .map(|s| PathBuf::from_str(&s).context("Could not parse provided wake word model path"))
+        .map_or(Ok(None), |v| v.map(Some))?;
+    let wake_word_threshold = parse_opt_env("WAKE_WORD_THRESHOLD")?;
+    let barge_in = parse_opt_env("BARGE_IN")?.unwrap_or(true);
+
+    let default_model = get_model_spec("MODEL")?.unwrap_or_default();
+    let models = ModelRoles {
+        classifier: get_model_spec("CLASSIFIER_MODEL")?.unwrap_or_else(|| default_model.clone()),
+        answering: get_model_spec("ANSWERING_MODEL")?.unwrap_or_else(|| default_model.clone()),
+        code_change: get_model_spec("CODE_CHANGE_MODEL")?.unwrap_or(default_model),
+    };
+    let openai_key = get_opt_env("OPENAI_KEY");
+    if openai_key.is_none() && needs_openai_key(&speech_backend, &models) {
+        anyhow::bail!(
+            "environment variable {ENV_PREFIX}OPENAI_KEY is required by OpenAI models and the OpenAI speech backend"
+        );
+    }
+    let anthropic_key = get_opt_env("ANTHROPIC_KEY");
+    let anthropic_url = get_opt_env("ANTHROPIC_URL");
+    let chat_completions_url = get_opt_env("CHAT_COMPLETIONS_URL");
+    let chat_completions_key = get_opt_env("CHAT_COMPLETIONS_KEY");
+    let image_detail = get_opt_env("IMAGE_DETAIL")
+        .map(|s| {
+            s.parse::<ImageDetail>().context(format!(
+                "Could not parse environment variable {ENV_PREFIX}IMAGE_DETAIL"
+            ))
+        })
+        .transpose()?
+        .unwrap_or_default();
+    let mut model_http = HttpOptions::default();
+    if let Some(secs) = parse_opt_env("MODEL_CONNECT_TIMEOUT_SECS")? {
+        model_http.connect_timeout = Duration::from_secs(secs);
+    }
+    if let Some(secs) = parse_opt_env("MODEL_REQUEST_TIMEOUT_SECS")? {
+        model_http.request_timeout = Duration::from_secs(secs);
+    }
+    if let Some(max_retries) = parse_opt_env("MODEL_MAX_RETRIES")? {
+        model_http.retry.max_retries = max_retries;
+    }
+    if let Some(secs) = parse_opt_env("MODEL_POOL_IDLE_TIMEOUT_SECS")? {
+        model_http.pool_idle_timeout = Duration::from_secs(secs);
+    }
+    model_http.http2_prior_knowledge =
+        parse_opt_env("MODEL_HTTP2_PRIOR_KNOWLEDGE")?.unwrap_or(false);
+    model_http.proxy = get_opt_env("MODEL_PROXY");
+    model_http.ca_certificate = get_opt_env("MODEL_CA_CERTIFICATE")
+        .map(|s| PathBuf::from_str(&s).context("Could not parse provided CA certificate path"))
+        .map_or(Ok(None), |v| v.map(Some))?;
+    let prewarm_connections = parse_opt_env("PREWARM_CONNECTIONS")?.unwrap_or(false);
 
     Ok(Config {
         openai_key,
         recording_file,
+        speech_backend,
+        project_dir,
+        spoken_code_rules,
+        languages,
+        realtime_url,
+        min_transcription_confidence,
+        input_mode,
+        keyboard_history_file,
+        usage_ledger_file,
+        transcription_price_table,
+        corrections_log_file,
+        silence_trimming,
+        wake_word_model,
+        wake_word_threshold,
+        barge_in,
+        models,
+        anthropic_key,
+        anthropic_url,
+        chat_completions_url,
+        chat_completions_key,
+        image_detail,
+        model_http,
+        prewarm_connections,
     })
 }
 
+/// Whether anything configured talks to the OpenAI API
+fn needs_openai_key(speech_backend: &SpeechBackend, models: &ModelRoles) -> bool {
+    matches!(speech_backend, SpeechBackend::OpenAI)
+        || [&models.classifier, &models.answering, &models.code_change]
+            .iter()
+            .any(|spec| spec.provider == ModelProvider::OpenAI)
+}
+
 fn get_env(key: &str) -> anyhow::Result<String> {
     env::var(format!("{ENV_PREFIX}{key}")).context(format!(
         "environment variable {ENV_PREFIX}{key} is required"
@@ -30,3 +366,100 @@ fn get_env(key: &str) -> anyhow::Result<String> {
 fn get_opt_env(key: &str) -> Option<String> {
     env::var(format!("{ENV_PREFIX}{key}")).ok()
 }
+
+fn get_model_spec(key: &str) -> anyhow::Result<Option<ModelSpec>> {
+    get_opt_env(key)
+        .map(|s| {
+            s.parse().context(format!(
+                "Could not parse environment variable {ENV_PREFIX}{key}"
+            ))
+        })
+        .transpose()
+}
+
+fn parse_opt_env<T: FromStr>(key: &str) -> anyhow::Result<Option<T>>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    get_opt_env(key)
+        .map(|s| {
+            s.parse::<T>().context(format!(
+                "Could not parse environment variable {ENV_PREFIX}{key}"
+            ))
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_model_specs() {
+        let cases = [
+            (
+                "openai:gpt-4.1-mini",
+                Some((ModelProvider::OpenAI, "gpt-4.1-mini")),
+            ),
+            (
+                "anthropic:claude-sonnet-4-0",
+                Some((ModelProvider::Anthropic, "claude-sonnet-4-0")),
+            ),
+            // Ollama's model ids contain a colon themselves
+            (
+                "chat-completions:qwen2.5-coder:7b",
+                Some((ModelProvider::ChatCompletions, "qwen2.5-coder:7b")),
+            ),
+            ("gpt-4.1-mini", None),
+            ("openai:", None),
+            ("mistral:large", None),
+        ];
+
+        for (input, expected) in cases {
+            let parsed = input.parse::<ModelSpec>().ok();
+            let expected = expected.map(|(provider, model)| ModelSpec {
+                provider,
+                model: model.to_owned(),
+            });
+            assert_eq!(parsed, expected, "input: {input:?}");
+        }
+    }
+
+    #[test]
+    fn requires_the_openai_key_only_if_used() {
+        let local = ModelSpec {
+            provider: ModelProvider::ChatCompletions,
+            model: "qwen2.5-coder:7b".to_owned(),
+        };
+        let local_models = ModelRoles {
+            classifier: local.clone(),
+            answering: local.clone(),
+            code_change: local.clone(),
+        };
+        let command = SpeechBackend::Command(TranscriptionCommand {
+            command: "whisper-cli -f {wav}".to_owned(),
+            output: CommandOutputFormat::Text,
+        });
+        let cases = [
+            (SpeechBackend::OpenAI, local_models.clone(), true),
+            (command.clone(), ModelRoles::default(), true),
+            (
+                command.clone(),
+                ModelRoles {
+                    code_change: ModelSpec::default(),
+                    ..local_models.clone()
+                },
+                true,
+            ),
+            (command, local_models, false),
+        ];
+
+        for (speech_backend, models, expected) in cases {
+            assert_eq!(
+                needs_openai_key(&speech_backend, &models),
+                expected,
+                "models: {models:?}"
+            );
+        }
+    }
+}
@@ -19,6 +19,12 @@ pub struct Logger {
     verbosity: Level,
 }
 
+impl Default for Logger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Logger {
     pub fn new() -> Self {
         Self {
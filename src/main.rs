@@ -1,11 +1,19 @@
-use jarvis_code::actions::IntentClassifier;
-use jarvis_code::ai_providers::openai::Gpt4_1Nano;
+use jarvis_code::actions::{
+    ClarificationAnswer, CorrectionLog, Dictation, DictationTarget, IntentClassifier,
+    clarification_request, correct_last_user_message, detect_correction, dictation_command,
+    resolve_unconfirmed, run_dictation,
+};
+use jarvis_code::ai_providers::http::ApiError;
+use jarvis_code::ai_providers::registry::RoleModels;
 use jarvis_code::app_composite;
 use jarvis_code::config;
 use jarvis_code::session::Author;
+use jarvis_code::session::CancellationToken;
+use jarvis_code::session::Cancelled;
 use jarvis_code::session::ConversationContext;
+use jarvis_code::session::ScratchBuffer;
 use jarvis_code::session::TextMessage;
-use jarvis_code::speech::input::Transcription;
+use jarvis_code::speech::input::{ListenerEvent, Transcription};
 
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> anyhow::Result<()> {
@@ -17,6 +25,33 @@ async fn main() -> anyhow::Result<()> {
     let config = config::from_env()?;
 
     let mut app_composite = app_composite::AppComposite::new(&config)?;
+    let models = RoleModels::from_config(&config)?;
+    let http = models.http.clone();
+    if config.prewarm_connections {
+        // The classifier is the first model called after an utterance
+        let url = models.classifier.base_url().to_owned();
+        let runtime = tokio::runtime::Handle::current();
+        let logger = app_composite.logger;
+        let http = http.clone();
+        app_composite.speech_listener.on_speech_started(move || {
+            let (http, url) = (http.clone(), url.clone());
+            runtime.spawn(async move {
+                if let Err(err) = http.prewarm(&url).await {
+                    logger.debug(format!("Could not pre-warm the connection: {err:#}"));
+                }
+            });
+        });
+    }
+    let classifier = IntentClassifier::new(models.classifier);
+
+    // A transcription the user has been asked to confirm
+    let mut unconfirmed_text: Option<String> = None;
+    let mut conversation_context = ConversationContext {
+        log: Vec::new(),
+        language: None,
+    };
+    let correction_log = CorrectionLog::new(config.corrections_log_file.clone());
+    let mut scratch = ScratchBuffer::new();
 
     loop {
         let user_command = app_composite.speech_listener.listen_to_input().await?;
@@ -24,25 +59,123 @@ async fn main() -> anyhow::Result<()> {
             .logger
             .debug(format!("User said: {user_command:?}"));
 
-        let user_text = match user_command {
-            Transcription::Empty => String::default(),
-            Transcription::Some { text } => text,
+        // Along with the correction of a transcription the user was asked to
+        // confirm, if the answer was one
+        let (user_text, pending_correction) = match user_command {
+            Transcription::Empty => continue,
+            Transcription::Some {
+                text,
+                confidence,
+                language,
+                timing,
+            } => {
+                if let Some(timing) = timing {
+                    app_composite
+                        .logger
+                        .debug(format!("Utterance timing: {timing}"));
+                }
+                if language.is_some() {
+                    conversation_context.language = language;
+                }
+                let answer = unconfirmed_text
+                    .take()
+                    .and_then(|unconfirmed| resolve_unconfirmed(&unconfirmed, &text));
+                match answer {
+                    Some(ClarificationAnswer::Confirmed(text)) => (text, None),
+                    Some(ClarificationAnswer::Corrected(correction)) => {
+                        (correction.corrected.clone(), Some(correction))
+                    }
+                    None => {
+                        if let Some(question) = clarification_request(
+                            &text,
+                            confidence.as_ref(),
+                            config.min_transcription_confidence,
+                        ) {
+                            app_composite.logger.info(question);
+                            unconfirmed_text = Some(text);
+                            continue;
+                        }
+                        (text, None)
+                    }
+                }
+            }
         };
-        let conversation_context = ConversationContext {
-            log: vec![TextMessage {
+
+        if let Some(target) = dictation_command(&user_text, &config.project_dir) {
+            run_dictation(
+                &mut app_composite.speech_listener,
+                Dictation::new(target.clone()),
+                &mut scratch,
+                app_composite.logger,
+            )
+            .await?;
+            if target == DictationTarget::Scratch {
+                app_composite
+                    .logger
+                    .info(format!("Scratch buffer:\n{}", scratch.text()));
+            }
+            continue;
+        }
+
+        // A correction replaces the misheard message instead of adding a turn,
+        // unless it corrected a transcription that hasn't been acted on yet
+        let is_new_turn = pending_correction.is_some();
+        let correction = pending_correction.or_else(|| {
+            detect_correction(&user_text).and_then(|correction| {
+                correct_last_user_message(&mut conversation_context, &correction)
+            })
+        });
+        if let Some(correction) = &correction {
+            app_composite.logger.info(format!(
+                "Corrected \"{}\" to \"{}\"",
+                correction.original, correction.corrected
+            ));
+            if let Err(err) = correction_log.record(correction) {
+                app_composite
+                    .logger
+                    .warn(format!("Could not log the correction: {err:#}"));
+            }
+        }
+        if is_new_turn || correction.is_none() {
+            conversation_context.log.push(TextMessage {
                 author: Author::User,
                 parts: vec![user_text],
-            }],
-        };
+            });
+        }
 
-        let model = Gpt4_1Nano::new(config.openai_key);
-        let classifier = IntentClassifier::new(model);
-
-        classifier
-            .classify_intent(&conversation_context.into())
-            .await?;
+        // Talking while the model is busy cancels the request and starts a
+        // new turn
+        let barge_in = app_composite.speech_listener.monitor_barge_in()?;
+        let cancellation = barge_in
+            .as_ref()
+            .map_or_else(CancellationToken::new, |monitor| {
+                monitor.cancellation().clone()
+            });
+        let intent = classifier
+            .classify_intent(&conversation_context.clone().into(), &cancellation)
+            .await;
+        app_composite
+            .logger
+            .debug(format!("Model connections: {}", http.stats()));
+        if let Some(monitor) = barge_in {
+            if monitor.finish().await == Some(ListenerEvent::SpeechStarted) {
+                app_composite.logger.info("Interrupted, listening");
+            }
+        }
+        let intent = match intent {
+            Err(err) if err.is::<Cancelled>() => continue,
+            // Worth trying again after the user fixed the cause or waited
+            Err(err) if err.is::<ApiError>() => {
+                if let Some(api_error) = err.downcast_ref::<ApiError>() {
+                    app_composite.logger.warn(api_error.user_message());
+                }
+                continue;
+            }
+            intent => intent?,
+        };
+        app_composite.logger.info(format!("Intent: {intent:?}"));
 
-        todo!("for now let's run the loop only once")
+        // Acting on the intent isn't implemented yet:
         // user_command = get_user_input()
         // action = generate_action()
         // render_action()?;
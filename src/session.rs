@@ -1,3 +1,13 @@
+mod cancellation;
+mod images;
 mod model;
+mod scratch;
+mod structured;
+mod tools;
 
+pub use cancellation::{CancellationToken, Cancelled};
+pub use images::{ImageDetail, ImageLimits, load_image};
 pub use model::*;
+pub use scratch::ScratchBuffer;
+pub use structured::{JsonFormat, StructuredOutput, send_structured};
+pub use tools::{Tool, ToolCall, ToolDefinition, ToolResult, Toolbox, send_with_tools};
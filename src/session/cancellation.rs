@@ -0,0 +1,117 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tokio::sync::Notify;
+
+/// Signals work in progress, like a model call, that its result is no longer
+/// needed. Clones share the same state.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<CancellationState>,
+}
+
+#[derive(Default)]
+struct CancellationState {
+    is_cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancellationToken {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.inner.is_cancelled.store(true, Ordering::Release);
+        self.inner.notify.notify_waiters();
+    }
+
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.is_cancelled.load(Ordering::Acquire)
+    }
+
+    /// Completes once the token is cancelled.
+    pub async fn cancelled(&self) {
+        loop {
+            // Created before checking the flag, so a cancellation in between
+            // isn't missed
+            let notified = self.inner.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// The error returned by work that has been cancelled
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::session::{AIModel, ModelInput, ModelOutput, TextMessage};
+
+    /// A model that never answers
+    struct SilentModel;
+
+    impl AIModel<TextMessage, TextMessage> for SilentModel {
+        fn send(
+            &self,
+            _: ModelInput<TextMessage>,
+        ) -> impl Future<Output = anyhow::Result<ModelOutput<TextMessage>>> {
+            std::future::pending()
+        }
+    }
+
+    fn input() -> ModelInput<TextMessage> {
+        ModelInput {
+            instructions: String::new(),
+            log: Vec::new(),
+            tools: Vec::new(),
+            tool_results: Vec::new(),
+            json_format: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn cancels_requests_in_progress() {
+        let token = CancellationToken::new();
+        let cancel = async {
+            // Lets the request start first
+            tokio::task::yield_now().await;
+            token.cancel();
+        };
+
+        let (output, ()) = tokio::join!(SilentModel.send_cancellable(input(), &token), cancel);
+
+        assert!(output.err().is_some_and(|err| err.is::<Cancelled>()));
+    }
+
+    #[tokio::test]
+    async fn completes_at_once_when_already_cancelled() {
+        let token = CancellationToken::new();
+        token.cancel();
+
+        let cancelled = tokio::time::timeout(Duration::from_secs(1), token.cancelled()).await;
+        let output = SilentModel.send_cancellable(input(), &token).await;
+
+        assert!(cancelled.is_ok());
+        assert!(token.is_cancelled());
+        assert!(output.err().is_some_and(|err| err.is::<Cancelled>()));
+    }
+}
@@ -0,0 +1,143 @@
+use std::io::Cursor;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Context;
+use base64::prelude::*;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+
+use super::TextImage;
+
+/// Images aren't downscaled below this, but rejected instead
+const MIN_DIMENSION: u32 = 256;
+
+/// How closely a model looks at images. Low detail costs fewer tokens.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ImageDetail {
+    Low,
+    High,
+    /// Lets the provider decide
+    #[default]
+    Auto,
+}
+
+impl FromStr for ImageDetail {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(Self::Low),
+            "high" => Ok(Self::High),
+            "auto" => Ok(Self::Auto),
+            other => {
+                anyhow::bail!(
+                    "Invalid image detail '{other}', expected one of 'low', 'high' or 'auto'"
+                )
+            }
+        }
+    }
+}
+
+/// Bounds for images sent to models. Providers reject larger images, and
+/// scale down larger dimensions themselves anyway.
+#[derive(Clone, Copy, Debug)]
+pub struct ImageLimits {
+    /// Of the encoded file
+    pub max_bytes: usize,
+    /// Of the longer side, in pixels
+    pub max_dimension: u32,
+}
+
+impl Default for ImageLimits {
+    fn default() -> Self {
+        Self {
+            // The limit of the Anthropic API
+            max_bytes: 5 * 1024 * 1024,
+            max_dimension: 2048,
+        }
+    }
+}
+
+/// Loads a PNG or JPEG file as a data URL, downscaled to fit into `limits`.
+pub fn load_image(path: &Path, limits: ImageLimits) -> anyhow::Result<TextImage> {
+    let bytes = std::fs::read(path).context(format!("Could not read image {}", path.display()))?;
+    let format = image::guess_format(&bytes).context(format!(
+        "Could not recognize the format of image {}",
+        path.display()
+    ))?;
+    let media_type = match format {
+        ImageFormat::Png => "image/png",
+        ImageFormat::Jpeg => "image/jpeg",
+        _ => anyhow::bail!(
+            "Unsupported format of image {}, expected PNG or JPEG",
+            path.display()
+        ),
+    };
+    let image = image::load_from_memory_with_format(&bytes, format)
+        .context(format!("Could not decode image {}", path.display()))?;
+
+    let longer_side = image.width().max(image.height());
+    if longer_side <= limits.max_dimension && bytes.len() <= limits.max_bytes {
+        return Ok(data_url(media_type, &bytes));
+    }
+
+    let mut dimension = longer_side.min(limits.max_dimension);
+    loop {
+        let downscaled = encode(
+            &image.resize(dimension, dimension, FilterType::Triangle),
+            format,
+        )?;
+        if downscaled.len() <= limits.max_bytes {
+            return Ok(data_url(media_type, &downscaled));
+        }
+        if dimension <= MIN_DIMENSION {
+            anyhow::bail!(
+                "Image {} exceeds {} bytes even when downscaled to {dimension} pixels",
+                path.display(),
+                limits.max_bytes
+            );
+        }
+        dimension = (dimension * 3 / 4).max(MIN_DIMENSION);
+    }
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = Cursor::new(Vec::new());
+    match format {
+        // JPEG has no alpha channel
+        ImageFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8()).write_to(&mut bytes, format),
+        _ => image.write_to(&mut bytes, format),
+    }
+    .context("Could not encode the downscaled image")?;
+    Ok(bytes.into_inner())
+}
+
+fn data_url(media_type: &str, bytes: &[u8]) -> TextImage {
+    TextImage::Base64Image(format!(
+        "data:{media_type};base64,{}",
+        BASE64_STANDARD.encode(bytes)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downscales_large_images() {
+        let path =
+            std::env::temp_dir().join(format!("jarvis_code_image_{}.png", std::process::id()));
+        image::RgbImage::new(3000, 1500).save(&path).unwrap();
+
+        let loaded = load_image(&path, ImageLimits::default());
+        std::fs::remove_file(&path).unwrap();
+
+        let TextImage::Base64Image(url) = loaded.unwrap() else {
+            panic!("Expected a base64 image");
+        };
+        let data = url.strip_prefix("data:image/png;base64,").unwrap();
+        let image = image::load_from_memory(&BASE64_STANDARD.decode(data).unwrap()).unwrap();
+        assert_eq!((image.width(), image.height()), (2048, 1024));
+    }
+}
@@ -1,9 +1,19 @@
 // TODO session/model is not a good place for all the LLM related models
 
 use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
 
+use futures_util::{Stream, StreamExt, stream};
+
+use super::{CancellationToken, Cancelled, JsonFormat, ToolCall, ToolDefinition, ToolResult};
+
+#[derive(Clone)]
 pub struct ConversationContext<I: InputModes> {
     pub log: Vec<I>,
+    /// ISO 639-1 code of the language the user spoke last, which answers
+    /// should be in
+    pub language: Option<String>,
     // current code change
     // code vicinity (current and related files or parts of it?)
 }
@@ -28,21 +38,179 @@ impl From<ConversationContext<TextMessage>> for ConversationContext<TextImageMes
                 }
             })
             .collect();
-        ConversationContext { log }
+        ConversationContext {
+            log,
+            language: ctx.language,
+        }
     }
 }
 
 pub trait AIModel<I: InputModes, O: OutputModes> {
     fn send(&self, i: ModelInput<I>) -> impl Future<Output = anyhow::Result<ModelOutput<O>>>;
+
+    /// Like [`AIModel::send`], but gives up with a [`Cancelled`] error as
+    /// soon as `cancellation` is cancelled. The request is dropped then, which
+    /// closes its connection.
+    fn send_cancellable(
+        &self,
+        i: ModelInput<I>,
+        cancellation: &CancellationToken,
+    ) -> impl Future<Output = anyhow::Result<ModelOutput<O>>> {
+        async move {
+            tokio::select! {
+                output = self.send(i) => output,
+                () = cancellation.cancelled() => Err(Cancelled.into()),
+            }
+        }
+    }
+}
+
+/// A model that can send its output while it's being generated.
+pub trait StreamingAIModel<I: InputModes, O: OutputModes + Send + 'static>: AIModel<I, O> {
+    /// Sends the input and returns the stream of the output. Without
+    /// support by the provider, the whole output comes at once at the end.
+    fn send_streaming(
+        &self,
+        i: ModelInput<I>,
+    ) -> impl Future<Output = anyhow::Result<ModelStream<O>>> {
+        async move {
+            let output = self.send(i).await?;
+            Ok(ModelStream::new(stream::once(async move {
+                Ok(StreamEvent::Completed(output))
+            })))
+        }
+    }
+}
+
+pub enum StreamEvent<O: OutputModes> {
+    /// The next piece of text
+    TextDelta(String),
+    /// The whole output, always the last event
+    Completed(ModelOutput<O>),
+}
+
+/// The events of a streamed model output
+pub struct ModelStream<O: OutputModes> {
+    events: Pin<Box<dyn Stream<Item = anyhow::Result<StreamEvent<O>>> + Send>>,
+}
+
+impl<O: OutputModes> ModelStream<O> {
+    pub fn new(
+        events: impl Stream<Item = anyhow::Result<StreamEvent<O>>> + Send + 'static,
+    ) -> Self {
+        Self {
+            events: Box::pin(events),
+        }
+    }
+}
+
+impl ModelStream<TextMessage> {
+    /// Passes each piece of text to `on_delta` as it comes in, and returns
+    /// the whole output at the end.
+    pub async fn finish(
+        mut self,
+        mut on_delta: impl FnMut(&str),
+    ) -> anyhow::Result<ModelOutput<TextMessage>> {
+        let mut has_deltas = false;
+        while let Some(event) = self.next().await {
+            match event? {
+                StreamEvent::TextDelta(delta) => {
+                    has_deltas = true;
+                    on_delta(&delta);
+                }
+                StreamEvent::Completed(output) => {
+                    if !has_deltas {
+                        // The provider doesn't stream
+                        for message in &output.items {
+                            on_delta(&message.parts.join("\n\n"));
+                        }
+                    }
+                    return Ok(output);
+                }
+            }
+        }
+        anyhow::bail!("The model output ended before it was completed")
+    }
 }
 
+impl<O: OutputModes> Stream for ModelStream<O> {
+    type Item = anyhow::Result<StreamEvent<O>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.as_mut().poll_next(cx)
+    }
+}
+
+#[derive(Clone)]
 pub struct ModelInput<I: InputModes> {
     pub instructions: String,
     pub log: Vec<I>,
+    /// Tools the model may call
+    pub tools: Vec<ToolDefinition>,
+    /// Tools called since the last message of the log, with their results
+    pub tool_results: Vec<ToolResult>,
+    /// Asks for an answer in JSON instead of text
+    pub json_format: Option<JsonFormat>,
 }
 
 pub struct ModelOutput<O: OutputModes> {
     pub items: Vec<O>,
+    /// Tools the model wants to be called before it continues
+    pub tool_calls: Vec<ToolCall>,
+    /// How the generation ended
+    pub outcome: Outcome,
+    pub usage: Option<TokenUsage>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The model finished its answer, or stopped for tools to be called
+    Completed,
+    /// The model declined to answer
+    Refused {
+        /// As explained by the model, may be empty
+        reason: String,
+    },
+    /// The answer was cut off
+    Incomplete(IncompleteReason),
+    /// The provider could not generate an answer
+    Failed { error: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IncompleteReason {
+    /// The maximum number of output tokens was reached
+    MaxTokens,
+    /// The provider's content filter stopped the answer
+    ContentFilter,
+    /// A reason this application doesn't know about
+    Other(String),
+}
+
+impl std::fmt::Display for IncompleteReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MaxTokens => f.write_str("the maximum number of output tokens was reached"),
+            Self::ContentFilter => f.write_str("the content filter stopped it"),
+            Self::Other(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TokenUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// Part of the input tokens that were read from the provider's cache
+    pub cached_input_tokens: u64,
+}
+
+impl std::ops::AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cached_input_tokens += other.cached_input_tokens;
+    }
 }
 
 pub trait InputModes {}
@@ -51,6 +219,7 @@ pub trait OutputModes {}
 pub trait TextMode {}
 pub trait ImageMode {}
 
+#[derive(Clone)]
 pub struct TextMessage {
     pub author: Author,
     pub parts: Vec<String>,
@@ -70,6 +239,14 @@ pub enum TextImage {
     Base64Image(String),
     ImageUrl(String),
 }
+impl From<TextMessage> for TextImageMessage {
+    fn from(message: TextMessage) -> Self {
+        Self {
+            author: message.author,
+            parts: message.parts.into_iter().map(TextImage::Text).collect(),
+        }
+    }
+}
 impl InputModes for TextImageMessage {}
 impl OutputModes for TextImageMessage {}
 impl TextMode for TextImageMessage {}
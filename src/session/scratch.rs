@@ -0,0 +1,27 @@
+/// Text dictated during the session that isn't meant for a file, e.g. notes
+/// or a commit message to paste somewhere later.
+#[derive(Default)]
+pub struct ScratchBuffer {
+    text: String,
+}
+
+impl ScratchBuffer {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn append(&mut self, text: &str) {
+        self.text.push_str(text);
+    }
+
+    #[must_use]
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Returns the text and empties the buffer
+    pub fn take(&mut self) -> String {
+        std::mem::take(&mut self.text)
+    }
+}
@@ -0,0 +1,634 @@
+//! Answers in JSON, deserialized into Rust types.
+//!
+//! The JSON schema of a type is derived from its [`Deserialize`]
+//! implementation: deserializing it from a tracer, which hands out
+//! placeholder values, reveals the fields and their types.
+
+use std::fmt::Display;
+
+use serde::Deserialize;
+use serde::de::value::StrDeserializer;
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, Deserializer, EnumAccess, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use serde_json::{Map, Value, json};
+
+use super::{
+    AIModel, Author, CancellationToken, InputModes, ModelInput, ModelOutput, Outcome, TextMessage,
+};
+
+/// Asks the model to answer in JSON matching a schema
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonFormat {
+    /// Identifies the format, e.g. "Classification"
+    pub name: String,
+    pub schema: Value,
+}
+
+impl JsonFormat {
+    /// The format of `T`. Supported are structs, sequences, options,
+    /// primitives and enums with unit variants only, which is what strict
+    /// structured outputs of OpenAI support as well. Recursive types are
+    /// not.
+    pub fn of<'de, T: Deserialize<'de>>() -> anyhow::Result<Self> {
+        let type_name = std::any::type_name::<T>();
+        let name = type_name
+            .split('<')
+            .next()
+            .and_then(|path| path.rsplit("::").next())
+            .unwrap_or(type_name)
+            .to_owned();
+        let mut schema = Value::Null;
+        T::deserialize(Tracer {
+            schema: &mut schema,
+        })
+        .map_err(|err| anyhow::anyhow!("Could not derive the JSON schema of {type_name}: {err}"))?;
+        Ok(Self { name, schema })
+    }
+}
+
+/// The answer of a model asked for JSON
+pub struct StructuredOutput<T> {
+    /// `None` unless the outcome of `output` is [`Outcome::Completed`]
+    pub value: Option<T>,
+    /// The output the value was read from. Its usage covers a retry.
+    pub output: ModelOutput<TextMessage>,
+}
+
+/// Sends `input` asking for JSON in the shape of `T`. If the answer doesn't
+/// match, the model is told what's wrong and asked once more.
+pub async fn send_structured<T, I, M>(
+    model: &M,
+    mut input: ModelInput<I>,
+    cancellation: &CancellationToken,
+) -> anyhow::Result<StructuredOutput<T>>
+where
+    T: DeserializeOwned,
+    I: InputModes + Clone + From<TextMessage>,
+    M: AIModel<I, TextMessage>,
+{
+    input.json_format = Some(JsonFormat::of::<T>()?);
+
+    let output = model.send_cancellable(input.clone(), cancellation).await?;
+    if output.outcome != Outcome::Completed {
+        return Ok(StructuredOutput {
+            value: None,
+            output,
+        });
+    }
+    let answer = answer_text(&output);
+    let error = match serde_json::from_str(&answer) {
+        Ok(value) => {
+            return Ok(StructuredOutput {
+                value: Some(value),
+                output,
+            });
+        }
+        Err(err) => err,
+    };
+
+    input.log.push(
+        TextMessage {
+            author: Author::Assistant,
+            parts: vec![answer],
+        }
+        .into(),
+    );
+    input.log.push(
+        TextMessage {
+            author: Author::User,
+            parts: vec![format!(
+                "Your answer doesn't match the JSON schema: {error}. Answer again, \
+                with JSON matching the schema only."
+            )],
+        }
+        .into(),
+    );
+    let first_usage = output.usage;
+    let mut output = model.send_cancellable(input, cancellation).await?;
+    if let (Some(usage), Some(first_usage)) = (&mut output.usage, first_usage) {
+        *usage += first_usage;
+    }
+    if output.outcome != Outcome::Completed {
+        return Ok(StructuredOutput {
+            value: None,
+            output,
+        });
+    }
+    let answer = answer_text(&output);
+    let value = serde_json::from_str(&answer).map_err(|err| {
+        anyhow::anyhow!("The model answered with invalid JSON twice, {err}: {answer}")
+    })?;
+    Ok(StructuredOutput {
+        value: Some(value),
+        output,
+    })
+}
+
+fn answer_text(output: &ModelOutput<TextMessage>) -> String {
+    output
+        .items
+        .last()
+        .map(|message| message.parts.join(""))
+        .unwrap_or_default()
+}
+
+#[derive(Debug)]
+struct TraceError(String);
+
+impl Display for TraceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+impl de::Error for TraceError {
+    fn custom<T: Display>(msg: T) -> Self {
+        Self(msg.to_string())
+    }
+}
+
+fn unsupported(what: &str) -> TraceError {
+    TraceError(format!("{what} are not supported"))
+}
+
+/// Writes the schema of the type deserialized from it into `schema`
+struct Tracer<'s> {
+    schema: &'s mut Value,
+}
+
+impl Tracer<'_> {
+    fn set(self, schema: Value) {
+        *self.schema = schema;
+    }
+}
+
+impl<'de> Deserializer<'de> for Tracer<'_> {
+    type Error = TraceError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, TraceError> {
+        // Also asked for by untagged and internally tagged enums, which
+        // buffer the input to try their variants
+        Err(unsupported(
+            "Self-describing types like serde_json::Value, untagged and internally tagged enums",
+        ))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.set(json!({"type": "boolean"}));
+        visitor.visit_bool(false)
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.set(json!({"type": "integer"}));
+        visitor.visit_i64(0)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.set(json!({"type": "integer"}));
+        visitor.visit_u64(0)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_f64(visitor)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.set(json!({"type": "number"}));
+        visitor.visit_f64(0.0)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.set(json!({"type": "string"}));
+        visitor.visit_char(' ')
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.set(json!({"type": "string"}));
+        visitor.visit_str("")
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, TraceError> {
+        Err(unsupported("Byte arrays"))
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, TraceError> {
+        Err(unsupported("Byte arrays"))
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        let mut inner = Value::Null;
+        let value = visitor.visit_some(Tracer { schema: &mut inner })?;
+        self.set(nullable(inner));
+        Ok(value)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.set(json!({"type": "null"}));
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        let mut items = Value::Null;
+        let value = visitor.visit_seq(OneElement {
+            schema: &mut items,
+            is_done: false,
+        })?;
+        self.set(json!({"type": "array", "items": items}));
+        Ok(value)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        _visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        Err(unsupported("Tuples"))
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        _visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        Err(unsupported("Tuple structs"))
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, TraceError> {
+        // Also asked for by structs with flattened fields
+        Err(unsupported(
+            "Maps, which have no fixed keys, and flattened fields",
+        ))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        let mut properties = Map::new();
+        let value = visitor.visit_map(StructFields {
+            fields,
+            next: 0,
+            properties: &mut properties,
+        })?;
+        // Strict structured outputs require all fields, optional ones are
+        // nullable instead
+        self.set(json!({
+            "type": "object",
+            "properties": properties,
+            "required": fields,
+            "additionalProperties": false,
+        }));
+        Ok(value)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        let Some(first) = variants.first() else {
+            return Err(unsupported("Enums without variants"));
+        };
+        self.set(json!({"type": "string", "enum": variants}));
+        visitor.visit_enum(UnitVariant(first))
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, TraceError> {
+        Err(unsupported("Identifiers outside of structs and enums"))
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        visitor.visit_unit()
+    }
+}
+
+/// Makes `null` an allowed value, too
+fn nullable(mut schema: Value) -> Value {
+    let Some(Value::String(type_)) = schema.get("type").cloned() else {
+        return json!({"anyOf": [schema, {"type": "null"}]});
+    };
+    schema["type"] = json!([type_, "null"]);
+    if let Some(Value::Array(variants)) = schema.get_mut("enum") {
+        variants.push(Value::Null);
+    }
+    schema
+}
+
+/// A sequence with a single element, to trace the schema of the items
+struct OneElement<'s> {
+    schema: &'s mut Value,
+    is_done: bool,
+}
+
+impl<'de> SeqAccess<'de> for OneElement<'_> {
+    type Error = TraceError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, TraceError> {
+        if self.is_done {
+            return Ok(None);
+        }
+        self.is_done = true;
+        seed.deserialize(Tracer {
+            schema: &mut *self.schema,
+        })
+        .map(Some)
+    }
+}
+
+/// All fields of a struct, each traced into `properties`
+struct StructFields<'s> {
+    fields: &'static [&'static str],
+    next: usize,
+    properties: &'s mut Map<String, Value>,
+}
+
+impl<'de> MapAccess<'de> for StructFields<'_> {
+    type Error = TraceError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, TraceError> {
+        let Some(field) = self.fields.get(self.next) else {
+            return Ok(None);
+        };
+        seed.deserialize(StrDeserializer::<TraceError>::new(field))
+            .map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, TraceError> {
+        let field = self.fields[self.next];
+        self.next += 1;
+        let mut schema = Value::Null;
+        let value = seed.deserialize(Tracer {
+            schema: &mut schema,
+        })?;
+        self.properties.insert(field.to_owned(), schema);
+        Ok(value)
+    }
+}
+
+/// Chooses the given variant of an enum, which has to be a unit variant
+struct UnitVariant(&'static str);
+
+impl<'de> EnumAccess<'de> for UnitVariant {
+    type Error = TraceError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self), TraceError> {
+        let variant = seed.deserialize(StrDeserializer::<TraceError>::new(self.0))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> VariantAccess<'de> for UnitVariant {
+    type Error = TraceError;
+
+    fn unit_variant(self) -> Result<(), TraceError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        _seed: T,
+    ) -> Result<T::Value, TraceError> {
+        Err(unsupported("Enum variants with data"))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        _visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        Err(unsupported("Enum variants with data"))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        Err(unsupported("Enum variants with data"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    enum Kind {
+        Question,
+        FollowUp,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    struct Answer {
+        kind: Kind,
+        #[serde(rename = "text")]
+        answer_text: String,
+        confidence: f32,
+        sources: Vec<Source>,
+        follow_up: Option<Kind>,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    struct Source {
+        line: u32,
+        file: Option<String>,
+    }
+
+    #[test]
+    fn derives_the_schema_from_deserialize() {
+        let format = JsonFormat::of::<Answer>().unwrap();
+
+        assert_eq!(format.name, "Answer");
+        assert_eq!(
+            format.schema,
+            json!({
+                "type": "object",
+                "properties": {
+                    "kind": {"type": "string", "enum": ["question", "follow_up"]},
+                    "text": {"type": "string"},
+                    "confidence": {"type": "number"},
+                    "sources": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "line": {"type": "integer"},
+                                "file": {"type": ["string", "null"]},
+                            },
+                            "required": ["line", "file"],
+                            "additionalProperties": false,
+                        },
+                    },
+                    "follow_up": {
+                        "type": ["string", "null"],
+                        "enum": ["question", "follow_up", null],
+                    },
+                },
+                "required": ["kind", "text", "confidence", "sources", "follow_up"],
+                "additionalProperties": false,
+            })
+        );
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    struct Defaults {
+        #[serde(default)]
+        count: u32,
+        #[serde(default)]
+        file: Option<String>,
+    }
+
+    #[test]
+    fn requires_fields_with_defaults() {
+        let format = JsonFormat::of::<Defaults>().unwrap();
+
+        // Strict structured outputs have no optional fields
+        assert_eq!(
+            format.schema,
+            json!({
+                "type": "object",
+                "properties": {
+                    "count": {"type": "integer"},
+                    "file": {"type": ["string", "null"]},
+                },
+                "required": ["count", "file"],
+                "additionalProperties": false,
+            })
+        );
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    struct Flattened {
+        text: String,
+        #[serde(flatten)]
+        source: Source,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Untagged {
+        Line(u32),
+        Text(String),
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    #[serde(tag = "kind")]
+    enum InternallyTagged {
+        Question { text: String },
+        FollowUp,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    enum WithData {
+        Question(String),
+    }
+
+    #[test]
+    fn rejects_types_without_a_fixed_schema() {
+        let self_describing = "Self-describing types like serde_json::Value, untagged and internally tagged enums \
+            are not supported";
+        let cases = [
+            (
+                JsonFormat::of::<std::collections::HashMap<String, u32>>(),
+                "Maps, which have no fixed keys, and flattened fields are not supported",
+            ),
+            (
+                JsonFormat::of::<Flattened>(),
+                "Maps, which have no fixed keys, and flattened fields are not supported",
+            ),
+            (JsonFormat::of::<Value>(), self_describing),
+            (JsonFormat::of::<Untagged>(), self_describing),
+            (JsonFormat::of::<InternallyTagged>(), self_describing),
+            (
+                JsonFormat::of::<WithData>(),
+                "Enum variants with data are not supported",
+            ),
+            (
+                JsonFormat::of::<(u32, String)>(),
+                "Tuples are not supported",
+            ),
+        ];
+
+        for (format, expected) in cases {
+            let err = format.unwrap_err().to_string();
+            assert!(
+                err.starts_with("Could not derive the JSON schema of ") && err.ends_with(expected),
+                "{err}"
+            );
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use std::future::Future;
+
+use futures_util::future::BoxFuture;
+
+use super::{
+    AIModel, CancellationToken, Cancelled, InputModes, ModelInput, ModelOutput, OutputModes,
+    TokenUsage,
+};
+
+/// How often the model may call tools before giving its answer, so a model
+/// calling tools over and over doesn't run forever
+const MAX_TOOL_ROUNDS: usize = 8;
+
+/// What the model gets to know about a tool
+#[derive(Clone, Debug, PartialEq)]
+pub struct ToolDefinition {
+    pub name: String,
+    /// Tells the model when to use the tool
+    pub description: String,
+    /// JSON schema of the arguments
+    pub parameters: serde_json::Value,
+}
+
+/// A call of a tool requested by the model
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ToolCall {
+    /// Identifies the call, for its result to be matched up with it
+    pub call_id: String,
+    pub name: String,
+    /// JSON encoded, as generated by the model, so not necessarily valid
+    pub arguments: String,
+}
+
+/// A tool call and what it returned
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ToolResult {
+    pub call: ToolCall,
+    pub output: String,
+}
+
+type ToolHandler =
+    Box<dyn Fn(serde_json::Value) -> BoxFuture<'static, anyhow::Result<String>> + Send + Sync>;
+
+/// A function the model can call
+pub struct Tool {
+    pub definition: ToolDefinition,
+    handler: ToolHandler,
+}
+
+impl Tool {
+    /// `handler` receives the arguments, which the model should have
+    /// generated according to `parameters`, and returns the text given back
+    /// to the model.
+    pub fn new<F, Fut>(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        parameters: serde_json::Value,
+        handler: F,
+    ) -> Self
+    where
+        F: Fn(serde_json::Value) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<String>> + Send + 'static,
+    {
+        Self {
+            definition: ToolDefinition {
+                name: name.into(),
+                description: description.into(),
+                parameters,
+            },
+            handler: Box::new(move |arguments| Box::pin(handler(arguments))),
+        }
+    }
+}
+
+/// The tools offered to the model
+#[derive(Default)]
+pub struct Toolbox {
+    tools: Vec<Tool>,
+}
+
+impl Toolbox {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn with(mut self, tool: Tool) -> Self {
+        self.tools.push(tool);
+        self
+    }
+
+    #[must_use]
+    pub fn definitions(&self) -> Vec<ToolDefinition> {
+        self.tools
+            .iter()
+            .map(|tool| tool.definition.clone())
+            .collect()
+    }
+
+    /// Runs the tool the model asked for. Failures are reported to the model
+    /// in the output instead of failing, so it can try differently.
+    pub async fn call(&self, call: ToolCall) -> ToolResult {
+        let output = match self.run(&call).await {
+            Ok(output) => output,
+            Err(err) => format!("Error: {err:#}"),
+        };
+        ToolResult { call, output }
+    }
+
+    async fn run(&self, call: &ToolCall) -> anyhow::Result<String> {
+        let Some(tool) = self
+            .tools
+            .iter()
+            .find(|tool| tool.definition.name == call.name)
+        else {
+            anyhow::bail!("There is no tool named '{}'", call.name);
+        };
+        let arguments = if call.arguments.trim().is_empty() {
+            serde_json::Value::Object(serde_json::Map::new())
+        } else {
+            serde_json::from_str(&call.arguments)
+                .map_err(|err| anyhow::anyhow!("The arguments are not valid JSON: {err}"))?
+        };
+        (tool.handler)(arguments).await
+    }
+}
+
+/// Sends `input` with the tools of `toolbox`, and runs the tools the model
+/// calls until it gives an answer without tool calls. The usage of the
+/// returned output covers all rounds. Fails with [`Cancelled`] once
+/// `cancellation` is cancelled, without running further tools.
+pub async fn send_with_tools<I, O, M>(
+    model: &M,
+    mut input: ModelInput<I>,
+    toolbox: &Toolbox,
+    cancellation: &CancellationToken,
+) -> anyhow::Result<ModelOutput<O>>
+where
+    I: InputModes + Clone,
+    O: OutputModes,
+    M: AIModel<I, O>,
+{
+    input.tools = toolbox.definitions();
+    let mut usage: Option<TokenUsage> = None;
+    for _ in 0..MAX_TOOL_ROUNDS {
+        let mut output = model.send_cancellable(input.clone(), cancellation).await?;
+        if let Some(round_usage) = output.usage {
+            *usage.get_or_insert_default() += round_usage;
+        }
+        if output.tool_calls.is_empty() {
+            output.usage = usage;
+            return Ok(output);
+        }
+        for call in std::mem::take(&mut output.tool_calls) {
+            if cancellation.is_cancelled() {
+                return Err(Cancelled.into());
+            }
+            input.tool_results.push(toolbox.call(call).await);
+        }
+    }
+    anyhow::bail!("The model still called tools after {MAX_TOOL_ROUNDS} rounds")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn toolbox() -> Toolbox {
+        Toolbox::new().with(Tool::new(
+            "read_file",
+            "Reads a file",
+            serde_json::json!({"type": "object"}),
+            |arguments| async move { Ok(format!("Called with {arguments}")) },
+        ))
+    }
+
+    #[tokio::test]
+    async fn reports_failed_calls_to_the_model() {
+        let cases = [
+            (
+                "read_file",
+                r#"{"path": "a.rs"}"#,
+                r#"Called with {"path":"a.rs"}"#,
+            ),
+            ("read_file", "", "Called with {}"),
+            ("read_file", "  ", "Called with {}"),
+            (
+                "write_file",
+                "{}",
+                "Error: There is no tool named 'write_file'",
+            ),
+            (
+                "read_file",
+                r#"{"path": "#,
+                "Error: The arguments are not valid JSON: EOF while parsing a value at line 1 column 9",
+            ),
+        ];
+
+        let toolbox = toolbox();
+        for (name, arguments, expected) in cases {
+            let call = ToolCall {
+                call_id: "call_1".to_owned(),
+                name: name.to_owned(),
+                arguments: arguments.to_owned(),
+            };
+            let result = toolbox.call(call.clone()).await;
+            assert_eq!(result.call, call);
+            assert_eq!(result.output, expected, "{name}({arguments})");
+        }
+    }
+}
@@ -1,2 +1,3 @@
 pub mod audio;
 pub mod input;
+pub mod wake_word;
@@ -1,12 +1,14 @@
 pub mod format;
 mod recorder;
+pub mod silence;
+pub mod wav;
 
 use std::sync::{
     Arc,
     atomic::{AtomicBool, Ordering},
 };
 
-pub use recorder::AudioRecorder;
+pub use recorder::{AudioRecorder, Capture, CaptureHandover};
 
 #[derive(Clone)]
 pub struct StopTrigger {
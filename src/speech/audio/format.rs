@@ -9,6 +9,19 @@ pub enum SoundSpec {
     },
 }
 
+impl SoundSpec {
+    #[must_use]
+    pub fn bytes_per_second(&self) -> u32 {
+        match self {
+            Self::PCM {
+                format,
+                sample_rate_hz,
+                num_channels,
+            } => format.bytes_per_sample() * sample_rate_hz * num_channels,
+        }
+    }
+}
+
 impl Display for SoundSpec {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str("Audio format [")?;
@@ -32,6 +45,15 @@ pub enum PCMFormat {
     S16LE,
 }
 
+impl PCMFormat {
+    #[must_use]
+    pub fn bytes_per_sample(&self) -> u32 {
+        match self {
+            PCMFormat::S16LE => 2,
+        }
+    }
+}
+
 impl Display for PCMFormat {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let fmt_str = match self {
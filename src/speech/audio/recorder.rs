@@ -1,7 +1,9 @@
 mod file;
 mod pipewire;
 
-use std::{path::Path, sync::mpsc::Receiver};
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
 
 use file::FileAudioRecorder;
 use pipewire::PipewireAudioRecorder;
@@ -12,22 +14,73 @@ use super::{StopTrigger, format::SoundSpec};
 
 type ListenResult = anyhow::Result<(Receiver<Vec<u8>>, StopTrigger, Option<SoundSpec>)>;
 
-pub struct AudioRecorder(AudioRecorderImpl);
+pub struct AudioRecorder {
+    recorder: AudioRecorderImpl,
+    continued: CaptureHandover,
+}
 
 impl AudioRecorder {
     pub fn new(logger: Logger, from_file: Option<&Path>) -> anyhow::Result<Self> {
-        match from_file {
-            Some(path) => Ok(Self(AudioRecorderImpl::SampleFile(FileAudioRecorder(
-                path.to_path_buf(),
-            )))),
-            None => Ok(Self(AudioRecorderImpl::Pipewire(
-                PipewireAudioRecorder::new(logger),
-            ))),
-        }
+        let recorder = match from_file {
+            Some(path) => AudioRecorderImpl::SampleFile(FileAudioRecorder(path.to_path_buf())),
+            None => AudioRecorderImpl::Pipewire(PipewireAudioRecorder::new(logger)),
+        };
+        Ok(Self {
+            recorder,
+            continued: CaptureHandover::default(),
+        })
     }
 
+    /// Starts recording, or continues a capture that has been handed over in
+    /// the same format.
     pub fn listen(&mut self, request_format: Option<SoundSpec>) -> ListenResult {
-        self.0.listen(request_format)
+        if let Some(capture) = self.continued.take() {
+            if capture.request_format == request_format {
+                return Ok((capture.receiver, capture.stop, capture.actual_format));
+            }
+            capture.stop.stop();
+        }
+        self.recorder.listen(request_format)
+    }
+
+    /// Whether the next [`AudioRecorder::listen`] continues a capture that
+    /// has been handed over
+    #[must_use]
+    pub fn is_continuing(&self) -> bool {
+        self.continued.0.lock().unwrap().is_some()
+    }
+
+    /// Where a capture started by [`AudioRecorder::listen`] can be handed
+    /// back, for the next call to continue it
+    #[must_use]
+    pub fn handover(&self) -> CaptureHandover {
+        self.continued.clone()
+    }
+}
+
+/// A recording in progress
+pub struct Capture {
+    pub receiver: Receiver<Vec<u8>>,
+    pub stop: StopTrigger,
+    /// The format the capture was requested in
+    pub request_format: Option<SoundSpec>,
+    pub actual_format: Option<SoundSpec>,
+}
+
+/// Shared slot for a [`Capture`] to be continued by an [`AudioRecorder`]
+#[derive(Clone, Default)]
+pub struct CaptureHandover(Arc<Mutex<Option<Capture>>>);
+
+impl CaptureHandover {
+    /// Replaces a capture handed over before, which is stopped then.
+    pub fn put(&self, capture: Capture) {
+        if let Some(previous) = self.0.lock().unwrap().replace(capture) {
+            previous.stop.stop();
+        }
+    }
+
+    fn take(&self) -> Option<Capture> {
+        self.0.lock().unwrap().take()
     }
 }
 
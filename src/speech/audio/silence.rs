@@ -0,0 +1,302 @@
+//! Local speech detection based on the signal energy, used to avoid sending
+//! silence to a transcription service.
+
+use std::collections::VecDeque;
+
+use super::format::SoundSpec;
+
+/// A level that works for a close microphone in a quiet room
+pub const DEFAULT_THRESHOLD_DBFS: f64 = -45.0;
+
+/// Silence before the first speech after which the threshold is probably
+/// too high for the microphone
+const NO_SPEECH_PATIENCE_MS: usize = 5000;
+
+/// Classifies chunks of 16 bit PCM audio as speech or silence, by comparing
+/// their RMS level against a threshold.
+#[derive(Clone, Copy)]
+pub struct EnergyDetector {
+    threshold_dbfs: f64,
+}
+
+impl EnergyDetector {
+    /// `threshold_dbfs` is the level in decibels relative to full scale above
+    /// which audio is considered speech, e.g. -45.0
+    #[must_use]
+    pub fn new(threshold_dbfs: f64) -> Self {
+        Self { threshold_dbfs }
+    }
+
+    #[must_use]
+    pub fn threshold_dbfs(&self) -> f64 {
+        self.threshold_dbfs
+    }
+
+    #[must_use]
+    pub fn is_speech(&self, chunk: &[u8]) -> bool {
+        level_dbfs(chunk) >= self.threshold_dbfs
+    }
+}
+
+/// The RMS level of little endian 16 bit samples in dBFS.
+#[must_use]
+pub fn level_dbfs(chunk: &[u8]) -> f64 {
+    let samples = chunk.chunks_exact(2);
+    if samples.len() == 0 {
+        return f64::NEG_INFINITY;
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let num_samples = samples.len() as f64;
+    let sum_of_squares: f64 = samples
+        .map(|s| f64::from(i16::from_le_bytes([s[0], s[1]])))
+        .map(|s| s * s)
+        .sum();
+    let rms = (sum_of_squares / num_samples).sqrt();
+    20.0 * (rms / f64::from(i16::MAX)).log10()
+}
+
+enum TrimState {
+    /// Silence is held back, apart from the pre-roll
+    Waiting,
+    Speaking,
+    /// Silence after speech is still passed on, until the hangover is used up
+    Hangover {
+        remaining_bytes: usize,
+    },
+    /// Everything is passed on, see [`SilenceTrimmer::pass_through`]
+    PassingThrough,
+}
+
+/// Holds back leading silence and drops trailing silence, so only the speech
+/// with some padding around it is passed on.
+///
+/// The most recent silence is kept as pre-roll and passed on right before the
+/// speech, so the beginning of the first word isn't cut off. After speech,
+/// silence is passed on for the duration of the hangover. The hangover should
+/// be longer than the silence a server-side voice activity detection waits
+/// for before ending the turn.
+pub struct SilenceTrimmer {
+    detector: EnergyDetector,
+    pre_roll_bytes: usize,
+    hangover_bytes: usize,
+    pre_roll: VecDeque<Vec<u8>>,
+    state: TrimState,
+    trimmed_bytes: usize,
+    /// Whether any chunk has been speech so far
+    has_heard_speech: bool,
+    /// Silence held back before the first speech
+    waited_bytes: usize,
+    patience_bytes: usize,
+}
+
+impl SilenceTrimmer {
+    #[must_use]
+    pub fn new(
+        detector: EnergyDetector,
+        format: &SoundSpec,
+        pre_roll_ms: u32,
+        hangover_ms: u32,
+    ) -> Self {
+        let bytes_per_ms = format.bytes_per_second() as usize / 1000;
+        Self {
+            detector,
+            pre_roll_bytes: pre_roll_ms as usize * bytes_per_ms,
+            hangover_bytes: hangover_ms as usize * bytes_per_ms,
+            pre_roll: VecDeque::new(),
+            state: TrimState::Waiting,
+            trimmed_bytes: 0,
+            has_heard_speech: false,
+            waited_bytes: 0,
+            patience_bytes: NO_SPEECH_PATIENCE_MS * bytes_per_ms,
+        }
+    }
+
+    /// Takes the next chunk of audio and returns the chunks that should be
+    /// passed on.
+    pub fn push(&mut self, chunk: Vec<u8>) -> Vec<Vec<u8>> {
+        let is_speech = self.detector.is_speech(&chunk);
+        self.has_heard_speech |= is_speech;
+        if !self.has_heard_speech {
+            self.waited_bytes += chunk.len();
+        }
+
+        match self.state {
+            TrimState::PassingThrough => vec![chunk],
+            TrimState::Waiting if is_speech => {
+                self.state = TrimState::Speaking;
+                let mut chunks: Vec<Vec<u8>> = self.pre_roll.drain(..).collect();
+                chunks.push(chunk);
+                chunks
+            }
+            TrimState::Speaking | TrimState::Hangover { .. } if is_speech => {
+                self.state = TrimState::Speaking;
+                vec![chunk]
+            }
+            TrimState::Waiting | TrimState::Hangover { remaining_bytes: 0 } => {
+                self.state = TrimState::Waiting;
+                self.pre_roll.push_back(chunk);
+                self.trim_pre_roll();
+                Vec::new()
+            }
+            TrimState::Speaking => {
+                self.state = TrimState::Hangover {
+                    remaining_bytes: self.hangover_bytes.saturating_sub(chunk.len()),
+                };
+                vec![chunk]
+            }
+            TrimState::Hangover { remaining_bytes } => {
+                self.state = TrimState::Hangover {
+                    remaining_bytes: remaining_bytes.saturating_sub(chunk.len()),
+                };
+                vec![chunk]
+            }
+        }
+    }
+
+    /// Whether nothing but silence has come in for several seconds, which
+    /// suggests that the threshold is too high for the microphone
+    #[must_use]
+    pub fn has_waited_too_long(&self) -> bool {
+        !matches!(self.state, TrimState::PassingThrough)
+            && !self.has_heard_speech
+            && self.waited_bytes >= self.patience_bytes
+    }
+
+    /// Stops trimming, and returns the held back pre-roll. All chunks pushed
+    /// afterwards are passed on.
+    pub fn pass_through(&mut self) -> Vec<Vec<u8>> {
+        self.state = TrimState::PassingThrough;
+        self.pre_roll.drain(..).collect()
+    }
+
+    /// Number of bytes that have been held back for good so far
+    #[must_use]
+    pub fn trimmed_bytes(&self) -> usize {
+        self.trimmed_bytes + self.pre_roll.iter().map(Vec::len).sum::<usize>()
+    }
+
+    fn trim_pre_roll(&mut self) {
+        let mut len: usize = self.pre_roll.iter().map(Vec::len).sum();
+        while let Some(front) = self.pre_roll.front() {
+            if len - front.len() < self.pre_roll_bytes {
+                break;
+            }
+            len -= front.len();
+            self.trimmed_bytes += front.len();
+            self.pre_roll.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::speech::audio::format::PCMFormat;
+
+    /// 1 byte per millisecond, so chunk lengths are durations
+    fn format() -> SoundSpec {
+        SoundSpec::PCM {
+            format: PCMFormat::S16LE,
+            sample_rate_hz: 500,
+            num_channels: 1,
+        }
+    }
+
+    fn silence(ms: usize) -> Vec<u8> {
+        vec![0; ms]
+    }
+
+    fn speech(ms: usize) -> Vec<u8> {
+        i16::MAX
+            .to_le_bytes()
+            .into_iter()
+            .cycle()
+            .take(ms)
+            .collect()
+    }
+
+    fn trimmer() -> SilenceTrimmer {
+        SilenceTrimmer::new(
+            EnergyDetector::new(DEFAULT_THRESHOLD_DBFS),
+            &format(),
+            300,
+            200,
+        )
+    }
+
+    fn lengths(chunks: &[Vec<u8>]) -> Vec<usize> {
+        chunks.iter().map(Vec::len).collect()
+    }
+
+    #[test]
+    fn passes_on_the_pre_roll_before_speech() {
+        let mut trimmer = trimmer();
+        for _ in 0..5 {
+            assert!(trimmer.push(silence(100)).is_empty());
+        }
+
+        let chunks = trimmer.push(speech(100));
+
+        assert_eq!(lengths(&chunks), [100, 100, 100, 100]);
+        assert!(chunks[..3].iter().flatten().all(|b| *b == 0));
+        assert_eq!(trimmer.trimmed_bytes(), 200);
+    }
+
+    #[test]
+    fn passes_on_silence_until_the_hangover_expires() {
+        let cases = [
+            // Hangover of 200 ms, in chunks of 100 ms
+            (vec![false, false, false], vec![1, 1, 0]),
+            (vec![false, true, false, false, false], vec![1, 1, 1, 1, 0]),
+            (vec![false, false, true, false], vec![1, 1, 1, 1]),
+        ];
+
+        for (after_speech, expected) in cases {
+            let mut trimmer = trimmer();
+            trimmer.push(speech(100));
+            let passed_on: Vec<usize> = after_speech
+                .iter()
+                .map(|is_speech| {
+                    let chunk = if *is_speech {
+                        speech(100)
+                    } else {
+                        silence(100)
+                    };
+                    trimmer.push(chunk).len()
+                })
+                .collect();
+            assert_eq!(passed_on, expected, "after speech: {after_speech:?}");
+        }
+    }
+
+    #[test]
+    fn counts_the_trimmed_bytes() {
+        let mut trimmer = trimmer();
+        for chunk in [silence(250), silence(250), silence(250), speech(100)] {
+            trimmer.push(chunk);
+        }
+        // The first chunk was dropped, the others were passed on as pre-roll
+        assert_eq!(trimmer.trimmed_bytes(), 250);
+
+        for _ in 0..6 {
+            trimmer.push(silence(100));
+        }
+        // 200 ms of hangover were passed on, and 300 ms are held as pre-roll
+        assert_eq!(trimmer.trimmed_bytes(), 650);
+    }
+
+    #[test]
+    fn passes_everything_through_when_there_is_no_speech() {
+        let mut trimmer = trimmer();
+        for _ in 0..49 {
+            trimmer.push(silence(100));
+        }
+        assert!(!trimmer.has_waited_too_long());
+        trimmer.push(silence(100));
+        assert!(trimmer.has_waited_too_long());
+
+        assert_eq!(lengths(&trimmer.pass_through()), [100, 100, 100]);
+        assert!(!trimmer.has_waited_too_long());
+        assert_eq!(lengths(&trimmer.push(silence(100))), [100]);
+    }
+}
@@ -0,0 +1,45 @@
+//! Writing recorded audio as WAV files, the format most local speech
+//! recognition programs expect.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::Context;
+
+use super::format::SoundSpec;
+
+const HEADER_LEN: u32 = 44;
+
+/// Writes `data` as a WAV file with the canonical 44 byte header.
+pub fn write_wav(path: &Path, format: &SoundSpec, data: &[u8]) -> anyhow::Result<()> {
+    let SoundSpec::PCM {
+        format: pcm_format,
+        sample_rate_hz,
+        num_channels,
+    } = format;
+    let data_len = u32::try_from(data.len()).context("Audio is too long for a WAV file")?;
+    let bits_per_sample = pcm_format.bytes_per_sample() * 8;
+    let block_align = pcm_format.bytes_per_sample() * num_channels;
+
+    let mut wav = Vec::with_capacity(HEADER_LEN as usize + data.len());
+    wav.extend(b"RIFF");
+    wav.extend((HEADER_LEN - 8 + data_len).to_le_bytes());
+    wav.extend(b"WAVE");
+    wav.extend(b"fmt ");
+    wav.extend(16u32.to_le_bytes());
+    // Uncompressed PCM
+    wav.extend(1u16.to_le_bytes());
+    #[allow(clippy::cast_possible_truncation)]
+    wav.extend((*num_channels as u16).to_le_bytes());
+    wav.extend(sample_rate_hz.to_le_bytes());
+    wav.extend(format.bytes_per_second().to_le_bytes());
+    #[allow(clippy::cast_possible_truncation)]
+    wav.extend((block_align as u16).to_le_bytes());
+    #[allow(clippy::cast_possible_truncation)]
+    wav.extend((bits_per_sample as u16).to_le_bytes());
+    wav.extend(b"data");
+    wav.extend(data_len.to_le_bytes());
+    wav.extend(data);
+
+    fs::write(path, wav).context(format!("Failed to write {}", path.display()))
+}
@@ -3,13 +3,44 @@
 //! This module contains a [`SpeechListener`] struct which abstracts over the
 //! different possible implementations.
 
+mod barge_in;
+mod command;
+mod keyboard;
+mod language;
+mod normalization;
 mod openai;
+mod spelling;
+mod usage;
+mod vocabulary;
 
-use crate::config::Config;
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+
+use crate::config::{Config, InputMode, SpeechBackend};
+use crate::logger::Logger;
 
 use super::audio::AudioRecorder;
+use super::audio::silence::{self, EnergyDetector};
+
+#[cfg(feature = "mock-realtime")]
+pub use openai::mock_server;
+
+pub use barge_in::{BargeInMonitor, ListenerEvent};
+pub use language::{detect_language, language_name};
 
+use command::CommandSpeechListener;
+use keyboard::{KeyboardListener, input_switch_command};
+use normalization::SpokenCodeNormalizer;
 use openai::SpeechListener as OpenAISpeechListener;
+use spelling::{Segment, SpellingMode};
+
+/// Called when the user starts speaking, before the utterance is
+/// transcribed. Runs on the thread that noticed the speech, so it must not
+/// block.
+pub type SpeechStartedHook = Arc<dyn Fn() + Send + Sync>;
 
 #[derive(Clone)]
 pub struct RecognizedSpeech {
@@ -19,35 +50,369 @@ pub struct RecognizedSpeech {
 #[derive(Debug)]
 pub enum Transcription {
     Empty,
-    Some { text: String },
+    Some {
+        text: String,
+        /// How sure the transcription model is about each token, if the
+        /// backend reports it
+        confidence: Option<Confidence>,
+        /// ISO 639-1 code of the language spoken, if known
+        language: Option<String>,
+        /// When the utterance was spoken and how long transcribing it took,
+        /// if the backend can tell
+        timing: Option<UtteranceTiming>,
+    },
 }
 
-pub struct SpeechListener(SpeechListenerImpl);
+#[derive(Clone, Debug, Default)]
+pub struct UtteranceTiming {
+    /// The backend's id for the utterance, e.g. the conversation item
+    pub item_id: Option<String>,
+    /// Offset of the beginning of speech in the audio sent to the backend
+    pub start: Option<Duration>,
+    /// Offset of the end of speech in the audio sent to the backend
+    pub end: Option<Duration>,
+    /// From the end of speech being detected to the completed transcript
+    pub latency: Option<Duration>,
+}
 
-impl SpeechListener {
+impl UtteranceTiming {
+    #[must_use]
+    pub fn duration(&self) -> Option<Duration> {
+        Some(self.end?.saturating_sub(self.start?))
+    }
+}
+
+impl Display for UtteranceTiming {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let seconds = |d: Option<Duration>| {
+            d.map_or_else(|| "?".to_owned(), |d| format!("{:.2} s", d.as_secs_f64()))
+        };
+        write!(
+            f,
+            "speech from {} to {} ({}), transcribed after {}",
+            seconds(self.start),
+            seconds(self.end),
+            seconds(self.duration()),
+            seconds(self.latency)
+        )?;
+        if let Some(item_id) = &self.item_id {
+            write!(f, " [{item_id}]")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TokenConfidence {
+    pub token: String,
+    /// Probability between 0 and 1 that the token was transcribed correctly
+    pub probability: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Confidence {
+    pub tokens: Vec<TokenConfidence>,
+}
+
+impl Confidence {
+    /// The mean probability over all tokens, or `None` if there are no tokens.
+    #[must_use]
+    pub fn average(&self) -> Option<f64> {
+        if self.tokens.is_empty() {
+            return None;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let num_tokens = self.tokens.len() as f64;
+        Some(self.tokens.iter().map(|t| t.probability).sum::<f64>() / num_tokens)
+    }
+
+    /// The words containing at least one token with a probability below
+    /// `threshold`. Tokens starting with whitespace begin a new word.
     #[must_use]
-    pub fn new(config: &Config, audio_recorder: AudioRecorder) -> Self {
-        // If more speech listener backends are to be implemented, use the
-        // config to decide which one to use at runtime.
-        Self(SpeechListenerImpl::OpenAI(OpenAISpeechListener::new(
-            config,
-            audio_recorder,
-        )))
+    pub fn uncertain_words(&self, threshold: f64) -> Vec<String> {
+        let mut words: Vec<(String, bool)> = Vec::new();
+        for token in &self.tokens {
+            let is_uncertain = token.probability < threshold;
+            match words.last_mut() {
+                Some((word, uncertain)) if !token.token.starts_with(char::is_whitespace) => {
+                    word.push_str(&token.token);
+                    *uncertain |= is_uncertain;
+                }
+                _ => words.push((token.token.clone(), is_uncertain)),
+            }
+        }
+        words
+            .into_iter()
+            .filter(|(_, uncertain)| *uncertain)
+            .map(|(word, _)| word.trim_matches(|c: char| !c.is_alphanumeric()).to_owned())
+            .filter(|word| !word.is_empty())
+            .collect()
+    }
+}
+
+pub struct SpeechListener {
+    listener: SpeechListenerImpl,
+    /// The listener to switch to when the user asks for the other input mode,
+    /// `None` until voice input is first switched to
+    standby: Option<SpeechListenerImpl>,
+    /// Kept to set up voice input on the first switch to it, so the
+    /// microphone and the transcription backend aren't touched before
+    voice_config: Option<Box<Config>>,
+    speech_started: Option<SpeechStartedHook>,
+    normalizer: SpokenCodeNormalizer,
+    /// Off while dictating prose, where e.g. "equals" should stay a word
+    normalize_code: bool,
+    spelling: SpellingMode,
+    languages: Vec<String>,
+    /// Used for utterances too short to tell their language
+    last_language: Option<String>,
+    /// Detects the user talking while the assistant is busy, `None` if
+    /// barge-in is disabled
+    barge_in: Option<EnergyDetector>,
+    logger: Logger,
+}
+
+impl SpeechListener {
+    pub fn new(config: &Config, logger: Logger) -> anyhow::Result<Self> {
+        let keyboard =
+            SpeechListenerImpl::Keyboard(Box::new(KeyboardListener::new(config, logger)?));
+        let (listener, standby, voice_config) = match config.input_mode {
+            InputMode::Voice => (
+                SpeechListenerImpl::voice(config, logger)?,
+                Some(keyboard),
+                None,
+            ),
+            InputMode::Keyboard => (keyboard, None, Some(Box::new(config.clone()))),
+        };
+        let normalizer = match &config.spoken_code_rules {
+            Some(path) => SpokenCodeNormalizer::from_rules_file(path)?,
+            None => SpokenCodeNormalizer::new(),
+        };
+
+        Ok(Self {
+            listener,
+            standby,
+            voice_config,
+            speech_started: None,
+            normalizer,
+            normalize_code: true,
+            spelling: SpellingMode::new(),
+            languages: config.languages.clone(),
+            last_language: None,
+            barge_in: config.barge_in.then(|| {
+                EnergyDetector::new(
+                    config
+                        .silence_trimming
+                        .map_or(silence::DEFAULT_THRESHOLD_DBFS, |t| t.threshold_dbfs),
+                )
+            }),
+            logger,
+        })
     }
 
+    /// Listens to the next utterance. If the user asks to switch between voice
+    /// and keyboard input, the switch is made and the next utterance is
+    /// listened to in the new mode. While spelling, transcriptions are
+    /// collected until spelling mode is left.
     pub async fn listen_to_input(&mut self) -> anyhow::Result<Transcription> {
-        self.0.listen_to_input().await
+        loop {
+            let transcription = self.listener.listen_to_input().await?;
+
+            if let Transcription::Some { text, .. } = &transcription {
+                if let Some(mode) = input_switch_command(text) {
+                    if mode != self.listener.input_mode() {
+                        let next = match self.take_standby() {
+                            Ok(next) => next,
+                            Err(err) => {
+                                self.logger
+                                    .error(format!("Could not switch the input: {err:#}"));
+                                continue;
+                            }
+                        };
+                        self.standby = Some(std::mem::replace(&mut self.listener, next));
+                    }
+                    self.logger.info(match mode {
+                        InputMode::Voice => "Listening to the microphone",
+                        InputMode::Keyboard => "Listening to the keyboard",
+                    });
+                    continue;
+                }
+            }
+
+            let transcription = match self.listener.input_mode() {
+                InputMode::Voice => match transcription {
+                    Transcription::Some { text, .. }
+                        if self.spelling.is_spelling() || SpellingMode::starts_spelling(&text) =>
+                    {
+                        if !self.spelling.push(&text) {
+                            self.logger
+                                .info("Spelling, say \"stop spelling\" when you're done");
+                            continue;
+                        }
+                        self.spelled_transcription()
+                    }
+                    Transcription::Empty if self.spelling.is_spelling() => continue,
+                    transcription if self.normalize_code => {
+                        self.normalizer.normalize_transcription(transcription)
+                    }
+                    transcription => transcription,
+                },
+                // Typed text already is what the user meant
+                InputMode::Keyboard => transcription,
+            };
+            return Ok(self.with_language(transcription));
+        }
+    }
+
+    /// Starts monitoring the microphone while the assistant is busy. Returns
+    /// `None` when listening to the keyboard or if barge-in is disabled.
+    pub fn monitor_barge_in(&mut self) -> anyhow::Result<Option<BargeInMonitor>> {
+        let Some(detector) = self.barge_in else {
+            return Ok(None);
+        };
+        let (recorder, format) = match &mut self.listener {
+            SpeechListenerImpl::OpenAI(l) => l.audio_capture(),
+            SpeechListenerImpl::Command(l) => l.audio_capture(),
+            SpeechListenerImpl::Keyboard(_) => return Ok(None),
+        };
+        BargeInMonitor::start(recorder, format, detector).map(Some)
+    }
+
+    /// Calls `hook` whenever the user starts speaking. Typed input has no
+    /// such moment, so the hook isn't called for it.
+    pub fn on_speech_started(&mut self, hook: impl Fn() + Send + Sync + 'static) {
+        let hook: SpeechStartedHook = Arc::new(hook);
+        self.listener.set_speech_started(&hook);
+        if let Some(standby) = &mut self.standby {
+            standby.set_speech_started(&hook);
+        }
+        self.speech_started = Some(hook);
+    }
+
+    /// The listener for the other input mode, setting up voice input if it
+    /// hasn't been used yet.
+    fn take_standby(&mut self) -> anyhow::Result<SpeechListenerImpl> {
+        if let Some(standby) = self.standby.take() {
+            return Ok(standby);
+        }
+        let config = self
+            .voice_config
+            .as_deref()
+            .context("No listener to switch to")?;
+        let mut voice = SpeechListenerImpl::voice(config, self.logger)?;
+        if let Some(hook) = &self.speech_started {
+            voice.set_speech_started(hook);
+        }
+        self.voice_config = None;
+        Ok(voice)
+    }
+
+    /// Whether spoken code like "snake case user id" is turned into code.
+    /// Enabled by default.
+    pub fn set_code_normalization(&mut self, enabled: bool) {
+        self.normalize_code = enabled;
+    }
+
+    /// Joins the segments of a spelled utterance. The confidence of the
+    /// individual transcriptions doesn't apply to the joined text.
+    fn spelled_transcription(&mut self) -> Transcription {
+        let (segments, unrecognized) = self.spelling.take();
+        if !unrecognized.is_empty() {
+            self.logger.warn(format!(
+                "Could not spell {}",
+                unrecognized
+                    .iter()
+                    .map(|w| format!("\"{w}\""))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        let text = segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Spoken(text) if self.normalize_code => self.normalizer.normalize(text),
+                Segment::Spoken(text) => text.clone(),
+                Segment::Spelled(text) => {
+                    self.logger.info(format!("Spelled \"{text}\""));
+                    text.clone()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        Transcription::Some {
+            text,
+            confidence: None,
+            language: None,
+            timing: None,
+        }
+    }
+
+    /// Fills in the language, if the backend hasn't reported it.
+    fn with_language(&mut self, transcription: Transcription) -> Transcription {
+        match transcription {
+            Transcription::Empty => Transcription::Empty,
+            Transcription::Some {
+                text,
+                confidence,
+                language,
+                timing,
+            } => {
+                let language = language
+                    .or_else(|| detect_language(&text, &self.languages))
+                    .or_else(|| self.last_language.clone());
+                self.last_language.clone_from(&language);
+                Transcription::Some {
+                    text,
+                    confidence,
+                    language,
+                    timing,
+                }
+            }
+        }
     }
 }
 
 enum SpeechListenerImpl {
-    OpenAI(OpenAISpeechListener),
+    OpenAI(Box<OpenAISpeechListener>),
+    Command(Box<CommandSpeechListener>),
+    Keyboard(Box<KeyboardListener>),
 }
 
 impl SpeechListenerImpl {
+    /// Sets up the configured speech backend and the recorder it listens to.
+    fn voice(config: &Config, logger: Logger) -> anyhow::Result<Self> {
+        let audio_recorder = AudioRecorder::new(logger, config.recording_file.as_deref())?;
+        Ok(match &config.speech_backend {
+            SpeechBackend::OpenAI => SpeechListenerImpl::OpenAI(Box::new(
+                OpenAISpeechListener::new(config, logger, audio_recorder)?,
+            )),
+            SpeechBackend::Command(command) => SpeechListenerImpl::Command(Box::new(
+                CommandSpeechListener::new(command.clone(), config, logger, audio_recorder),
+            )),
+        })
+    }
+
     async fn listen_to_input(&mut self) -> anyhow::Result<Transcription> {
         match self {
             SpeechListenerImpl::OpenAI(l) => l.listen_to_input().await,
+            SpeechListenerImpl::Command(l) => l.listen_to_input().await,
+            SpeechListenerImpl::Keyboard(l) => l.listen_to_input().await,
+        }
+    }
+
+    fn set_speech_started(&mut self, hook: &SpeechStartedHook) {
+        match self {
+            SpeechListenerImpl::OpenAI(l) => l.set_speech_started(Arc::clone(hook)),
+            SpeechListenerImpl::Command(l) => l.set_speech_started(Arc::clone(hook)),
+            SpeechListenerImpl::Keyboard(_) => {}
+        }
+    }
+
+    fn input_mode(&self) -> InputMode {
+        match self {
+            SpeechListenerImpl::OpenAI(_) | SpeechListenerImpl::Command(_) => InputMode::Voice,
+            SpeechListenerImpl::Keyboard(_) => InputMode::Keyboard,
         }
     }
 }
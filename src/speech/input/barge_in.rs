@@ -0,0 +1,153 @@
+//! Interrupting the assistant by speaking.
+//!
+//! While the assistant is busy, the microphone keeps being monitored. Once
+//! the user speaks, the work in progress is cancelled, and the recording is
+//! handed over to the listener, so the next turn includes the words said
+//! while the assistant was still busy.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::mpsc::channel;
+
+use tokio::sync::mpsc;
+
+use crate::session::CancellationToken;
+use crate::speech::audio::format::SoundSpec;
+use crate::speech::audio::silence::EnergyDetector;
+use crate::speech::audio::{AudioRecorder, Capture, StopTrigger};
+
+/// How long the level has to stay above the threshold, so a cough or a
+/// keyboard click doesn't count as speech
+const MIN_SPEECH_MS: u32 = 250;
+
+/// Audio before the speech that is handed over as well
+const PRE_ROLL_MS: u32 = 300;
+
+const MONITORING: u8 = 0;
+const FINISHED: u8 = 1;
+const INTERRUPTED: u8 = 2;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ListenerEvent {
+    /// The user started talking while the assistant was busy
+    SpeechStarted,
+}
+
+/// Watches the microphone in the background until [`BargeInMonitor::finish`]
+/// is called.
+pub struct BargeInMonitor {
+    events: mpsc::Receiver<ListenerEvent>,
+    cancellation: CancellationToken,
+    state: Arc<AtomicU8>,
+    capture_stop: StopTrigger,
+}
+
+impl BargeInMonitor {
+    /// Starts recording in `format`. Once speech is detected, the returned
+    /// monitor's cancellation token is cancelled, and the recording is handed
+    /// over to `recorder` for its next listen.
+    pub fn start(
+        recorder: &mut AudioRecorder,
+        format: SoundSpec,
+        detector: EnergyDetector,
+    ) -> anyhow::Result<Self> {
+        let (receiver, stop, actual_format) = recorder.listen(Some(format.clone()))?;
+        let handover = recorder.handover();
+        let bytes_per_ms =
+            actual_format.as_ref().unwrap_or(&format).bytes_per_second() as usize / 1000;
+        let min_speech_bytes = MIN_SPEECH_MS as usize * bytes_per_ms;
+        let pre_roll_bytes = PRE_ROLL_MS as usize * bytes_per_ms;
+
+        let (event_sender, events) = mpsc::channel(1);
+        let cancellation = CancellationToken::new();
+        let state = Arc::new(AtomicU8::new(MONITORING));
+
+        let capture_stop = stop.clone();
+        let thread_cancellation = cancellation.clone();
+        let thread_state = Arc::clone(&state);
+        std::thread::spawn(move || {
+            let (cancellation, state) = (thread_cancellation, thread_state);
+            let mut recent = VecDeque::new();
+            let mut recent_bytes = 0;
+            let mut speech_bytes = 0;
+
+            while let Ok(chunk) = receiver.recv() {
+                if state.load(Ordering::Acquire) != MONITORING {
+                    return;
+                }
+                if detector.is_speech(&chunk) {
+                    speech_bytes += chunk.len();
+                } else {
+                    speech_bytes = 0;
+                }
+                recent_bytes += chunk.len();
+                recent.push_back(chunk);
+                while recent_bytes - recent.front().map_or(0, Vec::len)
+                    >= pre_roll_bytes + speech_bytes
+                {
+                    recent_bytes -= recent.pop_front().map_or(0, |c| c.len());
+                }
+
+                if speech_bytes < min_speech_bytes {
+                    continue;
+                }
+                if state
+                    .compare_exchange(MONITORING, INTERRUPTED, Ordering::AcqRel, Ordering::Acquire)
+                    .is_err()
+                {
+                    return;
+                }
+                let _ = event_sender.blocking_send(ListenerEvent::SpeechStarted);
+                cancellation.cancel();
+
+                // The audio so far is replayed to the listener, followed by
+                // the rest of the recording
+                let (sender, continued) = channel();
+                for chunk in recent.drain(..) {
+                    let _ = sender.send(chunk);
+                }
+                handover.put(Capture {
+                    receiver: continued,
+                    stop,
+                    request_format: Some(format),
+                    actual_format,
+                });
+                for chunk in receiver {
+                    if sender.send(chunk).is_err() {
+                        break;
+                    }
+                }
+                return;
+            }
+        });
+
+        Ok(Self {
+            events,
+            cancellation,
+            state,
+            capture_stop,
+        })
+    }
+
+    /// Cancelled once the user starts talking
+    #[must_use]
+    pub fn cancellation(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    /// Stops monitoring, and returns what happened in the meantime. Without
+    /// an interruption, the recording is stopped.
+    pub async fn finish(mut self) -> Option<ListenerEvent> {
+        if self
+            .state
+            .compare_exchange(MONITORING, FINISHED, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            self.capture_stop.stop();
+            return None;
+        }
+        // The event is sent right after the state has changed
+        self.events.recv().await
+    }
+}
@@ -0,0 +1,273 @@
+//! Transcription by a local program, e.g. whisper.cpp, so that no audio has
+//! to leave the machine.
+//!
+//! Each utterance is recorded until the user stops talking, written to a
+//! temporary WAV file and passed to the configured command, which prints the
+//! transcript to stdout.
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::config::{CommandOutputFormat, Config, SilenceTrimming, TranscriptionCommand};
+use crate::logger::Logger;
+use crate::speech::audio::AudioRecorder;
+use crate::speech::audio::format::{PCMFormat, SoundSpec};
+use crate::speech::audio::silence::{EnergyDetector, SilenceTrimmer};
+use crate::speech::audio::wav::write_wav;
+
+use super::{SpeechStartedHook, Transcription, UtteranceTiming};
+
+const WAV_PLACEHOLDER: &str = "{wav}";
+
+pub struct CommandSpeechListener {
+    command: TranscriptionCommand,
+    audio_recorder: AudioRecorder,
+    /// Used to tell when an utterance has ended
+    silence_trimming: SilenceTrimming,
+    speech_started: Option<SpeechStartedHook>,
+    logger: Logger,
+}
+
+impl CommandSpeechListener {
+    #[must_use]
+    pub fn new(
+        command: TranscriptionCommand,
+        config: &Config,
+        logger: Logger,
+        audio_recorder: AudioRecorder,
+    ) -> Self {
+        Self {
+            command,
+            audio_recorder,
+            silence_trimming: config.silence_trimming.unwrap_or_default(),
+            speech_started: None,
+            logger,
+        }
+    }
+
+    pub fn set_speech_started(&mut self, hook: SpeechStartedHook) {
+        self.speech_started = Some(hook);
+    }
+
+    /// The recorder and the format it records in, to keep listening while
+    /// the assistant is busy
+    pub fn audio_capture(&mut self) -> (&mut AudioRecorder, SoundSpec) {
+        (&mut self.audio_recorder, capture_format())
+    }
+
+    pub async fn listen_to_input(&mut self) -> anyhow::Result<Transcription> {
+        let desired_format = capture_format();
+        let (sound_receiver, stop, actual_format) =
+            self.audio_recorder.listen(Some(desired_format.clone()))?;
+        let format = actual_format.unwrap_or(desired_format);
+
+        let detector = EnergyDetector::new(self.silence_trimming.threshold_dbfs);
+        let mut trimmer = SilenceTrimmer::new(
+            detector,
+            &format,
+            self.silence_trimming.pre_roll_ms,
+            self.silence_trimming.hangover_ms,
+        );
+        let speech_started = self.speech_started.clone();
+        let logger = self.logger;
+        let (utterance, speech) = tokio::task::spawn_blocking(move || {
+            let mut utterance = Vec::new();
+            let mut has_warned = false;
+            // Where the speech starts and ends in the utterance, in bytes
+            let mut speech: Option<(usize, usize)> = None;
+            for chunk in sound_receiver {
+                let (is_speech, chunk_len) = (detector.is_speech(&chunk), chunk.len());
+                let chunks = trimmer.push(chunk);
+                if !has_warned && trimmer.has_waited_too_long() {
+                    // Without speech, the end of the utterance can't be told
+                    logger.warn(format!(
+                        "No speech detected so far. If you are speaking, lower \
+                        JARVIS_CODE__SILENCE_THRESHOLD_DBFS below {} dBFS.",
+                        detector.threshold_dbfs()
+                    ));
+                    has_warned = true;
+                }
+                if chunks.is_empty() && !utterance.is_empty() {
+                    // The hangover after the speech is used up
+                    break;
+                }
+                utterance.extend(chunks.into_iter().flatten());
+                if is_speech {
+                    if let (None, Some(hook)) = (speech, &speech_started) {
+                        hook();
+                    }
+                    // A speech chunk is always the last one passed on
+                    let end = utterance.len();
+                    speech = Some((speech.map_or(end - chunk_len, |(start, _)| start), end));
+                }
+            }
+            (utterance, speech)
+        })
+        .await
+        .context("Failed to record the utterance")?;
+        stop.stop();
+        let recorded_at = Instant::now();
+
+        if utterance.is_empty() {
+            return Ok(Transcription::Empty);
+        }
+
+        let wav_file =
+            std::env::temp_dir().join(format!("jarvis_code_utterance_{}.wav", std::process::id()));
+        write_wav(&wav_file, &format, &utterance)?;
+        let result = self.transcribe(&wav_file).await;
+        if let Err(err) = std::fs::remove_file(&wav_file) {
+            self.logger
+                .warn(format!("Could not remove {}: {err}", wav_file.display()));
+        }
+        let (transcript, language) = result?;
+
+        #[allow(clippy::cast_precision_loss)]
+        let offset = |bytes: usize| {
+            Duration::from_secs_f64(bytes as f64 / f64::from(format.bytes_per_second()))
+        };
+        let timing = UtteranceTiming {
+            item_id: None,
+            start: speech.map(|(start, _)| offset(start)),
+            end: speech.map(|(_, end)| offset(end)),
+            latency: Some(recorded_at.elapsed()),
+        };
+
+        Ok(if transcript.is_empty() {
+            Transcription::Empty
+        } else {
+            Transcription::Some {
+                text: transcript,
+                confidence: None,
+                language,
+                timing: Some(timing),
+            }
+        })
+    }
+
+    /// Returns the transcript and its language, if the command reports it
+    async fn transcribe(&self, wav_file: &Path) -> anyhow::Result<(String, Option<String>)> {
+        let command = self
+            .command
+            .command
+            .replace(WAV_PLACEHOLDER, &shell_quote(&wav_file.to_string_lossy()));
+        self.logger
+            .debug(format!("Running transcription command: {command}"));
+
+        let start = Instant::now();
+        let output = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .output()
+            .await
+            .context(format!("Failed to run transcription command '{command}'"))?;
+        self.logger.debug(format!(
+            "Transcription command finished in {:.1} s",
+            start.elapsed().as_secs_f64()
+        ));
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "Transcription command '{command}' failed with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let stdout = String::from_utf8(output.stdout)
+            .context("Transcription command printed invalid UTF-8")?;
+
+        parse_transcript(&stdout, self.command.output)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonTranscript {
+    Text {
+        text: String,
+        language: Option<String>,
+    },
+    /// The output of whisper.cpp with `--output-json`
+    Segments {
+        transcription: Vec<JsonSegment>,
+        result: Option<JsonResult>,
+    },
+}
+
+#[derive(Deserialize)]
+struct JsonResult {
+    language: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JsonSegment {
+    text: String,
+}
+
+/// Most local speech recognition models, including whisper.cpp, expect
+/// 16 kHz
+fn capture_format() -> SoundSpec {
+    SoundSpec::PCM {
+        format: PCMFormat::S16LE,
+        sample_rate_hz: 16000,
+        num_channels: 1,
+    }
+}
+
+fn parse_transcript(
+    stdout: &str,
+    format: CommandOutputFormat,
+) -> anyhow::Result<(String, Option<String>)> {
+    let (parts, language): (Vec<String>, _) = match format {
+        CommandOutputFormat::Text => (stdout.lines().map(strip_annotations).collect(), None),
+        CommandOutputFormat::Json => {
+            let transcript: JsonTranscript = serde_json::from_str(stdout).context(format!(
+                "Failed to parse transcription command output {stdout}"
+            ))?;
+            match transcript {
+                JsonTranscript::Text { text, language } => {
+                    (vec![strip_annotations(&text)], language)
+                }
+                JsonTranscript::Segments {
+                    transcription,
+                    result,
+                } => (
+                    transcription
+                        .iter()
+                        .map(|segment| strip_annotations(&segment.text))
+                        .collect(),
+                    result.and_then(|r| r.language),
+                ),
+            }
+        }
+    };
+
+    let text = parts
+        .iter()
+        .filter(|part| !part.is_empty())
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(" ");
+    Ok((text, language))
+}
+
+/// Removes leading bracketed annotations, like the timestamps whisper.cpp
+/// prints before each segment or its `[BLANK_AUDIO]` marker.
+fn strip_annotations(text: &str) -> String {
+    let mut text = text.trim();
+    while let Some(rest) = text.strip_prefix('[') {
+        match rest.split_once(']') {
+            Some((_, rest)) => text = rest.trim_start(),
+            None => break,
+        }
+    }
+    text.trim().to_owned()
+}
+
+/// Quotes `s` as a single argument for `sh`.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
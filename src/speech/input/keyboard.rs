@@ -0,0 +1,162 @@
+//! A fallback for situations where the user can't talk: utterances are typed
+//! into a line editor on the terminal instead.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+
+use crate::config::{Config, InputMode};
+use crate::logger::Logger;
+
+use super::Transcription;
+
+const PROMPT: &str = "> ";
+
+pub struct KeyboardListener {
+    // The editor is moved to a blocking thread while reading a line
+    editor: Option<DefaultEditor>,
+    history_file: Option<PathBuf>,
+    logger: Logger,
+}
+
+impl KeyboardListener {
+    pub fn new(config: &Config, logger: Logger) -> anyhow::Result<Self> {
+        let editor = line_editor(config.keyboard_history_file.as_deref())?;
+
+        Ok(Self {
+            editor: Some(editor),
+            history_file: config.keyboard_history_file.clone(),
+            logger,
+        })
+    }
+
+    pub async fn listen_to_input(&mut self) -> anyhow::Result<Transcription> {
+        let mut editor = self
+            .editor
+            .take()
+            .context("The line editor is already in use")?;
+        let (mut editor, line) = tokio::task::spawn_blocking(move || {
+            let line = editor.readline(PROMPT);
+            (editor, line)
+        })
+        .await
+        .context("Failed to read keyboard input")?;
+
+        let result = match line {
+            Ok(line) if line.trim().is_empty() => Ok(Transcription::Empty),
+            Ok(line) => {
+                self.remember(&mut editor, &line);
+                Ok(Transcription::Some {
+                    text: line.trim().to_owned(),
+                    confidence: None,
+                    language: None,
+                    timing: None,
+                })
+            }
+            Err(ReadlineError::Eof) => Err(anyhow::anyhow!("Keyboard input has ended")),
+            Err(ReadlineError::Interrupted) => Err(anyhow::anyhow!("Keyboard input interrupted")),
+            Err(err) => Err(err).context("Failed to read keyboard input"),
+        };
+        self.editor = Some(editor);
+
+        result
+    }
+
+    fn remember(&self, editor: &mut DefaultEditor, line: &str) {
+        let result = editor.add_history_entry(line).and_then(|_| {
+            self.history_file
+                .as_ref()
+                .map_or(Ok(()), |f| editor.save_history(f))
+        });
+        if let Err(err) = result {
+            self.logger
+                .warn(format!("Could not save keyboard input history: {err}"));
+        }
+    }
+}
+
+fn line_editor(history_file: Option<&Path>) -> anyhow::Result<DefaultEditor> {
+    let mut editor = DefaultEditor::new().context("Failed to initialize the line editor")?;
+    if let Some(history_file) = history_file {
+        // The history file doesn't exist on the first run
+        if history_file.exists() {
+            editor.load_history(history_file).context(format!(
+                "Failed to load keyboard input history from {}",
+                history_file.display()
+            ))?;
+        }
+    }
+    Ok(editor)
+}
+
+/// Checks whether `text` asks to switch between spoken and typed input, and
+/// if so, returns the requested mode.
+pub fn input_switch_command(text: &str) -> Option<InputMode> {
+    let command = text
+        .trim_matches(|c: char| !c.is_alphanumeric() && c != '/')
+        .to_lowercase();
+    match command.as_str() {
+        "/keyboard" | "switch to keyboard" | "keyboard mode" | "silent mode" => {
+            Some(InputMode::Keyboard)
+        }
+        "/voice" | "switch to voice" | "voice mode" => Some(InputMode::Voice),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_input_switch_commands() {
+        let cases = [
+            ("/keyboard", Some(InputMode::Keyboard)),
+            ("Switch to keyboard.", Some(InputMode::Keyboard)),
+            ("  keyboard mode ", Some(InputMode::Keyboard)),
+            ("Silent mode!", Some(InputMode::Keyboard)),
+            ("/voice", Some(InputMode::Voice)),
+            ("Switch to voice.", Some(InputMode::Voice)),
+            ("VOICE MODE", Some(InputMode::Voice)),
+            ("keyboard", None),
+            ("switch to the keyboard", None),
+            ("Please switch to voice mode", None),
+            ("/keyboard mode", None),
+            ("", None),
+        ];
+
+        for (text, expected) in cases {
+            assert!(input_switch_command(text) == expected, "text: {text:?}");
+        }
+    }
+
+    #[test]
+    fn keeps_the_history_across_sessions() {
+        let history_file = std::env::temp_dir().join(format!(
+            "jarvis_code_keyboard_history_{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&history_file);
+        let listener = KeyboardListener {
+            editor: None,
+            history_file: Some(history_file.clone()),
+            logger: Logger::new(),
+        };
+
+        let mut editor = line_editor(listener.history_file.as_deref()).unwrap();
+        assert_eq!(editor.history().iter().count(), 0);
+        for line in ["open main.rs", "open main.rs", "run the tests"] {
+            listener.remember(&mut editor, line);
+        }
+        let reloaded = line_editor(Some(&history_file)).unwrap();
+        std::fs::remove_file(&history_file).unwrap();
+
+        // Repeated lines are only remembered once
+        assert_eq!(
+            reloaded.history().iter().collect::<Vec<_>>(),
+            ["open main.rs", "run the tests"]
+        );
+    }
+}
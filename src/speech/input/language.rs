@@ -0,0 +1,198 @@
+//! Telling which of the configured languages a transcript is in.
+//!
+//! The realtime API doesn't report the language it detected, so the
+//! transcript is scored by how many of its words are among the most common
+//! words of each language, or contain letters typical for it. This is
+//! reliable enough to pick between a handful of languages for anything
+//! longer than a couple of words.
+
+struct Language {
+    /// ISO 639-1 code
+    code: &'static str,
+    /// English name
+    name: &'static str,
+    /// Frequent words that don't occur in the other lists
+    words: &'static [&'static str],
+    /// Letters that are rare in the other languages
+    letters: &'static str,
+}
+
+const LANGUAGES: &[Language] = &[
+    Language {
+        code: "en",
+        name: "English",
+        words: &[
+            "the", "and", "are", "to", "of", "in", "it", "this", "that", "what", "with", "you",
+            "can", "please", "how", "why", "my", "be", "for", "not", "should", "would", "file",
+            "function",
+        ],
+        letters: "",
+    },
+    Language {
+        code: "de",
+        name: "German",
+        words: &[
+            "der", "die", "das", "und", "ist", "nicht", "ich", "du", "wir", "ein", "eine", "zu",
+            "mit", "auf", "für", "bitte", "wie", "warum", "was", "den", "dem", "kannst", "diese",
+            "dieser", "datei", "funktion", "mach", "mache",
+        ],
+        letters: "äöüß",
+    },
+    Language {
+        code: "fr",
+        name: "French",
+        words: &[
+            "le", "la", "les", "et", "est", "une", "je", "tu", "nous", "pas", "pour", "avec",
+            "dans", "qui", "quoi", "pourquoi", "fichier", "fonction", "merci",
+        ],
+        letters: "çœâêîôû",
+    },
+    Language {
+        code: "es",
+        name: "Spanish",
+        words: &[
+            "el", "los", "las", "y", "es", "por", "para", "qué", "yo", "pero", "como", "cómo",
+            "archivo", "función", "gracias", "esto", "este",
+        ],
+        letters: "ñ¿¡",
+    },
+    Language {
+        code: "it",
+        name: "Italian",
+        words: &[
+            "il", "lo", "gli", "e", "è", "per", "che", "non", "sono", "questo", "perché", "come",
+            "funzione", "grazie",
+        ],
+        letters: "ìò",
+    },
+    Language {
+        code: "nl",
+        name: "Dutch",
+        words: &[
+            "de", "het", "een", "en", "niet", "ik", "jij", "wij", "met", "op", "voor", "waarom",
+            "hoe", "wat", "dit", "deze", "maar", "ook", "bestand", "functie", "bedankt",
+        ],
+        letters: "",
+    },
+];
+
+/// The English name of the language with the ISO 639-1 `code`, or the code
+/// itself for languages without a name here
+#[must_use]
+pub fn language_name(code: &str) -> &str {
+    LANGUAGES
+        .iter()
+        .find(|language| language.code == code)
+        .map_or(code, |language| language.name)
+}
+
+/// Returns which of the `allowed` languages `text` is most likely in, or
+/// `None` if there are no hints at all, or several languages fit equally
+/// well.
+#[must_use]
+pub fn detect_language(text: &str, allowed: &[String]) -> Option<String> {
+    if let [only] = allowed {
+        return Some(only.clone());
+    }
+
+    let words: Vec<String> = text
+        .split_whitespace()
+        .map(|w| {
+            w.trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase()
+        })
+        .collect();
+
+    let mut best: Option<(&String, usize)> = None;
+    let mut is_tied = false;
+    for code in allowed {
+        let Some(language) = LANGUAGES.iter().find(|language| language.code == code) else {
+            continue;
+        };
+        let score = words
+            .iter()
+            .filter(|w| {
+                language.words.contains(&w.as_str()) || w.contains(|c| language.letters.contains(c))
+            })
+            .count();
+        match best {
+            _ if score == 0 => {}
+            Some((_, best_score)) if score == best_score => is_tied = true,
+            Some((_, best_score)) if score < best_score => {}
+            _ => {
+                best = Some((code, score));
+                is_tied = false;
+            }
+        }
+    }
+
+    if is_tied {
+        return None;
+    }
+    best.map(|(code, _)| code.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_the_language() {
+        let all = ["en", "de", "fr", "es", "it", "nl"];
+        let cases: &[(&str, &[&str], Option<&str>)] = &[
+            ("What is wrong with this function?", &all, Some("en")),
+            ("Warum ist diese Funktion so langsam?", &all, Some("de")),
+            ("Öffne bitte main.rs", &all, Some("de")),
+            ("Waarom is deze functie zo traag?", &all, Some("nl")),
+            ("¿Por qué esta función es tan lenta?", &all, Some("es")),
+            ("Perché questa funzione è così lenta?", &all, Some("it")),
+            ("Pourquoi cette fonction est lente?", &all, Some("fr")),
+            // Single words
+            ("Bestand", &all, Some("nl")),
+            ("Größe", &all, Some("de")),
+            ("main.rs", &all, None),
+            // "wat" is Dutch and "was" German
+            ("wat was", &all, None),
+            ("wat was", &["en", "nl"], Some("nl")),
+            // Languages that aren't allowed don't count
+            ("Warum ist diese Funktion so langsam?", &["en", "nl"], None),
+            ("anything", &["de"], Some("de")),
+            ("the file", &["xx", "en"], Some("en")),
+            ("", &all, None),
+        ];
+
+        for (text, allowed, expected) in cases {
+            let allowed: Vec<String> = allowed.iter().map(|&code| code.to_owned()).collect();
+            assert_eq!(
+                detect_language(text, &allowed).as_deref(),
+                *expected,
+                "{text} in {allowed:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn has_no_words_in_several_languages() {
+        for (i, language) in LANGUAGES.iter().enumerate() {
+            for other in &LANGUAGES[i + 1..] {
+                let shared: Vec<_> = language
+                    .words
+                    .iter()
+                    .filter(|w| other.words.contains(w))
+                    .collect();
+                assert!(
+                    shared.is_empty(),
+                    "{} and {} share {shared:?}",
+                    language.code,
+                    other.code
+                );
+                assert!(
+                    !language.letters.contains(|c| other.letters.contains(c)),
+                    "{} and {} share letters",
+                    language.code,
+                    other.code
+                );
+            }
+        }
+    }
+}
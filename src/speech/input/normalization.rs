@@ -0,0 +1,633 @@
+//! Turns spoken programming constructs into code text.
+//!
+//! A dictated `get_user_id().unwrap()` comes back from the transcription as
+//! "snake case get user id open paren close paren dot unwrap". The
+//! [`SpokenCodeNormalizer`] rewrites such transcripts based on a table of
+//! rules: symbol rules replace a spoken phrase by a piece of code, casing rules
+//! join the words following them into a single identifier, up to "end case".
+//! Number words are converted to digits.
+//!
+//! Only code spans are rewritten, which start with "begin code" and end with
+//! "end code" or the utterance, e.g. "call begin code snake case get user id
+//! open paren close paren end code please".
+//!
+//! The built-in rule table can be extended with a JSON file, e.g.
+//!
+//! ```json
+//! [
+//!     { "spoken": "walrus", "code": ":=" },
+//!     { "spoken": "shouty case", "case": "screaming_snake" }
+//! ]
+//! ```
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, bail};
+use serde::Deserialize;
+
+use super::Transcription;
+
+/// How a piece of code is separated from its neighbors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Spacing {
+    /// Surrounded by spaces, e.g. `=`
+    #[default]
+    Spaced,
+    /// No space on either side, e.g. `::`
+    Joined,
+    /// No space before, e.g. `,`
+    JoinLeft,
+    /// No space after, e.g. `&`
+    JoinRight,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaseStyle {
+    Snake,
+    ScreamingSnake,
+    Camel,
+    Pascal,
+    Kebab,
+}
+
+impl CaseStyle {
+    fn apply(self, words: &[String]) -> String {
+        match self {
+            Self::Snake => words.join("_"),
+            Self::ScreamingSnake => words.join("_").to_uppercase(),
+            Self::Kebab => words.join("-"),
+            Self::Camel => words
+                .iter()
+                .enumerate()
+                .map(|(i, w)| if i == 0 { w.clone() } else { capitalize(w) })
+                .collect(),
+            Self::Pascal => words.iter().map(|w| capitalize(w)).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum RuleAction {
+    Symbol { code: String, spacing: Spacing },
+    Casing(CaseStyle),
+}
+
+#[derive(Clone, Debug)]
+pub struct Rule {
+    /// The spoken phrase, as lowercase words
+    words: Vec<String>,
+    action: RuleAction,
+}
+
+impl Rule {
+    /// Fails if `spoken` has no words, as such a rule would match anywhere.
+    pub fn new(spoken: &str, action: RuleAction) -> anyhow::Result<Self> {
+        let words: Vec<String> = spoken.split_whitespace().map(str::to_lowercase).collect();
+        if words.is_empty() {
+            bail!("Spoken code rules need a non-empty \"spoken\" phrase");
+        }
+        Ok(Self { words, action })
+    }
+}
+
+/// A rule as written by the user in the rules file.
+#[derive(Deserialize)]
+struct RuleDefinition {
+    spoken: String,
+    code: Option<String>,
+    #[serde(default)]
+    spacing: Spacing,
+    case: Option<CaseStyle>,
+}
+
+impl TryFrom<RuleDefinition> for Rule {
+    type Error = anyhow::Error;
+
+    fn try_from(def: RuleDefinition) -> Result<Self, Self::Error> {
+        let action = match (def.code, def.case) {
+            (Some(code), None) => RuleAction::Symbol {
+                code,
+                spacing: def.spacing,
+            },
+            (None, Some(case)) => RuleAction::Casing(case),
+            _ => bail!(
+                "Spoken code rule \"{}\" must have either a \"code\" or a \"case\" attribute",
+                def.spoken
+            ),
+        };
+        Self::new(&def.spoken, action)
+    }
+}
+
+const DEFAULT_SYMBOLS: [(&str, &str, Spacing); 42] = [
+    ("open paren", "(", Spacing::Joined),
+    ("close paren", ")", Spacing::JoinLeft),
+    ("open bracket", "[", Spacing::Joined),
+    ("close bracket", "]", Spacing::JoinLeft),
+    ("open brace", "{", Spacing::Spaced),
+    ("close brace", "}", Spacing::Spaced),
+    ("open angle", "<", Spacing::Joined),
+    ("close angle", ">", Spacing::JoinLeft),
+    ("colon colon", "::", Spacing::Joined),
+    ("double colon", "::", Spacing::Joined),
+    ("dot", ".", Spacing::Joined),
+    ("comma", ",", Spacing::JoinLeft),
+    ("colon", ":", Spacing::JoinLeft),
+    ("semicolon", ";", Spacing::JoinLeft),
+    ("question mark", "?", Spacing::JoinLeft),
+    ("underscore", "_", Spacing::Joined),
+    ("ampersand", "&", Spacing::JoinRight),
+    ("bang", "!", Spacing::JoinRight),
+    ("hash", "#", Spacing::JoinRight),
+    ("quote", "\"", Spacing::Spaced),
+    ("single quote", "'", Spacing::Spaced),
+    ("backtick", "`", Spacing::Spaced),
+    ("equals", "=", Spacing::Spaced),
+    ("double equals", "==", Spacing::Spaced),
+    ("equals equals", "==", Spacing::Spaced),
+    ("not equals", "!=", Spacing::Spaced),
+    ("plus equals", "+=", Spacing::Spaced),
+    ("minus equals", "-=", Spacing::Spaced),
+    ("plus", "+", Spacing::Spaced),
+    ("minus", "-", Spacing::Spaced),
+    ("times", "*", Spacing::Spaced),
+    ("divided by", "/", Spacing::Spaced),
+    ("modulo", "%", Spacing::Spaced),
+    ("less than", "<", Spacing::Spaced),
+    ("greater than", ">", Spacing::Spaced),
+    ("less or equal", "<=", Spacing::Spaced),
+    ("greater or equal", ">=", Spacing::Spaced),
+    ("double and", "&&", Spacing::Spaced),
+    ("double pipe", "||", Spacing::Spaced),
+    ("pipe", "|", Spacing::Spaced),
+    ("arrow", "->", Spacing::Spaced),
+    ("fat arrow", "=>", Spacing::Spaced),
+];
+
+const DEFAULT_CASINGS: [(&str, CaseStyle); 7] = [
+    ("snake case", CaseStyle::Snake),
+    ("screaming snake case", CaseStyle::ScreamingSnake),
+    ("constant case", CaseStyle::ScreamingSnake),
+    ("camel case", CaseStyle::Camel),
+    ("pascal case", CaseStyle::Pascal),
+    ("type case", CaseStyle::Pascal),
+    ("kebab case", CaseStyle::Kebab),
+];
+
+/// Phrases starting a code span. Only code spans are normalized, so that
+/// e.g. "what does the arrow do" stays a question.
+const CODE_START_PHRASES: &[&[&str]] = &[&["begin", "code"], &["start", "code"]];
+
+/// Phrases ending a code span, which otherwise ends with the utterance
+const CODE_END_PHRASES: &[&[&str]] = &[&["end", "code"], &["stop", "code"]];
+
+/// Phrases ending an identifier started by a casing rule, which otherwise
+/// ends at the next rule or with the code span
+const CASE_END_PHRASES: &[&[&str]] = &[&["end", "case"]];
+
+fn default_rules() -> Vec<Rule> {
+    let symbols = DEFAULT_SYMBOLS.iter().map(|(spoken, code, spacing)| {
+        Rule::new(
+            spoken,
+            RuleAction::Symbol {
+                code: (*code).to_owned(),
+                spacing: *spacing,
+            },
+        )
+    });
+    let casings = DEFAULT_CASINGS
+        .iter()
+        .map(|(spoken, case)| Rule::new(spoken, RuleAction::Casing(*case)));
+    symbols
+        .chain(casings)
+        .map(|rule| rule.expect("Built-in rules have a spoken phrase"))
+        .collect()
+}
+
+pub struct SpokenCodeNormalizer {
+    rules: Vec<Rule>,
+}
+
+impl Default for SpokenCodeNormalizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpokenCodeNormalizer {
+    /// Creates a normalizer with the built-in rule table.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_rules(Vec::new())
+    }
+
+    /// Creates a normalizer with the built-in rule table, extended by
+    /// `user_rules`. A user rule for a phrase that is already in the built-in
+    /// table replaces the built-in rule.
+    #[must_use]
+    pub fn with_rules(user_rules: Vec<Rule>) -> Self {
+        let mut rules = default_rules();
+        rules.retain(|rule| !user_rules.iter().any(|u| u.words == rule.words));
+        rules.extend(user_rules);
+        // Longest phrases first, so "double colon" wins over "colon"
+        rules.sort_by_key(|rule| std::cmp::Reverse(rule.words.len()));
+        Self { rules }
+    }
+
+    /// Creates a normalizer with the built-in rule table, extended by the
+    /// rules defined in the JSON file at `path`.
+    pub fn from_rules_file(path: &Path) -> anyhow::Result<Self> {
+        let json = fs::read_to_string(path).context(format!(
+            "Failed to read spoken code rules from {}",
+            path.display()
+        ))?;
+        let definitions: Vec<RuleDefinition> = serde_json::from_str(&json).context(format!(
+            "Failed to parse spoken code rules from {}",
+            path.display()
+        ))?;
+        let rules = definitions
+            .into_iter()
+            .map(Rule::try_from)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self::with_rules(rules))
+    }
+
+    #[must_use]
+    pub fn normalize_transcription(&self, transcription: Transcription) -> Transcription {
+        match transcription {
+            Transcription::Empty => Transcription::Empty,
+            Transcription::Some {
+                text,
+                confidence,
+                language,
+                timing,
+            } => Transcription::Some {
+                text: self.normalize(&text),
+                confidence,
+                language,
+                timing,
+            },
+        }
+    }
+
+    /// Rewrites the code spans of `text`, leaving the rest as it is
+    #[must_use]
+    pub fn normalize(&self, text: &str) -> String {
+        // Byte offsets of the words, to copy the text between code spans
+        let words: Vec<(usize, &str)> = text
+            .split_whitespace()
+            .map(|w| (w.as_ptr() as usize - text.as_ptr() as usize, w))
+            .collect();
+        let keys: Vec<String> = words.iter().map(|(_, w)| match_key(w)).collect();
+
+        let mut out = String::new();
+        let mut copied = 0;
+        let mut i = 0;
+        while i < words.len() {
+            let Some(start_len) = phrase_len(&keys[i..], CODE_START_PHRASES) else {
+                i += 1;
+                continue;
+            };
+            out.push_str(&text[copied..words[i].0]);
+            i += start_len;
+            let (end, end_len) = (i..words.len())
+                .find_map(|j| Some((j, phrase_len(&keys[j..], CODE_END_PHRASES)?)))
+                .unwrap_or((words.len(), 0));
+            let code_words: Vec<&str> = words[i..end].iter().map(|(_, w)| *w).collect();
+            out.push_str(&join(&self.normalize_code(&code_words, &keys[i..end])));
+            i = end + end_len;
+            let (offset, last_word) = words[i - 1];
+            copied = offset + last_word.len();
+        }
+        out.push_str(&text[copied..]);
+
+        out
+    }
+
+    fn normalize_code(&self, words: &[&str], keys: &[String]) -> Vec<(String, Spacing)> {
+        let mut pieces: Vec<(String, Spacing)> = Vec::new();
+        let mut i = 0;
+        while i < words.len() {
+            if let Some(rule) = self.matching_rule(&keys[i..]) {
+                i += rule.words.len();
+                match &rule.action {
+                    RuleAction::Symbol { code, spacing } => pieces.push((code.clone(), *spacing)),
+                    RuleAction::Casing(style) => {
+                        let mut ident_words = Vec::new();
+                        while i < words.len() && self.matching_rule(&keys[i..]).is_none() {
+                            if let Some(len) = phrase_len(&keys[i..], CASE_END_PHRASES) {
+                                i += len;
+                                break;
+                            }
+                            // Punctuation of the transcription
+                            if keys[i].is_empty() {
+                                i += 1;
+                                continue;
+                            }
+                            let (number, len) = parse_number(&keys[i..]);
+                            match number {
+                                Some(n) => {
+                                    ident_words.push(n.to_string());
+                                    i += len;
+                                }
+                                None => {
+                                    ident_words.push(keys[i].clone());
+                                    i += 1;
+                                }
+                            }
+                        }
+                        if !ident_words.is_empty() {
+                            pieces.push((style.apply(&ident_words), Spacing::Spaced));
+                        }
+                    }
+                }
+            } else if let (Some(n), len) = parse_number(&keys[i..]) {
+                pieces.push((
+                    n.to_string() + trailing_punctuation(words[i + len - 1]),
+                    Spacing::Spaced,
+                ));
+                i += len;
+            } else {
+                pieces.push((words[i].to_owned(), Spacing::Spaced));
+                i += 1;
+            }
+        }
+        pieces
+    }
+
+    fn matching_rule(&self, keys: &[String]) -> Option<&Rule> {
+        self.rules.iter().find(|rule| {
+            rule.words.len() <= keys.len() && rule.words.iter().zip(keys).all(|(r, k)| r == k)
+        })
+    }
+}
+
+/// The number of words of the first phrase `keys` starts with, if any
+fn phrase_len(keys: &[String], phrases: &[&[&str]]) -> Option<usize> {
+    phrases
+        .iter()
+        .find(|phrase| keys.len() >= phrase.len() && phrase.iter().zip(keys).all(|(p, k)| p == k))
+        .map(|phrase| phrase.len())
+}
+
+fn join(pieces: &[(String, Spacing)]) -> String {
+    let mut out = String::new();
+    let mut previous: Option<Spacing> = None;
+    for (text, spacing) in pieces {
+        let glue_left = matches!(spacing, Spacing::Joined | Spacing::JoinLeft);
+        let glued_right = matches!(previous, Some(Spacing::Joined | Spacing::JoinRight));
+        if previous.is_some() && !glue_left && !glued_right {
+            out.push(' ');
+        }
+        out.push_str(text);
+        previous = Some(*spacing);
+    }
+    out
+}
+
+/// The transcription adds punctuation and capitalization, which must be
+/// ignored when matching rules.
+fn match_key(word: &str) -> String {
+    word.trim_matches(|c: char| !c.is_alphanumeric())
+        .to_lowercase()
+}
+
+fn trailing_punctuation(word: &str) -> &str {
+    let trimmed = word.trim_end_matches(|c: char| !c.is_alphanumeric());
+    &word[trimmed.len()..]
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn number_word_value(word: &str) -> Option<u64> {
+    let value = match word {
+        "zero" => 0,
+        "one" => 1,
+        "two" => 2,
+        "three" => 3,
+        "four" => 4,
+        "five" => 5,
+        "six" => 6,
+        "seven" => 7,
+        "eight" => 8,
+        "nine" => 9,
+        "ten" => 10,
+        "eleven" => 11,
+        "twelve" => 12,
+        "thirteen" => 13,
+        "fourteen" => 14,
+        "fifteen" => 15,
+        "sixteen" => 16,
+        "seventeen" => 17,
+        "eighteen" => 18,
+        "nineteen" => 19,
+        "twenty" => 20,
+        "thirty" => 30,
+        "forty" => 40,
+        "fifty" => 50,
+        "sixty" => 60,
+        "seventy" => 70,
+        "eighty" => 80,
+        "ninety" => 90,
+        "hundred" => 100,
+        "thousand" => 1000,
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Parses a number spelled out in words at the start of `keys`, returning the
+/// number and how many words it spans. "forty two" is 42, while "two three"
+/// is parsed as 2 followed by another number.
+fn parse_number(keys: &[String]) -> (Option<u64>, usize) {
+    let mut total = 0;
+    let mut current: u64 = 0;
+    let mut len = 0;
+
+    for key in keys {
+        let Some(value) = number_word_value(key) else {
+            break;
+        };
+        let is_teen = (10..20).contains(&(current % 100));
+        match value {
+            // "zero" never is a part of a longer number
+            0 if len == 0 => {
+                len = 1;
+                break;
+            }
+            1..=9 if current % 10 == 0 && !is_teen => current += value,
+            10..=90 if current % 100 == 0 => current += value,
+            100 if (1..10).contains(&current) => current *= 100,
+            1000 if total == 0 && current > 0 => {
+                total = current * 1000;
+                current = 0;
+            }
+            _ => break,
+        }
+        len += 1;
+    }
+
+    if len == 0 {
+        (None, 0)
+    } else {
+        (Some(total + current), len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_spoken_code() {
+        let cases = [
+            ("snake case get user id", "get_user_id"),
+            ("camel case get user id", "getUserId"),
+            ("pascal case audio recorder", "AudioRecorder"),
+            ("screaming snake case max file bytes", "MAX_FILE_BYTES"),
+            ("kebab case jarvis code", "jarvis-code"),
+            ("Snake case get user ID dot unwrap", "get_user_id.unwrap"),
+            (
+                "result dot unwrap open paren close paren",
+                "result.unwrap()",
+            ),
+            ("std colon colon io", "std::io"),
+            ("std double colon fs", "std::fs"),
+            ("let x equals forty two semicolon", "let x = 42;"),
+            ("a comma b", "a, b"),
+            ("x double equals y", "x == y"),
+            ("x not equals one hundred", "x != 100"),
+            ("ampersand self", "&self"),
+            ("fn foo arrow bool", "fn foo -> bool"),
+            ("vec open bracket zero close bracket", "vec[0]"),
+            ("snake case user two", "user_2"),
+            ("snake case user id end case equals one", "user_id = 1"),
+            ("snake case user id end case please", "user_id please"),
+            ("snake case get - user, id", "get_user_id"),
+            ("two three", "2 3"),
+            ("twenty one", "21"),
+            ("one thousand two hundred five", "1205"),
+        ];
+
+        let normalizer = SpokenCodeNormalizer::new();
+        for (spoken, expected) in cases {
+            let spoken = format!("begin code {spoken}");
+            assert_eq!(normalizer.normalize(&spoken), expected, "input: {spoken:?}");
+        }
+    }
+
+    #[test]
+    fn normalizes_only_code_spans() {
+        let cases = [
+            ("Please rename it.", "Please rename it."),
+            ("I have two cats.", "I have two cats."),
+            ("Explain this hash map", "Explain this hash map"),
+            ("What does the arrow do?", "What does the arrow do?"),
+            ("Is this the one?", "Is this the one?"),
+            (
+                "Why is there a pipe, a bang and a colon times two plus a dot in quote marks?",
+                "Why is there a pipe, a bang and a colon times two plus a dot in quote marks?",
+            ),
+            (
+                "What does snake case user id please mean",
+                "What does snake case user id please mean",
+            ),
+            (
+                "Call Begin code snake case get user id end code, please.",
+                "Call get_user_id please.",
+            ),
+            (
+                "rename begin code x end code to begin code snake case max len",
+                "rename x to max_len",
+            ),
+            (
+                "begin code snake case user id end code please",
+                "user_id please",
+            ),
+            ("begin code", ""),
+            ("", ""),
+            ("First  line\n\nsecond line", "First  line\n\nsecond line"),
+            (
+                "Use\n  begin code x dot y end code\n\nthen  stop",
+                "Use\n  x.y\n\nthen  stop",
+            ),
+        ];
+
+        let normalizer = SpokenCodeNormalizer::new();
+        for (spoken, expected) in cases {
+            assert_eq!(normalizer.normalize(spoken), expected, "input: {spoken:?}");
+        }
+    }
+
+    #[test]
+    fn user_rules_extend_and_override_defaults() {
+        let rules = vec![
+            Rule::new(
+                "walrus",
+                RuleAction::Symbol {
+                    code: ":=".to_owned(),
+                    spacing: Spacing::Spaced,
+                },
+            ),
+            Rule::new(
+                "dot",
+                RuleAction::Symbol {
+                    code: "->".to_owned(),
+                    spacing: Spacing::Joined,
+                },
+            ),
+            Rule::new("shouty case", RuleAction::Casing(CaseStyle::ScreamingSnake)),
+        ]
+        .into_iter()
+        .map(Result::unwrap)
+        .collect();
+        let cases = [
+            ("x walrus one", "x := 1"),
+            ("ptr dot next", "ptr->next"),
+            ("shouty case limit", "LIMIT"),
+            ("snake case still works", "still_works"),
+        ];
+
+        let normalizer = SpokenCodeNormalizer::with_rules(rules);
+        for (spoken, expected) in cases {
+            let spoken = format!("begin code {spoken}");
+            assert_eq!(normalizer.normalize(&spoken), expected, "input: {spoken:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_rules_without_words() {
+        for spoken in ["", "  "] {
+            let rule = Rule::new(spoken, RuleAction::Casing(CaseStyle::Snake));
+            assert!(rule.is_err(), "spoken: {spoken:?}");
+        }
+    }
+
+    #[test]
+    fn parses_rule_definitions() {
+        let cases = [
+            (r#"{"spoken": "walrus", "code": ":="}"#, true),
+            (
+                r#"{"spoken": "shouty case", "case": "screaming_snake"}"#,
+                true,
+            ),
+            (r#"{"spoken": "both", "code": "x", "case": "snake"}"#, false),
+            (r#"{"spoken": "neither"}"#, false),
+            (r#"{"spoken": " ", "code": "x"}"#, false),
+        ];
+
+        for (json, is_valid) in cases {
+            let def: RuleDefinition = serde_json::from_str(json).unwrap();
+            assert_eq!(Rule::try_from(def).is_ok(), is_valid, "definition: {json}");
+        }
+    }
+}
@@ -1,7 +1,11 @@
 //! Using the [Open AI realtime transcription API](https://platform.openai.com/docs/guides/realtime?use-case=transcription)
 
+#[cfg(feature = "mock-realtime")]
+pub mod mock_server;
+
 use std::str::FromStr;
 use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Ok, bail};
 use base64::prelude::*;
@@ -18,36 +22,119 @@ use tokio_tungstenite::{
     tungstenite::{http, protocol::Message},
 };
 
+use crate::logger::Logger;
 use crate::speech::audio::AudioRecorder;
+use crate::speech::audio::silence::{self, EnergyDetector, SilenceTrimmer};
+use crate::speech::wake_word::{WakeWordModel, WakeWordSpotter};
 use crate::{
-    config::Config,
+    config::{Config, SilenceTrimming},
     speech::audio::format::{PCMFormat, SoundSpec},
 };
 
-use super::Transcription;
+use super::usage::{PriceTable, UsageLedger, UsageRecord};
+use super::vocabulary::{BASE_PROMPT, ProjectVocabulary};
+use super::{Confidence, SpeechStartedHook, TokenConfidence, Transcription, UtteranceTiming};
+
+const REALTIME_TRANSCRIPTION_URL: &str = "wss://api.openai.com/v1/realtime?intent=transcription";
+
+/// How long the server VAD waits in silence before it ends a turn
+const SERVER_VAD_SILENCE_MS: u32 = 500;
+
+/// Silence to stream in addition to what the server VAD waits for, to be on
+/// the safe side when trimming silence
+const MIN_HANGOVER_MARGIN_MS: u32 = 200;
 
 pub struct SpeechListener {
     api_key: String,
+    url: String,
     audio_recorder: AudioRecorder,
+    vocabulary: ProjectVocabulary,
+    usage_ledger: UsageLedger,
+    /// `None` to let the model detect the language
+    language: Option<String>,
+    silence_trimming: Option<SilenceTrimming>,
+    /// Moved to a blocking thread while waiting for the wake phrase
+    wake_word: Option<WakeWordSpotter>,
+    speech_started: Option<SpeechStartedHook>,
+    logger: Logger,
 }
 
 impl SpeechListener {
-    #[must_use]
-    pub fn new(config: &Config, audio_recorder: AudioRecorder) -> Self {
-        Self {
-            api_key: config.openai_key.clone(),
+    pub fn new(
+        config: &Config,
+        logger: Logger,
+        audio_recorder: AudioRecorder,
+    ) -> anyhow::Result<Self> {
+        let prices = match &config.transcription_price_table {
+            Some(path) => PriceTable::from_file(path)?,
+            None => PriceTable::default(),
+        };
+        let wake_word = match &config.wake_word_model {
+            Some(path) => {
+                let detector = EnergyDetector::new(
+                    config
+                        .silence_trimming
+                        .map_or(silence::DEFAULT_THRESHOLD_DBFS, |t| t.threshold_dbfs),
+                );
+                Some(WakeWordSpotter::new(
+                    WakeWordModel::from_file(path)?,
+                    config.wake_word_threshold,
+                    detector,
+                ))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            api_key: config.openai_key.clone().context(
+                "The OpenAI speech backend requires an API key, set JARVIS_CODE__OPENAI_KEY",
+            )?,
+            url: config
+                .realtime_url
+                .clone()
+                .unwrap_or_else(|| REALTIME_TRANSCRIPTION_URL.to_owned()),
             audio_recorder,
-        }
+            vocabulary: ProjectVocabulary::new(config.project_dir.clone()),
+            usage_ledger: UsageLedger::new(config.usage_ledger_file.clone(), prices),
+            language: match config.languages.as_slice() {
+                [only] => Some(only.clone()),
+                _ => None,
+            },
+            silence_trimming: config.silence_trimming.map(|mut trimming| {
+                let min_hangover_ms = SERVER_VAD_SILENCE_MS + MIN_HANGOVER_MARGIN_MS;
+                if trimming.hangover_ms < min_hangover_ms {
+                    // Otherwise the server would never notice the end of the turn
+                    logger.warn(format!(
+                        "Silence hangover of {} ms is too short, using {min_hangover_ms} ms",
+                        trimming.hangover_ms
+                    ));
+                    trimming.hangover_ms = min_hangover_ms;
+                }
+                trimming
+            }),
+            wake_word,
+            speech_started: None,
+            logger,
+        })
+    }
+
+    pub fn set_speech_started(&mut self, hook: SpeechStartedHook) {
+        self.speech_started = Some(hook);
+    }
+
+    /// The recorder and the format it records in, to keep listening while
+    /// the assistant is busy
+    pub fn audio_capture(&mut self) -> (&mut AudioRecorder, SoundSpec) {
+        (&mut self.audio_recorder, capture_format())
     }
 
     pub async fn listen_to_input(&mut self) -> anyhow::Result<Transcription> {
-        // OpenAI specifies that when using PCM, audio data must be 16 bit,
-        // little endian, 24kHz, 1 channel
-        let desired_format = SoundSpec::PCM {
-            format: PCMFormat::S16LE,
-            sample_rate_hz: 24000,
-            num_channels: 1,
-        };
+        let desired_format = capture_format();
+        // The user already started talking while the assistant was busy
+        let is_continuing = self.audio_recorder.is_continuing();
+        if let (true, Some(hook)) = (is_continuing, &self.speech_started) {
+            hook();
+        }
         let (sound_receiver, stop, actual_format) =
             self.audio_recorder.listen(Some(desired_format.clone()))?;
 
@@ -59,39 +146,82 @@ impl SpeechListener {
             }
         }
 
+        let sound_receiver = match self.wake_word.take() {
+            Some(spotter) if is_continuing => {
+                self.wake_word = Some(spotter);
+                sound_receiver
+            }
+            Some(mut spotter) => {
+                self.logger
+                    .info(format!("Waiting for '{}'", spotter.phrase()));
+                let (sound_receiver, spotter, detected) = tokio::task::spawn_blocking(move || {
+                    spotter.reset();
+                    let detected = sound_receiver.iter().any(|chunk| spotter.push(&chunk));
+                    (sound_receiver, spotter, detected)
+                })
+                .await
+                .context("Failed to listen for the wake phrase")?;
+                self.wake_word = Some(spotter);
+                if !detected {
+                    // The recording ended before the phrase was said
+                    return Ok(Transcription::Empty);
+                }
+                self.logger.info("Listening");
+                sound_receiver
+            }
+            None => sound_receiver,
+        };
+
+        let prompt = self
+            .vocabulary
+            .transcription_prompt()
+            .await
+            .unwrap_or_else(|err| {
+                self.logger.warn(format!(
+                    "Could not build the project vocabulary, using a generic prompt: {err:#}"
+                ));
+                BASE_PROMPT.to_owned()
+            });
+        self.logger.debug(format!("Transcription prompt: {prompt}"));
+
         let ws_stream: WebSocketStream<MaybeTlsStream<TcpStream>> =
-            create_ws(&self.api_key).await?;
+            create_ws(&self.url, &self.api_key).await?;
         let (mut ws_write, ws_read) = ws_stream.split();
 
         let session_update = TranscriptionSessionUpdate {
             type_: TranscriptionSessionUpdateType::Update,
             session: TranscriptionSessionUpdateSession {
+                include: vec![TranscriptionInclude::Logprobs],
                 input_audio_format: TranscriptionAudioFormat::PCM16,
                 input_audio_noise_reduction: TranscriptionNoiseReduction {
                     type_: NoiseReductionType::FarField,
                 },
                 input_audio_transcription: InputAudioTranscription {
-                    language: Some("en".to_owned()),
+                    language: self.language.clone(),
                     model: Some("gpt-4o-transcribe".to_owned()),
-                    prompt: Some("Expect words related to programming".to_owned()),
+                    prompt: Some(prompt),
                 },
                 // TODO semantic VAD, although preferred, is broken right now
                 // See https://community.openai.com/t/semantic-vad-might-not-be-working-with-transcription-mode/1151522/7
                 turn_detection: TranscriptionTurnDetection {
                     type_: TurnDetectionType::ServerVad,
+                    silence_duration_ms: Some(SERVER_VAD_SILENCE_MS),
                 },
             },
         };
         ws_write
-            .feed(Message::Text(
+            .send(Message::Text(
                 serde_json::to_string(&session_update).unwrap().into(),
             ))
             .await
             .context("Failed to write transcription session update")?;
 
         let mut transcription_events = Box::pin(to_event_stream(ws_read));
+        let speech_started = self.speech_started.clone().filter(|_| !is_continuing);
         let transcription_fut = tokio::spawn(async move {
-            let mut result = Ok(Transcription::Empty);
+            let mut result = Ok((Transcription::Empty, None));
+            let mut timing = UtteranceTiming::default();
+            let mut speech_stopped_at = None;
 
             while let Some(event) = transcription_events.next().await {
                 match event {
@@ -108,10 +238,41 @@ impl SpeechListener {
                         )));
                         break;
                     }
+                    Result::Ok(TranscriptionMessage::SpeechStarted(event)) => {
+                        if timing.start.is_none() {
+                            timing.item_id = event.item_id;
+                            timing.start = event.audio_start_ms.map(ms);
+                            if let Some(hook) = &speech_started {
+                                hook();
+                            }
+                        }
+                    }
+                    Result::Ok(TranscriptionMessage::SpeechStopped(event)) => {
+                        timing.end = event.audio_end_ms.map(ms);
+                        speech_stopped_at = Some(Instant::now());
+                    }
                     Result::Ok(TranscriptionMessage::TranscriptionCompleted(transcription)) => {
-                        result = Ok(Transcription::Some {
-                            text: transcription.transcript,
+                        let confidence = transcription.logprobs.map(|logprobs| Confidence {
+                            tokens: logprobs
+                                .into_iter()
+                                .map(|it| TokenConfidence {
+                                    token: it.token,
+                                    probability: it.logprob.exp(),
+                                })
+                                .collect(),
                         });
+                        if transcription.item_id.is_some() {
+                            timing.item_id = transcription.item_id;
+                        }
+                        timing.latency = speech_stopped_at.map(|at| at.elapsed());
+                        let text_result = Transcription::Some {
+                            text: transcription.transcript,
+                            confidence,
+                            // Not reported by the API
+                            language: None,
+                            timing: Some(timing),
+                        };
+                        result = Ok((text_result, transcription.usage));
                         break;
                     }
                     _ => (),
@@ -122,34 +283,130 @@ impl SpeechListener {
             result
         });
 
-        let mut audio_receiver = to_async_receiver(sound_receiver);
+        let mut trimmer = self.silence_trimming.map(|trimming| {
+            SilenceTrimmer::new(
+                EnergyDetector::new(trimming.threshold_dbfs),
+                &desired_format,
+                trimming.pre_roll_ms,
+                trimming.hangover_ms,
+            )
+        });
+        let logger = self.logger;
+        let mut audio_receiver = to_async_receiver(sound_receiver, logger);
         let consume_audio = tokio::spawn(async move {
+            let mut bytes_streamed = 0;
             let mut next_msg = audio_receiver.recv().await;
             while let Some(chunk) = next_msg {
-                let json = "{\"type\": \"input_audio_buffer.append\",\"audio\": \"".to_owned();
-                let json = json + &BASE64_STANDARD.encode(chunk);
-                let json = json + "\"}";
-                match ws_write.feed(Message::Text(json.into())).await {
-                    Result::Ok(()) => (),
-                    Err(err) => eprintln!("Could not send audio data: {err}"),
+                let chunks = match trimmer.as_mut() {
+                    Some(trimmer) => {
+                        let chunks = trimmer.push(chunk);
+                        if trimmer.has_waited_too_long() {
+                            // The server detects speech itself
+                            logger.warn(
+                                "No speech detected so far, sending all audio. \
+                                The microphone may be too quiet for the silence threshold.",
+                            );
+                            trimmer.pass_through()
+                        } else {
+                            chunks
+                        }
+                    }
+                    None => vec![chunk],
+                };
+                for chunk in chunks {
+                    bytes_streamed += chunk.len();
+                    let json = "{\"type\": \"input_audio_buffer.append\",\"audio\": \"".to_owned();
+                    let json = json + &BASE64_STANDARD.encode(chunk);
+                    let json = json + "\"}";
+                    match ws_write.feed(Message::Text(json.into())).await {
+                        Result::Ok(()) => (),
+                        Err(err) => logger.warn(format!("Could not send audio data: {err}")),
+                    }
                 }
 
                 next_msg = audio_receiver.recv().await;
             }
+            if let Some(trimmer) = trimmer {
+                logger.debug(format!(
+                    "Held back {} KB of silence",
+                    trimmer.trimmed_bytes() / 1000
+                ));
+            }
+            // Fed messages are buffered until the buffer is full, so send
+            // whatever is left once the recording has ended
+            if let Err(err) = ws_write.flush().await {
+                logger.warn(format!("Could not send audio data: {err}"));
+            }
+            bytes_streamed
         });
 
         let (transcription, sink_result) = future::join(transcription_fut, consume_audio).await;
-        transcription
+        let (transcription, usage) = transcription
             .context("Failed to run transcription")
-            .and_then(|res| res)
-            .and_then(|res| {
-                sink_result
-                    .context("Failed to send audio data")
-                    .map(|()| res)
-            })
+            .and_then(|res| res)?;
+        let bytes_streamed = sink_result.context("Failed to send audio data")?;
+
+        self.record_usage(bytes_streamed, usage.as_ref(), &desired_format);
+
+        Ok(transcription)
+    }
+
+    fn record_usage(
+        &mut self,
+        bytes_streamed: usize,
+        usage: Option<&TranscriptionUsage>,
+        format: &SoundSpec,
+    ) {
+        #[allow(clippy::cast_precision_loss)]
+        let mut record = UsageRecord {
+            utterances: 1,
+            audio_seconds: bytes_streamed as f64 / f64::from(format.bytes_per_second()),
+            bytes_streamed: bytes_streamed as u64,
+            ..UsageRecord::default()
+        };
+        match usage {
+            Some(TranscriptionUsage::Tokens {
+                input_tokens,
+                output_tokens,
+                input_token_details,
+                ..
+            }) => {
+                match input_token_details {
+                    Some(details) => {
+                        record.audio_input_tokens = details.audio_tokens;
+                        record.text_input_tokens = details.text_tokens;
+                    }
+                    None => record.audio_input_tokens = *input_tokens,
+                }
+                record.output_tokens = *output_tokens;
+            }
+            Some(TranscriptionUsage::Duration { seconds }) => record.billed_seconds = *seconds,
+            None => (),
+        }
+
+        match self.usage_ledger.record(record) {
+            Result::Ok(summary) => self.logger.info(summary),
+            Err(err) => self
+                .logger
+                .warn(format!("Could not record transcription usage: {err:#}")),
+        }
+    }
+}
+
+/// OpenAI specifies that when using PCM, audio data must be 16 bit, little
+/// endian, 24kHz, 1 channel
+fn capture_format() -> SoundSpec {
+    SoundSpec::PCM {
+        format: PCMFormat::S16LE,
+        sample_rate_hz: 24000,
+        num_channels: 1,
     }
 }
 
+fn ms(milliseconds: u32) -> Duration {
+    Duration::from_millis(milliseconds.into())
+}
+
 fn to_event_stream<S: StreamExt<Item = Result<tungstenite::Message, tungstenite::Error>> + Send>(
     ws_stream: S,
 ) -> impl Stream<Item = anyhow::Result<TranscriptionMessage>> + Send {
@@ -171,7 +428,7 @@ fn to_event_stream<S: StreamExt<Item = Result<tungstenite::Message, tungstenite:
     })
 }
 
-fn to_async_receiver<T: Send + 'static>(receiver: Receiver<T>) -> TokioReceiver<T> {
+fn to_async_receiver<T: Send + 'static>(receiver: Receiver<T>, logger: Logger) -> TokioReceiver<T> {
     // channel size chosen arbitrarily; note that an unbounded channel here
     // can lead to an issue we block all threads on the Tokio runtime.
     let (tx, rx) = channel(1024);
@@ -179,15 +436,19 @@ fn to_async_receiver<T: Send + 'static>(receiver: Receiver<T>) -> TokioReceiver<
         for x in receiver {
             match tx.send(x).await {
                 Result::Ok(()) => (),
-                Result::Err(err) => println!("Failed to send: {err}"),
+                // The turn has ended and the audio isn't needed anymore
+                Result::Err(err) => logger.debug(format!("Failed to send: {err}")),
             }
         }
     });
     rx
 }
 
-async fn create_ws(api_key: &str) -> anyhow::Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
-    let url = http::Uri::from_str("wss://api.openai.com/v1/realtime?intent=transcription")?;
+async fn create_ws(
+    url: &str,
+    api_key: &str,
+) -> anyhow::Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
+    let url = http::Uri::from_str(url).context(format!("Invalid realtime API URL {url}"))?;
     // into_client_request for Uri will set headers required for websockets
     let mut req = url.into_client_request()?;
     let headers = req.headers_mut();
@@ -352,13 +613,30 @@ impl serde::Serialize for TurnDetectionType {
 pub struct TranscriptionTurnDetection {
     #[serde(rename = "type")]
     type_: TurnDetectionType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    silence_duration_ms: Option<u32>,
+}
+
+pub enum TranscriptionInclude {
+    Logprobs,
+}
+
+impl serde::Serialize for TranscriptionInclude {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            Self::Logprobs => serializer.serialize_str("item.input_audio_transcription.logprobs"),
+        }
+    }
 }
 
 #[derive(serde::Serialize)]
 pub struct TranscriptionSessionUpdateSession {
     // client_secret
-    // include
     // modalities
+    include: Vec<TranscriptionInclude>,
 
     // input audio must be 16-bit PCM at a 24kHz sample rate, single channel, little-endian
     input_audio_format: TranscriptionAudioFormat,
@@ -456,7 +734,10 @@ pub struct ErrorEventDetail {
 pub struct SpeechBoundaryEvent {
     pub event_id: Option<String>,
     pub item_id: Option<String>,
+    /// Only set when speech has started
     pub audio_start_ms: Option<u32>,
+    /// Only set when speech has stopped
+    pub audio_end_ms: Option<u32>,
 }
 
 #[allow(clippy::struct_field_names)]
@@ -545,5 +826,36 @@ pub struct TranscriptionCompletedEvent {
     pub item_id: Option<String>,
     pub content_index: Option<i32>,
     pub transcript: String,
-    // TODO maybe add usage
+    /// Only present if requested in the session's `include` list
+    #[serde(default)]
+    pub logprobs: Option<Vec<TranscriptionLogprob>>,
+    #[serde(default)]
+    pub usage: Option<TranscriptionUsage>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TranscriptionUsage {
+    Tokens {
+        input_tokens: u64,
+        output_tokens: u64,
+        total_tokens: u64,
+        input_token_details: Option<TranscriptionInputTokenDetails>,
+    },
+    Duration {
+        seconds: f64,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TranscriptionInputTokenDetails {
+    pub audio_tokens: u64,
+    pub text_tokens: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TranscriptionLogprob {
+    pub token: String,
+    pub logprob: f64,
+    pub bytes: Vec<u8>,
 }
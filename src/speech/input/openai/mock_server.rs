@@ -0,0 +1,288 @@
+//! A local stand-in for the OpenAI realtime transcription websocket API.
+//!
+//! The [`MockRealtimeServer`] implements the subset of the protocol that the
+//! [`SpeechListener`](super::SpeechListener) uses, so the listener can be
+//! tested end-to-end without an API key or network access. Each websocket
+//! connection plays the next [`ScriptedTurn`]: once the first audio chunk
+//! arrives, the server acts as if the user spoke and paused, and then sends
+//! the scripted transcript or error.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use base64::prelude::*;
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::{WebSocketStream, accept_async, tungstenite::protocol::Message};
+
+use super::{
+    ErrorEvent, ErrorEventDetail, SessionDetail, SessionEvent, SpeechBoundaryEvent,
+    SpeechCommittedEvent, TranscriptionCompletedEvent, TranscriptionDeltaEvent,
+    TranscriptionLogprob, TranscriptionMessage,
+};
+
+/// How long the user supposedly spoke in each turn
+pub const SCRIPTED_SPEECH_MS: u32 = 1200;
+
+#[derive(Clone)]
+pub enum ScriptedTurn {
+    /// The server transcribes the audio as the concatenation of `deltas`,
+    /// with the given log probability for each delta
+    Transcript { deltas: Vec<(String, f64)> },
+    /// The server responds with an error event
+    Error { code: String, message: String },
+}
+
+impl ScriptedTurn {
+    #[must_use]
+    pub fn transcript(text: &str) -> Self {
+        Self::Transcript {
+            deltas: text
+                .split(' ')
+                .enumerate()
+                .map(|(i, word)| {
+                    let delta = if i == 0 {
+                        word.to_owned()
+                    } else {
+                        format!(" {word}")
+                    };
+                    (delta, 0.0)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// What the server has received from its clients so far.
+#[derive(Default)]
+pub struct Received {
+    /// Every `transcription_session.update` event
+    pub session_updates: Vec<serde_json::Value>,
+    /// Number of decoded audio bytes appended to the input buffer
+    pub audio_bytes: usize,
+}
+
+pub struct MockRealtimeServer {
+    url: String,
+    received: Arc<Mutex<Received>>,
+    task: JoinHandle<()>,
+}
+
+impl MockRealtimeServer {
+    /// Binds the server to a free local port and starts serving `script`, one
+    /// turn per connection. Connections beyond the end of the script are
+    /// closed right away.
+    pub async fn start(script: Vec<ScriptedTurn>) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .context("Failed to bind mock realtime server")?;
+        let url = format!("ws://{}", listener.local_addr()?);
+        let received = Arc::new(Mutex::new(Received::default()));
+
+        let received_for_task = Arc::clone(&received);
+        let task = tokio::spawn(async move {
+            let mut turns = script.into_iter();
+            let mut session_num = 0;
+            while let Ok((stream, _addr)) = listener.accept().await {
+                let Some(turn) = turns.next() else {
+                    break;
+                };
+                session_num += 1;
+                let received = Arc::clone(&received_for_task);
+                tokio::spawn(async move {
+                    if let Err(err) = serve_turn(stream, turn, session_num, &received).await {
+                        eprintln!("Mock realtime server failed to serve a session: {err:#}");
+                    }
+                });
+            }
+        });
+
+        Ok(Self {
+            url,
+            received,
+            task,
+        })
+    }
+
+    /// The URL clients should connect to, e.g. `ws://127.0.0.1:12345`
+    #[must_use]
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn received<R>(&self, f: impl FnOnce(&Received) -> R) -> R {
+        f(&self.received.lock().unwrap())
+    }
+}
+
+impl Drop for MockRealtimeServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve_turn(
+    stream: TcpStream,
+    turn: ScriptedTurn,
+    session_num: usize,
+    received: &Mutex<Received>,
+) -> anyhow::Result<()> {
+    let mut ws = accept_async(stream)
+        .await
+        .context("Websocket handshake failed")?;
+    let mut events = EventIds::new(session_num);
+
+    send(
+        &mut ws,
+        TranscriptionMessage::SessionCreated(session_event(&mut events, session_num)),
+    )
+    .await?;
+
+    let mut turn = Some(turn);
+    while let Some(msg) = ws.next().await {
+        let Message::Text(text) = msg? else {
+            continue;
+        };
+        let event: serde_json::Value = serde_json::from_str(text.as_str())
+            .context(format!("Client sent invalid JSON: {text}"))?;
+
+        match event["type"].as_str() {
+            Some("transcription_session.update") => {
+                received.lock().unwrap().session_updates.push(event);
+                send(
+                    &mut ws,
+                    TranscriptionMessage::SessionUpdated(session_event(&mut events, session_num)),
+                )
+                .await?;
+            }
+            Some("input_audio_buffer.append") => {
+                let audio = BASE64_STANDARD
+                    .decode(event["audio"].as_str().unwrap_or_default())
+                    .context("Client sent audio that is not valid base64")?;
+                received.lock().unwrap().audio_bytes += audio.len();
+
+                if let Some(turn) = turn.take() {
+                    for msg in play_turn(turn, &mut events) {
+                        send(&mut ws, msg).await?;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+fn play_turn(turn: ScriptedTurn, events: &mut EventIds) -> Vec<TranscriptionMessage> {
+    let item_id = Some(format!("item_{}", events.session_num));
+    let mut msgs = vec![
+        TranscriptionMessage::SpeechStarted(SpeechBoundaryEvent {
+            event_id: Some(events.next()),
+            item_id: item_id.clone(),
+            audio_start_ms: Some(0),
+            audio_end_ms: None,
+        }),
+        TranscriptionMessage::SpeechStopped(SpeechBoundaryEvent {
+            event_id: Some(events.next()),
+            item_id: item_id.clone(),
+            audio_start_ms: None,
+            audio_end_ms: Some(SCRIPTED_SPEECH_MS),
+        }),
+        TranscriptionMessage::SpeechCommitted(SpeechCommittedEvent {
+            event_id: Some(events.next()),
+            item_id: item_id.clone(),
+            previous_item_id: None,
+        }),
+    ];
+
+    match turn {
+        ScriptedTurn::Transcript { deltas } => {
+            let transcript = deltas.iter().map(|(delta, _)| delta.as_str()).collect();
+            let logprobs = deltas
+                .iter()
+                .map(|(delta, logprob)| TranscriptionLogprob {
+                    token: delta.clone(),
+                    logprob: *logprob,
+                    bytes: delta.as_bytes().to_vec(),
+                })
+                .collect();
+            msgs.extend(deltas.into_iter().map(|(delta, _)| {
+                TranscriptionMessage::TranscriptionDelta(TranscriptionDeltaEvent {
+                    event_id: Some(events.next()),
+                    item_id: item_id.clone(),
+                    content_index: Some(0),
+                    delta,
+                })
+            }));
+            msgs.push(TranscriptionMessage::TranscriptionCompleted(
+                TranscriptionCompletedEvent {
+                    event_id: Some(events.next()),
+                    item_id,
+                    content_index: Some(0),
+                    transcript,
+                    logprobs: Some(logprobs),
+                    usage: None,
+                },
+            ));
+        }
+        ScriptedTurn::Error { code, message } => {
+            msgs.push(TranscriptionMessage::Error(ErrorEvent {
+                event_id: events.next(),
+                error: ErrorEventDetail {
+                    type_: "invalid_request_error".to_owned(),
+                    event_id: None,
+                    code: Some(code),
+                    message,
+                    param: None,
+                },
+            }));
+        }
+    }
+
+    msgs
+}
+
+fn session_event(events: &mut EventIds, session_num: usize) -> SessionEvent {
+    SessionEvent {
+        event_id: events.next(),
+        session: SessionDetail {
+            id: format!("sess_{session_num}"),
+            input_audio_format: "pcm16".to_owned(),
+            input_audio_noise_reduction: None,
+            input_audio_transcription: None,
+            instructions: None,
+            max_response_output_tokens: None,
+        },
+    }
+}
+
+async fn send(
+    ws: &mut WebSocketStream<TcpStream>,
+    msg: TranscriptionMessage,
+) -> anyhow::Result<()> {
+    let json = serde_json::to_string(&msg).context("Failed to serialize mock event")?;
+    ws.send(Message::Text(json.into()))
+        .await
+        .context("Failed to send mock event")
+}
+
+struct EventIds {
+    session_num: usize,
+    count: usize,
+}
+
+impl EventIds {
+    fn new(session_num: usize) -> Self {
+        Self {
+            session_num,
+            count: 0,
+        }
+    }
+
+    fn next(&mut self) -> String {
+        self.count += 1;
+        format!("event_{}_{}", self.session_num, self.count)
+    }
+}
@@ -0,0 +1,353 @@
+//! Spelling out identifiers that can't be dictated reliably, letter by
+//! letter or with the NATO alphabet.
+//!
+//! Saying "start spelling" switches to spelling mode, in which words are
+//! interpreted as letters, digits and modifiers like "capital" or
+//! "underscore", until "stop spelling" is said. The spelled string is
+//! inserted into the utterance at that point, so "rename it to start
+//! spelling capital x-ray underscore four two stop spelling" becomes
+//! "rename it to X_42".
+
+const START_PHRASES: &[&[&str]] = &[
+    &["start", "spelling"],
+    &["begin", "spelling"],
+    &["spelling", "mode"],
+    &["spell", "mode"],
+];
+
+const STOP_PHRASES: &[&[&str]] = &[
+    &["stop", "spelling"],
+    &["end", "spelling"],
+    &["done", "spelling"],
+    &["finish", "spelling"],
+    &["spelling", "done"],
+];
+
+const NATO_ALPHABET: &[(&str, char)] = &[
+    ("alpha", 'a'),
+    ("alfa", 'a'),
+    ("bravo", 'b'),
+    ("charlie", 'c'),
+    ("delta", 'd'),
+    ("echo", 'e'),
+    ("foxtrot", 'f'),
+    ("golf", 'g'),
+    ("hotel", 'h'),
+    ("india", 'i'),
+    ("juliet", 'j'),
+    ("juliett", 'j'),
+    ("kilo", 'k'),
+    ("lima", 'l'),
+    ("mike", 'm'),
+    ("november", 'n'),
+    ("oscar", 'o'),
+    ("papa", 'p'),
+    ("quebec", 'q'),
+    ("romeo", 'r'),
+    ("sierra", 's'),
+    ("tango", 't'),
+    ("uniform", 'u'),
+    ("victor", 'v'),
+    ("whiskey", 'w'),
+    ("whisky", 'w'),
+    ("xray", 'x'),
+    ("yankee", 'y'),
+    ("zulu", 'z'),
+];
+
+/// How transcription models tend to write letters that are said on their own
+const LETTER_NAMES: &[(&str, char)] = &[
+    ("ay", 'a'),
+    ("bee", 'b'),
+    ("be", 'b'),
+    ("cee", 'c'),
+    ("see", 'c'),
+    ("sea", 'c'),
+    ("dee", 'd'),
+    ("ee", 'e'),
+    ("ef", 'f'),
+    ("eff", 'f'),
+    ("gee", 'g'),
+    ("aitch", 'h'),
+    ("eye", 'i'),
+    ("jay", 'j'),
+    ("kay", 'k'),
+    ("el", 'l'),
+    ("ell", 'l'),
+    ("em", 'm'),
+    ("en", 'n'),
+    ("oh", 'o'),
+    ("pee", 'p'),
+    ("cue", 'q'),
+    ("queue", 'q'),
+    ("ar", 'r'),
+    ("are", 'r'),
+    ("ess", 's'),
+    ("tee", 't'),
+    ("tea", 't'),
+    ("you", 'u'),
+    ("vee", 'v'),
+    ("ex", 'x'),
+    ("why", 'y'),
+    ("zed", 'z'),
+    ("zee", 'z'),
+];
+
+const DIGITS: &[(&str, char)] = &[
+    ("zero", '0'),
+    ("one", '1'),
+    ("two", '2'),
+    ("three", '3'),
+    ("four", '4'),
+    ("five", '5'),
+    ("six", '6'),
+    ("seven", '7'),
+    ("eight", '8'),
+    ("nine", '9'),
+    ("niner", '9'),
+];
+
+const SYMBOLS: &[(&str, char)] = &[
+    ("underscore", '_'),
+    ("dash", '-'),
+    ("hyphen", '-'),
+    ("dot", '.'),
+    ("space", ' '),
+];
+
+const CAPITAL_MODIFIERS: &[&str] = &["capital", "cap", "uppercase"];
+
+/// A part of an utterance
+#[derive(Debug, PartialEq, Eq)]
+pub enum Segment {
+    /// Regular speech, still to be normalized
+    Spoken(String),
+    /// An exact string from spelling mode
+    Spelled(String),
+}
+
+/// Collects the segments of an utterance, which may span several
+/// transcriptions while spelling.
+#[derive(Default)]
+pub struct SpellingMode {
+    is_spelling: bool,
+    segments: Vec<Segment>,
+    /// Words in spelling mode that are neither letters, digits nor modifiers
+    unrecognized: Vec<String>,
+}
+
+impl SpellingMode {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn is_spelling(&self) -> bool {
+        self.is_spelling
+    }
+
+    /// Whether `text` would switch to spelling mode
+    #[must_use]
+    pub fn starts_spelling(text: &str) -> bool {
+        let keys = keys(text);
+        (0..keys.len()).any(|i| phrase_at(&keys, i, START_PHRASES).is_some())
+    }
+
+    /// Adds the next transcription and returns whether the utterance is
+    /// complete, i.e. spelling mode has been left again.
+    pub fn push(&mut self, text: &str) -> bool {
+        let tokens: Vec<&str> = text.split_whitespace().collect();
+        let keys = keys(text);
+        let mut spoken = Vec::new();
+        let mut spelled = Vec::new();
+
+        let mut i = 0;
+        while i < tokens.len() {
+            let phrases = if self.is_spelling {
+                STOP_PHRASES
+            } else {
+                START_PHRASES
+            };
+            if let Some(len) = phrase_at(&keys, i, phrases) {
+                self.flush(&mut spoken, &mut spelled);
+                self.is_spelling = !self.is_spelling;
+                i += len;
+            } else if self.is_spelling {
+                spelled.push(tokens[i]);
+                i += 1;
+            } else {
+                spoken.push(tokens[i]);
+                i += 1;
+            }
+        }
+        self.flush(&mut spoken, &mut spelled);
+
+        !self.is_spelling
+    }
+
+    /// Returns the segments of the utterance and words that could not be
+    /// spelled, and starts over.
+    pub fn take(&mut self) -> (Vec<Segment>, Vec<String>) {
+        self.is_spelling = false;
+        (
+            std::mem::take(&mut self.segments),
+            std::mem::take(&mut self.unrecognized),
+        )
+    }
+
+    fn flush(&mut self, spoken: &mut Vec<&str>, spelled: &mut Vec<&str>) {
+        if !spoken.is_empty() {
+            self.segments.push(Segment::Spoken(spoken.join(" ")));
+            spoken.clear();
+        }
+        if !spelled.is_empty() {
+            let (text, unrecognized) = spell(&words(&spelled.join(" ")));
+            // Letters spelled across several transcriptions form one string
+            match self.segments.last_mut() {
+                Some(Segment::Spelled(previous)) => previous.push_str(&text),
+                _ => self.segments.push(Segment::Spelled(text)),
+            }
+            self.unrecognized.extend(unrecognized);
+            spelled.clear();
+        }
+    }
+}
+
+/// Turns spelled words into the string they stand for. Returns the string
+/// and the words that could not be interpreted.
+#[must_use]
+pub fn spell(words: &[String]) -> (String, Vec<String>) {
+    let mut text = String::new();
+    let mut unrecognized = Vec::new();
+    let mut capitalize = false;
+
+    let mut i = 0;
+    while i < words.len() {
+        let word = words[i].to_lowercase();
+        i += 1;
+
+        if CAPITAL_MODIFIERS.contains(&word.as_str()) {
+            capitalize = true;
+            continue;
+        }
+        if word == "x" && words.get(i).is_some_and(|w| w.eq_ignore_ascii_case("ray")) {
+            // "x-ray" is split into two words
+            i += 1;
+            push_letter(&mut text, 'x', &mut capitalize);
+            continue;
+        }
+
+        let mut chars = word.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_alphabetic() => {
+                push_letter(&mut text, c, &mut capitalize);
+                continue;
+            }
+            _ => (),
+        }
+        if word.chars().all(|c| c.is_ascii_digit()) {
+            text.push_str(&word);
+            continue;
+        }
+        if let Some(c) = lookup(&word, NATO_ALPHABET).or_else(|| lookup(&word, LETTER_NAMES)) {
+            push_letter(&mut text, c, &mut capitalize);
+        } else if let Some(c) = lookup(&word, DIGITS).or_else(|| lookup(&word, SYMBOLS)) {
+            text.push(c);
+        } else {
+            unrecognized.push(words[i - 1].clone());
+        }
+    }
+
+    (text, unrecognized)
+}
+
+fn push_letter(text: &mut String, letter: char, capitalize: &mut bool) {
+    if *capitalize {
+        text.push(letter.to_ascii_uppercase());
+        *capitalize = false;
+    } else {
+        text.push(letter);
+    }
+}
+
+fn lookup(word: &str, table: &[(&str, char)]) -> Option<char> {
+    table.iter().find(|(w, _)| *w == word).map(|(_, c)| *c)
+}
+
+/// Splits `text` into words, dropping the punctuation a transcription model
+/// adds between spelled letters, like in "A, B. C-D"
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| c.is_whitespace() || matches!(c, ',' | '.' | '-' | '!' | '?'))
+        .filter(|w| !w.is_empty())
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+/// The whitespace separated tokens of `text` without surrounding punctuation,
+/// for matching phrases
+fn keys(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .map(|t| t.trim_matches(|c: char| !c.is_alphanumeric()).to_owned())
+        .collect()
+}
+
+/// The number of words of the phrase starting at `words[i]`, if any
+fn phrase_at(words: &[String], i: usize, phrases: &[&[&str]]) -> Option<usize> {
+    phrases
+        .iter()
+        .find(|phrase| {
+            words.len() >= i + phrase.len()
+                && phrase
+                    .iter()
+                    .zip(&words[i..])
+                    .all(|(p, w)| w.eq_ignore_ascii_case(p))
+        })
+        .map(|phrase| phrase.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spells_words() {
+        let cases = [
+            ("capital x-ray underscore four two", "X_42"),
+            ("Alpha, Bravo, Charlie.", "abc"),
+            ("cap hotel t t p", "Http"),
+            ("a b c one two 3", "abc123"),
+            ("sierra tango dee underscore i oh", "std_io"),
+            ("capital bee 64", "B64"),
+        ];
+
+        for (spoken, expected) in cases {
+            let words: Vec<String> = words(spoken);
+            assert_eq!(
+                spell(&words),
+                (expected.to_owned(), vec![]),
+                "input: {spoken:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn inserts_spelled_text_into_utterance() {
+        let mut mode = SpellingMode::new();
+
+        assert!(!mode.push("Rename it to start spelling capital foxtrot"));
+        assert!(mode.is_spelling());
+        assert!(mode.push("oscar oscar banana stop spelling please"));
+
+        let (segments, unrecognized) = mode.take();
+        assert_eq!(
+            segments,
+            vec![
+                Segment::Spoken("Rename it to".to_owned()),
+                Segment::Spelled("Foo".to_owned()),
+                Segment::Spoken("please".to_owned()),
+            ]
+        );
+        assert_eq!(unrecognized, vec!["banana".to_owned()]);
+    }
+}
@@ -0,0 +1,302 @@
+//! Keeps track of how much audio is sent for transcription and what it costs.
+//!
+//! Usage is accumulated per session in memory and per day in a JSON ledger
+//! file, which maps UTC dates to the usage of that day.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::ops::AddAssign;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UsageRecord {
+    pub utterances: u64,
+    pub audio_seconds: f64,
+    pub bytes_streamed: u64,
+    /// Tokens as reported by the server, if it bills by tokens
+    pub audio_input_tokens: u64,
+    pub text_input_tokens: u64,
+    pub output_tokens: u64,
+    /// Seconds as reported by the server, if it bills by duration
+    pub billed_seconds: f64,
+}
+
+impl AddAssign for UsageRecord {
+    fn add_assign(&mut self, other: Self) {
+        self.utterances += other.utterances;
+        self.audio_seconds += other.audio_seconds;
+        self.bytes_streamed += other.bytes_streamed;
+        self.audio_input_tokens += other.audio_input_tokens;
+        self.text_input_tokens += other.text_input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.billed_seconds += other.billed_seconds;
+    }
+}
+
+/// Prices in US dollars. The defaults are the list prices of
+/// `gpt-4o-transcribe`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PriceTable {
+    pub audio_input_per_million_tokens: f64,
+    pub text_input_per_million_tokens: f64,
+    pub output_per_million_tokens: f64,
+    /// Used if the server bills by duration, or doesn't report usage at all
+    pub per_minute: f64,
+}
+
+impl Default for PriceTable {
+    fn default() -> Self {
+        Self {
+            audio_input_per_million_tokens: 6.0,
+            text_input_per_million_tokens: 2.5,
+            output_per_million_tokens: 10.0,
+            per_minute: 0.006,
+        }
+    }
+}
+
+impl PriceTable {
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let json = fs::read_to_string(path).context(format!(
+            "Failed to read price table from {}",
+            path.display()
+        ))?;
+        serde_json::from_str(&json).context(format!(
+            "Failed to parse price table from {}",
+            path.display()
+        ))
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    #[must_use]
+    pub fn estimate(&self, usage: &UsageRecord) -> f64 {
+        let tokens = usage.audio_input_tokens + usage.text_input_tokens + usage.output_tokens;
+        if tokens > 0 {
+            (usage.audio_input_tokens as f64 * self.audio_input_per_million_tokens
+                + usage.text_input_tokens as f64 * self.text_input_per_million_tokens
+                + usage.output_tokens as f64 * self.output_per_million_tokens)
+                / 1_000_000.0
+        } else if usage.billed_seconds > 0.0 {
+            usage.billed_seconds / 60.0 * self.per_minute
+        } else {
+            usage.audio_seconds / 60.0 * self.per_minute
+        }
+    }
+}
+
+pub struct UsageLedger {
+    /// Without a file, usage is only tracked for the current session
+    file: Option<PathBuf>,
+    prices: PriceTable,
+    session: UsageRecord,
+    session_cost: f64,
+}
+
+impl UsageLedger {
+    #[must_use]
+    pub fn new(file: Option<PathBuf>, prices: PriceTable) -> Self {
+        Self {
+            file,
+            prices,
+            session: UsageRecord::default(),
+            session_cost: 0.0,
+        }
+    }
+
+    /// Adds the usage of a single utterance to the session and to today's
+    /// entry in the ledger file, and returns a human readable summary.
+    pub fn record(&mut self, utterance: UsageRecord) -> anyhow::Result<String> {
+        let utterance_cost = self.prices.estimate(&utterance);
+        self.session += utterance;
+        // Summing up the costs instead of estimating the totals, as the
+        // billing method may differ between utterances
+        self.session_cost += utterance_cost;
+
+        let mut summary = format!(
+            "Transcribed {:.1} s of audio ({} KB) for ~${utterance_cost:.4}, \
+            {:.1} s for ${:.4} in this session",
+            utterance.audio_seconds,
+            utterance.bytes_streamed / 1000,
+            self.session.audio_seconds,
+            self.session_cost,
+        );
+
+        if let Some(file) = &self.file {
+            let mut days = read_ledger(file)?;
+            let today = days.entry(utc_date(SystemTime::now())).or_default();
+            today.usage += utterance;
+            today.estimated_cost += utterance_cost;
+            summary += &format!(", ${:.4} today", today.estimated_cost);
+            write_ledger(file, &days)?;
+        }
+
+        Ok(summary)
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct LedgerDay {
+    #[serde(flatten)]
+    usage: UsageRecord,
+    estimated_cost: f64,
+}
+
+fn read_ledger(file: &Path) -> anyhow::Result<BTreeMap<String, LedgerDay>> {
+    if !file.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let json = fs::read_to_string(file)
+        .context(format!("Failed to read usage ledger {}", file.display()))?;
+    serde_json::from_str(&json).context(format!("Failed to parse usage ledger {}", file.display()))
+}
+
+fn write_ledger(file: &Path, days: &BTreeMap<String, LedgerDay>) -> anyhow::Result<()> {
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir).context(format!("Failed to create directory {}", dir.display()))?;
+    }
+    let json = serde_json::to_string_pretty(days).context("Failed to serialize usage ledger")?;
+    fs::write(file, json).context(format!("Failed to write usage ledger {}", file.display()))
+}
+
+/// Formats the UTC date of `time` as `YYYY-MM-DD`.
+fn utc_date(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    #[allow(clippy::cast_possible_wrap)]
+    let days = (secs / 86_400) as i64;
+
+    // Converts days since 1970-01-01 to a civil date, see
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn formats_utc_dates() {
+        let cases = [
+            (0, "1970-01-01"),
+            (86_399, "1970-01-01"),
+            (86_400, "1970-01-02"),
+            (68_169_600, "1972-02-29"),
+            (951_782_400, "2000-02-29"),
+            (951_868_799, "2000-02-29"),
+            (951_868_800, "2000-03-01"),
+            (1_704_067_199, "2023-12-31"),
+            (1_704_067_200, "2024-01-01"),
+            (1_709_164_800, "2024-02-29"),
+            // 2100 isn't a leap year
+            (4_107_542_399, "2100-02-28"),
+            (4_107_542_400, "2100-03-01"),
+        ];
+
+        for (secs, expected) in cases {
+            let time = UNIX_EPOCH + Duration::from_secs(secs);
+            assert_eq!(utc_date(time), expected, "seconds: {secs}");
+        }
+    }
+
+    #[test]
+    fn estimates_costs_by_the_billing_method() {
+        let prices = PriceTable {
+            audio_input_per_million_tokens: 6.0,
+            text_input_per_million_tokens: 2.0,
+            output_per_million_tokens: 10.0,
+            per_minute: 0.006,
+        };
+        let cases = [
+            // Billed by tokens, the durations are ignored
+            (
+                UsageRecord {
+                    audio_input_tokens: 1_000_000,
+                    text_input_tokens: 500_000,
+                    output_tokens: 100_000,
+                    billed_seconds: 60.0,
+                    audio_seconds: 60.0,
+                    ..UsageRecord::default()
+                },
+                8.0,
+            ),
+            // Billed by duration
+            (
+                UsageRecord {
+                    billed_seconds: 30.0,
+                    audio_seconds: 60.0,
+                    ..UsageRecord::default()
+                },
+                0.003,
+            ),
+            // No usage reported
+            (
+                UsageRecord {
+                    audio_seconds: 120.0,
+                    ..UsageRecord::default()
+                },
+                0.012,
+            ),
+            (UsageRecord::default(), 0.0),
+        ];
+
+        for (usage, expected) in cases {
+            let cost = prices.estimate(&usage);
+            assert!((cost - expected).abs() < 1e-9, "{usage:?}: {cost}");
+        }
+    }
+
+    #[test]
+    fn accumulates_usage_in_the_ledger() {
+        let file = std::env::temp_dir().join(format!(
+            "jarvis_code_usage_ledger_{}.json",
+            std::process::id()
+        ));
+        let earlier =
+            r#"{"2020-01-01": {"utterances": 3, "audio_seconds": 9.0, "estimated_cost": 0.5}}"#;
+        fs::write(&file, earlier).unwrap();
+        let utterance = UsageRecord {
+            utterances: 1,
+            audio_seconds: 30.0,
+            bytes_streamed: 960_000,
+            ..UsageRecord::default()
+        };
+
+        let mut ledger = UsageLedger::new(Some(file.clone()), PriceTable::default());
+        ledger.record(utterance).unwrap();
+        let summary = ledger.record(utterance).unwrap();
+        let days = read_ledger(&file).unwrap();
+        fs::remove_file(&file).unwrap();
+
+        assert_eq!(
+            summary,
+            "Transcribed 30.0 s of audio (960 KB) for ~$0.0030, \
+            60.0 s for $0.0060 in this session, $0.0060 today"
+        );
+        assert_eq!(days.len(), 2);
+        assert_eq!(days["2020-01-01"].usage.utterances, 3);
+        let today = &days[&utc_date(SystemTime::now())];
+        assert_eq!(today.usage.utterances, 2);
+        assert_eq!(today.usage.bytes_streamed, 1_920_000);
+        assert!((today.estimated_cost - 0.006).abs() < 1e-9);
+    }
+}
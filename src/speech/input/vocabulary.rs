@@ -0,0 +1,311 @@
+//! Builds a transcription prompt from the vocabulary of the project the user
+//! is working on.
+//!
+//! Transcription models do much better on names like `AudioRecorder` or
+//! `listen_to_input` if they are told to expect them. The [`ProjectVocabulary`]
+//! scans the working repository for Cargo package names, module names, public
+//! type and function identifiers and file names, ranks them and renders them
+//! into a prompt of bounded length.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::Context;
+
+pub(super) const BASE_PROMPT: &str = "Expect words related to programming";
+
+/// Upper bound for the length of the generated prompt. The transcription
+/// model only considers a limited number of prompt tokens anyway.
+const MAX_PROMPT_CHARS: usize = 800;
+
+/// Files larger than this are most likely generated or data files.
+const MAX_FILE_BYTES: u64 = 512 * 1024;
+
+/// Stop scanning huge directory trees at some point, so a session doesn't
+/// take forever to start.
+const MAX_FILES: usize = 5000;
+
+const IGNORED_DIRS: [&str; 2] = ["target", "node_modules"];
+
+/// Identifiers that are too common to be worth spending prompt space on.
+const IGNORED_TERMS: [&str; 12] = [
+    "new", "main", "lib", "mod", "default", "from", "into", "fmt", "build", "test", "tests", "self",
+];
+
+#[derive(Clone, Copy)]
+enum TermKind {
+    Package,
+    Type,
+    Function,
+    Module,
+    FileName,
+}
+
+impl TermKind {
+    fn weight(self) -> u32 {
+        match self {
+            Self::Package => 8,
+            Self::Type => 4,
+            Self::Module | Self::Function => 2,
+            Self::FileName => 1,
+        }
+    }
+}
+
+/// Cheap summary of the scanned files, used to notice when the project has
+/// changed and the vocabulary needs to be rebuilt.
+#[derive(PartialEq, Eq)]
+struct Fingerprint {
+    num_files: usize,
+    latest_modification: Option<SystemTime>,
+}
+
+pub struct ProjectVocabulary {
+    root: PathBuf,
+    fingerprint: Option<Fingerprint>,
+    prompt: String,
+}
+
+impl ProjectVocabulary {
+    #[must_use]
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            fingerprint: None,
+            prompt: BASE_PROMPT.to_owned(),
+        }
+    }
+
+    /// Returns the transcription prompt for a new session, rescanning the
+    /// project if any file has been added, removed or modified since the last
+    /// call. The scan runs on a blocking thread, as large projects take a
+    /// while.
+    pub async fn transcription_prompt(&mut self) -> anyhow::Result<String> {
+        let root = self.root.clone();
+        let files = tokio::task::spawn_blocking(move || collect_files(&root))
+            .await
+            .context("Failed to scan the project")??;
+        let fingerprint = Fingerprint {
+            num_files: files.len(),
+            latest_modification: files.iter().filter_map(|(_, modified)| *modified).max(),
+        };
+
+        if self.fingerprint.as_ref() != Some(&fingerprint) {
+            self.prompt = tokio::task::spawn_blocking(move || {
+                let paths: Vec<&Path> = files.iter().map(|(path, _)| path.as_path()).collect();
+                render_prompt(&rank_terms(&paths))
+            })
+            .await
+            .context("Failed to scan the project")?;
+            self.fingerprint = Some(fingerprint);
+        }
+
+        Ok(self.prompt.clone())
+    }
+}
+
+fn collect_files(root: &Path) -> anyhow::Result<Vec<(PathBuf, Option<SystemTime>)>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        let entries = fs::read_dir(&dir).context(format!(
+            "Failed to read directory {} while scanning the project",
+            dir.display()
+        ))?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with('.') {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+
+            if metadata.is_dir() {
+                if !IGNORED_DIRS.contains(&name.as_ref()) {
+                    dirs.push(path);
+                }
+            } else if is_relevant_file(&path) && metadata.len() <= MAX_FILE_BYTES {
+                files.push((path, metadata.modified().ok()));
+                if files.len() >= MAX_FILES {
+                    return Ok(files);
+                }
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+fn is_relevant_file(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name == "Cargo.toml")
+        || path.extension().is_some_and(|ext| ext == "rs")
+}
+
+fn rank_terms(files: &[&Path]) -> Vec<String> {
+    let mut scores: HashMap<String, u32> = HashMap::new();
+    let mut add = |term: &str, kind: TermKind| {
+        if term.len() < 3 || IGNORED_TERMS.contains(&term) {
+            return;
+        }
+        *scores.entry(term.to_owned()).or_default() += kind.weight();
+    };
+
+    for path in files {
+        let Ok(content) = fs::read_to_string(path) else {
+            continue;
+        };
+
+        if path.file_name().is_some_and(|name| name == "Cargo.toml") {
+            if let Some(name) = cargo_package_name(&content) {
+                add(&name, TermKind::Package);
+                // Crate names are used with underscores in code
+                add(&name.replace('-', "_"), TermKind::Package);
+            }
+            continue;
+        }
+
+        if let Some(stem) = path.file_stem() {
+            add(&stem.to_string_lossy(), TermKind::FileName);
+        }
+        for line in content.lines() {
+            if let Some((kind, ident)) = declared_identifier(line) {
+                add(ident, kind);
+            }
+        }
+    }
+
+    let mut terms: Vec<(String, u32)> = scores.into_iter().collect();
+    // Compound identifiers are the ones a transcription model is least
+    // likely to get right on its own, so they get a bonus.
+    terms.sort_by_key(|(term, score)| {
+        let bonus = if is_compound(term) { 2 } else { 1 };
+        (std::cmp::Reverse(score * bonus), term.clone())
+    });
+    terms.into_iter().map(|(term, _)| term).collect()
+}
+
+fn cargo_package_name(manifest: &str) -> Option<String> {
+    let mut in_package = false;
+    for line in manifest.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_package = line == "[package]";
+        } else if in_package {
+            if let Some((key, value)) = line.split_once('=') {
+                if key.trim() == "name" {
+                    return Some(value.trim().trim_matches('"').to_owned());
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Extracts the identifier from a line declaring a module, or a public type
+/// or function.
+fn declared_identifier(line: &str) -> Option<(TermKind, &str)> {
+    let line = line.trim_start();
+    let (is_pub, rest) = if let Some(rest) = line.strip_prefix("pub ") {
+        (true, rest)
+    } else if let Some(rest) = line.strip_prefix("pub(") {
+        (true, rest.split_once(") ")?.1)
+    } else {
+        (false, line)
+    };
+
+    let mut rest = rest;
+    for qualifier in ["async ", "const ", "unsafe "] {
+        rest = rest.strip_prefix(qualifier).unwrap_or(rest);
+    }
+
+    let (keyword, rest) = rest.split_once(' ')?;
+    let kind = match keyword {
+        "mod" => TermKind::Module,
+        "struct" | "enum" | "trait" | "type" if is_pub => TermKind::Type,
+        "fn" if is_pub => TermKind::Function,
+        _ => return None,
+    };
+
+    let end = rest
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len());
+    let ident = &rest[..end];
+    (!ident.is_empty()).then_some((kind, ident))
+}
+
+fn is_compound(term: &str) -> bool {
+    term.contains('_') || term.contains('-') || term.chars().skip(1).any(|c| c.is_uppercase())
+}
+
+fn render_prompt(terms: &[String]) -> String {
+    if terms.is_empty() {
+        return BASE_PROMPT.to_owned();
+    }
+
+    let mut prompt = format!("{BASE_PROMPT}. Names used in the current project: ");
+    let mut first = true;
+    for term in terms {
+        let separator = if first { "" } else { ", " };
+        if prompt.len() + separator.len() + term.len() > MAX_PROMPT_CHARS {
+            break;
+        }
+        prompt.push_str(separator);
+        prompt.push_str(term);
+        first = false;
+    }
+    prompt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranks_the_terms_of_a_project() {
+        let root =
+            std::env::temp_dir().join(format!("jarvis_code_vocabulary_{}", std::process::id()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        let files = [
+            (
+                "Cargo.toml",
+                "[package]\nname = \"voice-tool\"\n\n[dependencies]\nname = \"ignored\"\n",
+            ),
+            (
+                "src/recorder.rs",
+                "pub struct AudioRecorder;\nstruct Private;\npub fn listen_to_input() {}\nfn new() {}\n",
+            ),
+        ];
+        for (name, content) in files {
+            fs::write(root.join(name), content).unwrap();
+        }
+
+        let paths: Vec<PathBuf> = files.iter().map(|(name, _)| root.join(name)).collect();
+        let paths: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+        let terms = rank_terms(&paths);
+        let prompt = render_prompt(&terms);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(
+            terms,
+            [
+                "voice-tool",
+                "voice_tool",
+                "AudioRecorder",
+                "listen_to_input",
+                "recorder"
+            ]
+        );
+        assert_eq!(
+            prompt,
+            "Expect words related to programming. Names used in the current project: \
+             voice-tool, voice_tool, AudioRecorder, listen_to_input, recorder"
+        );
+        assert_eq!(render_prompt(&[]), BASE_PROMPT);
+    }
+}
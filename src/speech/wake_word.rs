@@ -0,0 +1,447 @@
+//! A local keyword spotter, so audio is only streamed to a transcription
+//! service after the user has said the wake phrase.
+//!
+//! Utterances of the wake phrase recorded during enrollment are stored as
+//! templates of MFCC frames. The capture stream is compared against them with
+//! dynamic time warping, which tolerates the phrase being said faster or
+//! slower than during enrollment.
+
+pub mod mfcc;
+
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::speech::audio::silence::{EnergyDetector, level_dbfs};
+
+use mfcc::{FRAME_LEN, Frame, HOP_LEN, MfccExtractor};
+
+/// Used if the threshold can't be calibrated, because there's only a single
+/// template
+const DEFAULT_THRESHOLD: f32 = 12.0;
+
+/// Calibrated thresholds are this much above the largest distance between
+/// two enrolled utterances
+const THRESHOLD_MARGIN: f32 = 1.3;
+
+/// Frames between comparisons of the capture stream against the templates
+const CHECK_EVERY_FRAMES: usize = 5;
+
+/// The stream window compared against a template is this much longer than
+/// the template, so the phrase can be said slower than during enrollment
+const WINDOW_STRETCH: f32 = 1.5;
+
+/// Templates of a wake phrase, as stored in the model file
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WakeWordModel {
+    pub phrase: String,
+    /// Detections need a DTW distance below this
+    pub threshold: f32,
+    templates: Vec<Vec<Frame>>,
+}
+
+impl WakeWordModel {
+    /// Builds templates from recordings of the wake phrase, as 16 bit little
+    /// endian mono PCM at 24 kHz. Silence around the phrase is cut off using
+    /// `detector`.
+    pub fn enroll(
+        phrase: String,
+        recordings: &[Vec<u8>],
+        detector: EnergyDetector,
+    ) -> anyhow::Result<Self> {
+        let extractor = MfccExtractor::new();
+        let templates = recordings
+            .iter()
+            .enumerate()
+            .map(|(i, recording)| {
+                let speech = trim_to_speech(recording, detector)
+                    .context(format!("No speech found in recording {}", i + 1))?;
+                let mut frames = extractor.frames(&to_samples(speech));
+                mfcc::normalize(&mut frames);
+                Ok(frames)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if templates.is_empty() {
+            anyhow::bail!("At least one recording of the wake phrase is required");
+        }
+
+        Ok(Self {
+            phrase,
+            threshold: calibrate_threshold(&templates),
+            templates,
+        })
+    }
+
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let json = fs::read_to_string(path).context(format!(
+            "Failed to read wake word model from {}",
+            path.display()
+        ))?;
+        serde_json::from_str(&json).context(format!(
+            "Failed to parse wake word model from {}",
+            path.display()
+        ))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let json = serde_json::to_string(self).context("Failed to serialize wake word model")?;
+        fs::write(path, json).context(format!(
+            "Failed to write wake word model to {}",
+            path.display()
+        ))
+    }
+
+    #[must_use]
+    pub fn num_templates(&self) -> usize {
+        self.templates.len()
+    }
+}
+
+/// Listens for the wake phrase in a stream of audio chunks.
+pub struct WakeWordSpotter {
+    model: WakeWordModel,
+    threshold: f32,
+    detector: EnergyDetector,
+    extractor: MfccExtractor,
+    /// Samples not yet covered by a complete frame
+    pending: Vec<i16>,
+    /// Recent frames and whether they contain speech
+    frames: VecDeque<(Frame, bool)>,
+    max_frames: usize,
+    frames_since_check: usize,
+    frames_since_speech: usize,
+    best_distance: f32,
+}
+
+impl WakeWordSpotter {
+    /// Without a `threshold`, the one stored in the model is used. Frames
+    /// below the level of `detector` don't trigger comparisons, which saves
+    /// CPU while it's quiet.
+    #[must_use]
+    pub fn new(model: WakeWordModel, threshold: Option<f32>, detector: EnergyDetector) -> Self {
+        #[allow(
+            clippy::cast_precision_loss,
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss
+        )]
+        let max_frames = model
+            .templates
+            .iter()
+            .map(|t| (t.len() as f32 * WINDOW_STRETCH) as usize)
+            .max()
+            .unwrap_or_default();
+
+        Self {
+            threshold: threshold.unwrap_or(model.threshold),
+            model,
+            detector,
+            extractor: MfccExtractor::new(),
+            pending: Vec::new(),
+            frames: VecDeque::new(),
+            max_frames,
+            frames_since_check: 0,
+            frames_since_speech: usize::MAX,
+            best_distance: f32::INFINITY,
+        }
+    }
+
+    #[must_use]
+    pub fn phrase(&self) -> &str {
+        &self.model.phrase
+    }
+
+    /// Takes the next chunk of 16 bit little endian mono PCM at 24 kHz and
+    /// returns whether the wake phrase has just been said.
+    pub fn push(&mut self, chunk: &[u8]) -> bool {
+        self.pending.extend(to_samples(chunk));
+
+        let mut detected = false;
+        let mut consumed = 0;
+        while self.pending.len() - consumed >= FRAME_LEN {
+            let samples = &self.pending[consumed..consumed + FRAME_LEN];
+            let frame = self.extractor.frames(samples)[0];
+            let is_speech = self.detector.is_speech(&to_bytes(&samples[..HOP_LEN]));
+            consumed += HOP_LEN;
+
+            self.frames.push_back((frame, is_speech));
+            if self.frames.len() > self.max_frames {
+                self.frames.pop_front();
+            }
+            self.frames_since_speech = if is_speech {
+                0
+            } else {
+                self.frames_since_speech.saturating_add(1)
+            };
+            self.frames_since_check += 1;
+
+            if self.frames_since_check >= CHECK_EVERY_FRAMES
+                && self.frames_since_speech < self.max_frames
+            {
+                self.frames_since_check = 0;
+                if self.check() {
+                    detected = true;
+                    break;
+                }
+            }
+        }
+        self.pending.drain(..consumed);
+
+        if detected {
+            self.reset();
+        }
+        detected
+    }
+
+    /// The lowest distance to a template seen so far. Useful to evaluate
+    /// thresholds, as the phrase would have been detected with any threshold
+    /// above it.
+    #[must_use]
+    pub fn best_distance(&self) -> f32 {
+        self.best_distance
+    }
+
+    /// Forgets the audio heard so far, e.g. after a detection or before
+    /// listening again.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.frames.clear();
+        self.frames_since_check = 0;
+        self.frames_since_speech = usize::MAX;
+    }
+
+    fn check(&mut self) -> bool {
+        let (mut window, is_speech): (Vec<Frame>, Vec<bool>) = self.frames.iter().copied().unzip();
+        mfcc::normalize_by_speech(&mut window, &is_speech);
+
+        let distance = self
+            .model
+            .templates
+            .iter()
+            .filter(|template| template.len() <= window.len())
+            .map(|template| {
+                // The phrase may have ended a few frames before this check
+                subsequence_distance(template, &window, CHECK_EVERY_FRAMES)
+            })
+            .fold(f32::INFINITY, f32::min);
+        self.best_distance = self.best_distance.min(distance);
+
+        distance < self.threshold
+    }
+}
+
+/// DTW distance of `template` to the best matching part of `stream` that
+/// ends within its last `end_slack` frames, normalized by the length of the
+/// warping path.
+fn subsequence_distance(template: &[Frame], stream: &[Frame], end_slack: usize) -> f32 {
+    // Accumulated cost and path length of the previous and current row
+    let mut previous: Vec<(f32, u32)> = vec![(0.0, 0); stream.len()];
+    let mut current = previous.clone();
+
+    for (i, template_frame) in template.iter().enumerate() {
+        for (j, stream_frame) in stream.iter().enumerate() {
+            let cost = frame_distance(template_frame, stream_frame);
+            let best = if i == 0 {
+                // The match may start anywhere in the stream
+                (0.0, 0)
+            } else {
+                let mut best = previous[j];
+                if j > 0 {
+                    for candidate in [current[j - 1], previous[j - 1]] {
+                        if candidate.0 < best.0 {
+                            best = candidate;
+                        }
+                    }
+                }
+                best
+            };
+            current[j] = (best.0 + cost, best.1 + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    previous
+        .iter()
+        .skip(stream.len().saturating_sub(end_slack))
+        .map(|(cost, len)| cost / *len as f32)
+        .fold(f32::INFINITY, f32::min)
+}
+
+fn frame_distance(a: &Frame, b: &Frame) -> f32 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| (x - y) * (x - y))
+        .sum::<f32>()
+        .sqrt()
+}
+
+/// Picks a threshold that accepts each enrolled utterance when compared
+/// against the others.
+fn calibrate_threshold(templates: &[Vec<Frame>]) -> f32 {
+    if templates.len() < 2 {
+        return DEFAULT_THRESHOLD;
+    }
+    let worst_match = templates
+        .iter()
+        .enumerate()
+        .map(|(i, template)| {
+            templates
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, other)| subsequence_distance(template, other, 1))
+                .fold(f32::INFINITY, f32::min)
+        })
+        .fold(0.0, f32::max);
+    worst_match * THRESHOLD_MARGIN
+}
+
+/// Cuts off the silence before the first and after the last hop containing
+/// speech.
+fn trim_to_speech(recording: &[u8], detector: EnergyDetector) -> Option<&[u8]> {
+    let hop_bytes = HOP_LEN * 2;
+    let speech_hops: Vec<usize> = recording
+        .chunks(hop_bytes)
+        .enumerate()
+        .filter(|(_, hop)| detector.is_speech(hop))
+        .map(|(i, _)| i)
+        .collect();
+    let first = *speech_hops.first()?;
+    let last = *speech_hops.last()?;
+    Some(&recording[first * hop_bytes..((last + 1) * hop_bytes).min(recording.len())])
+}
+
+/// The level of the loudest hop in `recording` in dBFS, to help choosing a
+/// speech threshold.
+#[must_use]
+pub fn peak_level_dbfs(recording: &[u8]) -> f64 {
+    recording
+        .chunks(HOP_LEN * 2)
+        .map(level_dbfs)
+        .fold(f64::NEG_INFINITY, f64::max)
+}
+
+fn to_samples(bytes: &[u8]) -> Vec<i16> {
+    bytes
+        .chunks_exact(2)
+        .map(|s| i16::from_le_bytes([s[0], s[1]]))
+        .collect()
+}
+
+fn to_bytes(samples: &[i16]) -> Vec<u8> {
+    samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tones of the given frequencies, 0.2 s each, between 0.3 s of silence
+    fn recording(tones_hz: &[f32]) -> Vec<u8> {
+        let tone_len = mfcc::SAMPLE_RATE_HZ as usize / 5;
+        let silence = vec![0i16; tone_len * 3 / 2];
+        #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+        let tones = tones_hz.iter().flat_map(|hz| {
+            (0..tone_len).map(move |i| {
+                let phase =
+                    i as f32 * hz * 2.0 * std::f32::consts::PI / mfcc::SAMPLE_RATE_HZ as f32;
+                ((phase.sin() + 0.5 * (2.0 * phase).sin()) * 8000.0) as i16
+            })
+        });
+        let samples: Vec<i16> = silence
+            .iter()
+            .copied()
+            .chain(tones)
+            .chain(silence.iter().copied())
+            .collect();
+        to_bytes(&samples)
+    }
+
+    fn detector() -> EnergyDetector {
+        EnergyDetector::new(-45.0)
+    }
+
+    fn model() -> WakeWordModel {
+        WakeWordModel::enroll(
+            "hey jarvis".to_owned(),
+            &[recording(&[300.0, 900.0, 2000.0])],
+            detector(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn matches_templates_anywhere_in_the_stream() {
+        let frame = |c: f32| [c; mfcc::NUM_COEFFS];
+        let template = [frame(1.0), frame(2.0), frame(3.0)];
+        let cases = [
+            (vec![frame(1.0), frame(2.0), frame(3.0)], 0.0),
+            // Shifted by some frames
+            (
+                vec![frame(7.0), frame(7.0), frame(1.0), frame(2.0), frame(3.0)],
+                0.0,
+            ),
+            // Said slower
+            (
+                vec![frame(1.0), frame(1.0), frame(2.0), frame(2.0), frame(3.0)],
+                0.0,
+            ),
+            // Ends too early
+            (
+                vec![frame(1.0), frame(2.0), frame(3.0), frame(7.0), frame(7.0)],
+                8.0 * 12f32.sqrt() / 5.0,
+            ),
+        ];
+
+        for (stream, expected) in cases {
+            let distance = subsequence_distance(&template, &stream, 1);
+            assert!(
+                (distance - expected).abs() < 1e-4,
+                "stream: {stream:?}, distance: {distance}"
+            );
+        }
+    }
+
+    #[test]
+    fn detects_the_enrolled_phrase() {
+        let cases = [
+            (vec![300.0, 900.0, 2000.0], true),
+            (vec![2000.0, 900.0, 300.0], false),
+            (vec![], false),
+        ];
+
+        for (tones_hz, expected) in cases {
+            let mut spotter = WakeWordSpotter::new(model(), None, detector());
+            // Chunks of 0.1 s
+            let detected = recording(&tones_hz)
+                .chunks(4800)
+                .any(|chunk| spotter.push(chunk));
+            assert_eq!(
+                detected,
+                expected,
+                "tones: {tones_hz:?}, distance: {}",
+                spotter.best_distance()
+            );
+        }
+    }
+
+    #[test]
+    fn saves_and_loads_models() {
+        let path = std::env::temp_dir().join(format!(
+            "jarvis_code_wake_word_model_{}.json",
+            std::process::id()
+        ));
+        let model = model();
+
+        model.save(&path).unwrap();
+        let loaded = WakeWordModel::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.phrase, model.phrase);
+        assert_eq!(loaded.threshold.to_bits(), model.threshold.to_bits());
+        assert_eq!(loaded.templates, model.templates);
+    }
+}
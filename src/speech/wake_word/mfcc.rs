@@ -0,0 +1,303 @@
+//! Mel-frequency cepstral coefficients, the features used to compare
+//! utterances of the wake phrase.
+
+use std::f32::consts::PI;
+
+pub const SAMPLE_RATE_HZ: u32 = 24000;
+/// 25 ms frames
+pub const FRAME_LEN: usize = 600;
+/// 10 ms between the start of consecutive frames
+pub const HOP_LEN: usize = 240;
+/// Number of coefficients per frame. The 0th coefficient, which mostly
+/// reflects the loudness, is left out.
+pub const NUM_COEFFS: usize = 12;
+
+const FFT_LEN: usize = 1024;
+const NUM_MEL_FILTERS: usize = 26;
+const MIN_FREQ_HZ: f32 = 80.0;
+/// Most of the information in speech is below 8 kHz
+const MAX_FREQ_HZ: f32 = 8000.0;
+const PRE_EMPHASIS: f32 = 0.97;
+
+pub type Frame = [f32; NUM_COEFFS];
+
+/// Computes MFCC frames from mono samples at [`SAMPLE_RATE_HZ`].
+pub struct MfccExtractor {
+    window: Vec<f32>,
+    mel_filters: Vec<Vec<(usize, f32)>>,
+    dct: Vec<[f32; NUM_MEL_FILTERS]>,
+}
+
+impl Default for MfccExtractor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MfccExtractor {
+    #[must_use]
+    pub fn new() -> Self {
+        #[allow(clippy::cast_precision_loss)]
+        let window = (0..FRAME_LEN)
+            .map(|n| 0.54 - 0.46 * (2.0 * PI * n as f32 / (FRAME_LEN - 1) as f32).cos())
+            .collect();
+
+        #[allow(clippy::cast_precision_loss)]
+        let dct = (1..=NUM_COEFFS)
+            .map(|k| {
+                let mut row = [0.0; NUM_MEL_FILTERS];
+                for (m, coeff) in row.iter_mut().enumerate() {
+                    *coeff = (PI * k as f32 * (m as f32 + 0.5) / NUM_MEL_FILTERS as f32).cos();
+                }
+                row
+            })
+            .collect();
+
+        Self {
+            window,
+            mel_filters: mel_filter_bank(),
+            dct,
+        }
+    }
+
+    /// Computes a frame for every [`HOP_LEN`] samples, as long as a full
+    /// frame fits into `samples`.
+    #[must_use]
+    pub fn frames(&self, samples: &[i16]) -> Vec<Frame> {
+        if samples.len() < FRAME_LEN {
+            return Vec::new();
+        }
+        (0..=samples.len() - FRAME_LEN)
+            .step_by(HOP_LEN)
+            .map(|start| self.frame(&samples[start..start + FRAME_LEN]))
+            .collect()
+    }
+
+    fn frame(&self, samples: &[i16]) -> Frame {
+        let mut re = [0.0f32; FFT_LEN];
+        let mut im = [0.0f32; FFT_LEN];
+        let mut previous = 0.0;
+        for (i, sample) in samples.iter().enumerate() {
+            let sample = f32::from(*sample) / f32::from(i16::MAX);
+            re[i] = (sample - PRE_EMPHASIS * previous) * self.window[i];
+            previous = sample;
+        }
+        fft(&mut re, &mut im);
+
+        let power: Vec<f32> = (0..=FFT_LEN / 2)
+            .map(|i| re[i] * re[i] + im[i] * im[i])
+            .collect();
+        let log_energies: Vec<f32> = self
+            .mel_filters
+            .iter()
+            .map(|filter| {
+                let energy: f32 = filter
+                    .iter()
+                    .map(|(bin, weight)| power[*bin] * weight)
+                    .sum();
+                energy.max(1e-10).ln()
+            })
+            .collect();
+
+        let mut frame = [0.0; NUM_COEFFS];
+        for (coeff, row) in frame.iter_mut().zip(&self.dct) {
+            *coeff = row.iter().zip(&log_energies).map(|(c, e)| c * e).sum();
+        }
+        frame
+    }
+}
+
+/// Subtracts the mean of each coefficient, which removes the influence of
+/// the microphone and room to some extent.
+pub fn normalize(frames: &mut [Frame]) {
+    let mean = mean(frames.iter());
+    subtract(frames, &mean);
+}
+
+/// Like [`normalize`], but only the frames marked as speech contribute to
+/// the mean, so the result doesn't depend on how much silence is included.
+pub fn normalize_by_speech(frames: &mut [Frame], is_speech: &[bool]) {
+    let speech = frames.iter().zip(is_speech).filter(|(_, s)| **s);
+    let mean = if speech.clone().next().is_some() {
+        mean(speech.map(|(f, _)| f))
+    } else {
+        mean(frames.iter())
+    };
+    subtract(frames, &mean);
+}
+
+fn mean<'a>(frames: impl Iterator<Item = &'a Frame>) -> Frame {
+    let mut sum = [0.0; NUM_COEFFS];
+    let mut count = 0;
+    for frame in frames {
+        for (s, c) in sum.iter_mut().zip(frame) {
+            *s += c;
+        }
+        count += 1;
+    }
+    if count > 0 {
+        #[allow(clippy::cast_precision_loss)]
+        sum.iter_mut().for_each(|s| *s /= count as f32);
+    }
+    sum
+}
+
+fn subtract(frames: &mut [Frame], mean: &Frame) {
+    for frame in frames {
+        for (c, m) in frame.iter_mut().zip(mean) {
+            *c -= m;
+        }
+    }
+}
+
+fn hz_to_mel(hz: f32) -> f32 {
+    2595.0 * (1.0 + hz / 700.0).log10()
+}
+
+fn mel_to_hz(mel: f32) -> f32 {
+    700.0 * (10f32.powf(mel / 2595.0) - 1.0)
+}
+
+/// Triangular filters, evenly spaced on the mel scale, as (FFT bin, weight)
+/// pairs.
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+fn mel_filter_bank() -> Vec<Vec<(usize, f32)>> {
+    let min_mel = hz_to_mel(MIN_FREQ_HZ);
+    let max_mel = hz_to_mel(MAX_FREQ_HZ);
+    let bin_of = |hz: f32| hz * FFT_LEN as f32 / SAMPLE_RATE_HZ as f32;
+    let edges: Vec<f32> = (0..NUM_MEL_FILTERS + 2)
+        .map(|i| {
+            let mel = min_mel + (max_mel - min_mel) * i as f32 / (NUM_MEL_FILTERS + 1) as f32;
+            bin_of(mel_to_hz(mel))
+        })
+        .collect();
+
+    edges
+        .windows(3)
+        .map(|w| {
+            let (left, center, right) = (w[0], w[1], w[2]);
+            (left.floor() as usize..=right.ceil() as usize)
+                .filter_map(|bin| {
+                    let pos = bin as f32;
+                    let weight = if pos < center {
+                        (pos - left) / (center - left)
+                    } else {
+                        (right - pos) / (right - center)
+                    };
+                    (weight > 0.0).then_some((bin, weight))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// In-place iterative radix-2 FFT. The length must be a power of two.
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        #[allow(clippy::cast_precision_loss)]
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                #[allow(clippy::cast_precision_loss)]
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_a_frame_per_hop() {
+        let cases = [
+            (0, 0),
+            (FRAME_LEN - 1, 0),
+            (FRAME_LEN, 1),
+            (FRAME_LEN + HOP_LEN - 1, 1),
+            (FRAME_LEN + HOP_LEN, 2),
+            (FRAME_LEN + 9 * HOP_LEN, 10),
+        ];
+
+        let extractor = MfccExtractor::new();
+        for (num_samples, expected) in cases {
+            #[allow(clippy::cast_possible_truncation)]
+            let samples: Vec<i16> = (0..num_samples)
+                .map(|i| ((i as f32 * 0.1).sin() * 8000.0) as i16)
+                .collect();
+            let frames = extractor.frames(&samples);
+            assert_eq!(frames.len(), expected, "samples: {num_samples}");
+            assert!(
+                frames.iter().flatten().all(|c| c.is_finite()),
+                "samples: {num_samples}"
+            );
+        }
+    }
+
+    #[test]
+    fn gives_the_same_frames_for_the_same_samples() {
+        let extractor = MfccExtractor::new();
+        let samples: Vec<i16> = (0..FRAME_LEN as i16).map(|i| i * 20).collect();
+        let mut repeated = samples.clone();
+        repeated.extend(&samples[..HOP_LEN]);
+        repeated.extend(&samples);
+
+        let frames = extractor.frames(&repeated);
+
+        // The third frame starts where the samples repeat
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames[0], extractor.frames(&samples)[0]);
+    }
+
+    #[test]
+    fn subtracts_the_mean() {
+        let frames = [[1.0; NUM_COEFFS], [3.0; NUM_COEFFS], [8.0; NUM_COEFFS]];
+        let cases = [
+            (None, [-3.0, -1.0, 4.0]),
+            (Some([true, true, false]), [-1.0, 1.0, 6.0]),
+            (Some([false, false, true]), [-7.0, -5.0, 0.0]),
+            // Without speech, all frames count
+            (Some([false, false, false]), [-3.0, -1.0, 4.0]),
+        ];
+
+        for (is_speech, expected) in cases {
+            let mut normalized = frames;
+            match is_speech {
+                Some(is_speech) => normalize_by_speech(&mut normalized, &is_speech),
+                None => normalize(&mut normalized),
+            }
+            let expected = expected.map(|c| [c; NUM_COEFFS]);
+            assert_eq!(normalized, expected, "speech: {is_speech:?}");
+        }
+    }
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use jarvis_code::ai_providers::anthropic::AnthropicModel;
+use jarvis_code::session::{
+    AIModel, Author, ModelInput, Outcome, TextImage, TextImageMessage, TokenUsage,
+};
+use serde_json::json;
+
+use common::{Reply, StubServer};
+
+fn input() -> ModelInput<TextImageMessage> {
+    ModelInput {
+        instructions: "Classify the intent".to_owned(),
+        log: vec![
+            TextImageMessage {
+                author: Author::User,
+                parts: vec![
+                    TextImage::Text("What is on this screenshot?".to_owned()),
+                    TextImage::Base64Image("data:image/png;base64,iVBORw0KGgo=".to_owned()),
+                ],
+            },
+            TextImageMessage {
+                author: Author::Assistant,
+                parts: vec![TextImage::Text("A stack trace".to_owned())],
+            },
+        ],
+        tools: Vec::new(),
+        tool_results: Vec::new(),
+        json_format: None,
+    }
+}
+
+#[tokio::test]
+async fn sends_messages_and_parses_the_answer() {
+    let server = StubServer::start(vec![Reply::json(
+        200,
+        &json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "content": [{"type": "text", "text": "ask"}],
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 20, "output_tokens": 2, "cache_read_input_tokens": 10},
+        }),
+    )])
+    .await;
+    let model = AnthropicModel::new("test-key".to_owned(), "test-model".to_owned())
+        .with_base_url(server.url.clone());
+
+    let output = model.send(input()).await.unwrap();
+
+    assert_eq!(output.items.len(), 1);
+    assert_eq!(output.items[0].parts, vec!["ask".to_owned()]);
+    assert_eq!(output.outcome, Outcome::Completed);
+    assert_eq!(
+        output.usage,
+        Some(TokenUsage {
+            input_tokens: 30,
+            output_tokens: 2,
+            cached_input_tokens: 10,
+        })
+    );
+    server.requests(|requests| {
+        let request = &requests[0];
+        assert_eq!(request.path, "/v1/messages");
+        assert_eq!(request.header("x-api-key"), Some("test-key"));
+        assert!(request.header("anthropic-version").is_some());
+        assert_eq!(
+            request.body,
+            json!({
+                "model": "test-model",
+                "max_tokens": 4096,
+                "system": "Classify the intent",
+                "messages": [
+                    {
+                        "role": "user",
+                        "content": [
+                            {"type": "text", "text": "What is on this screenshot?"},
+                            {
+                                "type": "image",
+                                "source": {
+                                    "type": "base64",
+                                    "media_type": "image/png",
+                                    "data": "iVBORw0KGgo=",
+                                },
+                            },
+                        ],
+                    },
+                    {
+                        "role": "assistant",
+                        "content": [{"type": "text", "text": "A stack trace"}],
+                    },
+                ],
+            })
+        );
+    });
+}
+
+#[tokio::test]
+async fn reports_api_errors() {
+    let server = StubServer::start(vec![Reply::json(
+        401,
+        &json!({
+            "type": "error",
+            "error": {"type": "authentication_error", "message": "invalid x-api-key"},
+        }),
+    )])
+    .await;
+    let model = AnthropicModel::new("wrong-key".to_owned(), "test-model".to_owned())
+        .with_base_url(server.url.clone());
+
+    let err = model.send(input()).await.err().unwrap();
+
+    let message = err.to_string();
+    assert!(message.contains("401"), "{message}");
+    assert!(message.contains("invalid x-api-key"), "{message}");
+}
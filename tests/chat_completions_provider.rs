@@ -0,0 +1,100 @@
+mod common;
+
+use jarvis_code::ai_providers::chat_completions::ChatCompletionsModel;
+use jarvis_code::session::{
+    AIModel, Author, ModelInput, Outcome, TextImage, TextImageMessage, TokenUsage,
+};
+use serde_json::json;
+
+use common::{Reply, StubServer};
+
+fn input(parts: Vec<TextImage>) -> ModelInput<TextImageMessage> {
+    ModelInput {
+        instructions: "Classify the intent".to_owned(),
+        log: vec![TextImageMessage {
+            author: Author::User,
+            parts,
+        }],
+        tools: Vec::new(),
+        tool_results: Vec::new(),
+        json_format: None,
+    }
+}
+
+fn completion(content: &str) -> Reply {
+    Reply::json(
+        200,
+        &json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": content},
+                "finish_reason": "stop",
+            }],
+            "usage": {"prompt_tokens": 12, "completion_tokens": 1, "total_tokens": 13},
+        }),
+    )
+}
+
+#[tokio::test]
+async fn sends_a_system_message_without_api_key() {
+    let server = StubServer::start(vec![completion("ask")]).await;
+    let model = ChatCompletionsModel::new(format!("{}/v1", server.url), "local".to_owned());
+
+    let output = model
+        .send(input(vec![TextImage::Text("What is XYZ?".to_owned())]))
+        .await
+        .unwrap();
+
+    assert_eq!(output.items[0].parts, vec!["ask".to_owned()]);
+    assert_eq!(output.outcome, Outcome::Completed);
+    assert_eq!(
+        output.usage,
+        Some(TokenUsage {
+            input_tokens: 12,
+            output_tokens: 1,
+            cached_input_tokens: 0,
+        })
+    );
+    server.requests(|requests| {
+        assert_eq!(requests[0].path, "/v1/chat/completions");
+        assert_eq!(requests[0].header("authorization"), None);
+        assert_eq!(
+            requests[0].body,
+            json!({
+                "model": "local",
+                "messages": [
+                    {"role": "system", "content": "Classify the intent"},
+                    {"role": "user", "content": "What is XYZ?"},
+                ],
+            })
+        );
+    });
+}
+
+#[tokio::test]
+async fn sends_images_as_content_parts() {
+    let server = StubServer::start(vec![completion("A stack trace")]).await;
+    let model = ChatCompletionsModel::new(format!("{}/v1", server.url), "local".to_owned())
+        .with_api_key(Some("test-key".to_owned()));
+
+    model
+        .send(input(vec![
+            TextImage::Text("What is this?".to_owned()),
+            TextImage::Base64Image("/9j/4AAQ".to_owned()),
+        ]))
+        .await
+        .unwrap();
+
+    server.requests(|requests| {
+        assert_eq!(requests[0].header("authorization"), Some("Bearer test-key"));
+        assert_eq!(
+            requests[0].body["messages"][1]["content"],
+            json!([
+                {"type": "text", "text": "What is this?"},
+                {"type": "image_url", "image_url": {"url": "data:image/jpeg;base64,/9j/4AAQ"}},
+            ])
+        );
+    });
+}
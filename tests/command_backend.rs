@@ -0,0 +1,120 @@
+mod common;
+
+use std::path::PathBuf;
+
+use jarvis_code::config::{CommandOutputFormat, Config, SpeechBackend, TranscriptionCommand};
+use jarvis_code::logger::Logger;
+use jarvis_code::speech::input::{SpeechListener, Transcription};
+
+use common::config::test_config;
+
+/// A stand-in for a speech recognition program, which checks that it got a
+/// WAV file and prints `output`
+fn script(name: &str, output: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("jarvis_code_{name}_{}.sh", std::process::id()));
+    let script = format!(
+        "[ \"$(head -c 4 \"$1\")\" = RIFF ] || {{ echo 'not a WAV file' >&2; exit 1; }}\n\
+        cat <<'EOF'\n{output}\nEOF\n"
+    );
+    std::fs::write(&path, script).unwrap();
+    path
+}
+
+/// Writes an utterance of a tone between silence, as 16 bit PCM at 16 kHz
+fn utterance_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("jarvis_code_{name}_{}.pcm", std::process::id()));
+    let mut audio = vec![0u8; 9600];
+    for i in 0..8000 {
+        let phase = f64::from(i) * 440.0 * 2.0 * std::f64::consts::PI / 16000.0;
+        #[allow(clippy::cast_possible_truncation)]
+        let sample = (phase.sin() * 8000.0) as i16;
+        audio.extend(sample.to_le_bytes());
+    }
+    audio.extend(vec![0u8; 64000]);
+    std::fs::write(&path, audio).unwrap();
+    path
+}
+
+fn listener(name: &str, command: String, output: CommandOutputFormat) -> (SpeechListener, PathBuf) {
+    let recording = utterance_file(name);
+    let config = Config {
+        recording_file: Some(recording.clone()),
+        speech_backend: SpeechBackend::Command(TranscriptionCommand { command, output }),
+        ..test_config()
+    };
+    let logger = Logger::new();
+    (SpeechListener::new(&config, logger).unwrap(), recording)
+}
+
+#[tokio::test]
+async fn transcribes_plain_text_output() {
+    let script = script(
+        "plain_text",
+        "[00:00:00.000 --> 00:00:01.500]   Open the file\n\
+        [00:00:01.500 --> 00:00:03.000]   called begin code main dot rs",
+    );
+    let (mut listener, recording) = listener(
+        "plain_text",
+        format!("sh {} {{wav}}", script.display()),
+        CommandOutputFormat::Text,
+    );
+
+    let transcription = listener.listen_to_input().await.unwrap();
+
+    let Transcription::Some { text, timing, .. } = transcription else {
+        panic!("Nothing was transcribed");
+    };
+    assert_eq!(text, "Open the file called main.rs");
+    // The tone lasts from 0.3 s to 0.8 s, recorded in chunks of 0.128 s
+    let timing = timing.unwrap();
+    let (start, end) = (timing.start.unwrap(), timing.end.unwrap());
+    assert!(
+        start.as_secs_f64() > 0.1 && start.as_secs_f64() <= 0.3,
+        "start: {start:?}"
+    );
+    assert!(
+        end.as_secs_f64() >= 0.8 && end.as_secs_f64() < 1.0,
+        "end: {end:?}"
+    );
+
+    std::fs::remove_file(script).unwrap();
+    std::fs::remove_file(recording).unwrap();
+}
+
+#[tokio::test]
+async fn transcribes_json_output() {
+    let script = script(
+        "json",
+        r#"{"transcription": [{"text": " Hello"}, {"text": " Jarvis"}]}"#,
+    );
+    let (mut listener, recording) = listener(
+        "json",
+        format!("sh {} {{wav}}", script.display()),
+        CommandOutputFormat::Json,
+    );
+
+    let transcription = listener.listen_to_input().await.unwrap();
+
+    assert!(matches!(
+        transcription,
+        Transcription::Some { text, .. } if text == "Hello Jarvis"
+    ));
+
+    std::fs::remove_file(script).unwrap();
+    std::fs::remove_file(recording).unwrap();
+}
+
+#[tokio::test]
+async fn reports_failing_commands() {
+    let (mut listener, recording) = listener(
+        "failing",
+        "echo 'model not found' >&2; exit 3".to_owned(),
+        CommandOutputFormat::Text,
+    );
+
+    let err = listener.listen_to_input().await.unwrap_err();
+
+    assert!(err.to_string().contains("model not found"));
+
+    std::fs::remove_file(recording).unwrap();
+}
@@ -0,0 +1,39 @@
+//! A configuration for tests, so adding a setting doesn't require updating
+//! every test crate.
+
+use std::path::PathBuf;
+
+use jarvis_code::ai_providers::http::HttpOptions;
+use jarvis_code::config::{Config, InputMode, ModelRoles, SpeechBackend};
+use jarvis_code::session::ImageDetail;
+
+/// Voice input in English, without any files written, trimming or wake word
+pub fn test_config() -> Config {
+    Config {
+        openai_key: Some("test-key".to_owned()),
+        recording_file: None,
+        speech_backend: SpeechBackend::OpenAI,
+        project_dir: PathBuf::from(env!("CARGO_MANIFEST_DIR")),
+        spoken_code_rules: None,
+        languages: vec!["en".to_owned()],
+        realtime_url: None,
+        min_transcription_confidence: 0.6,
+        input_mode: InputMode::Voice,
+        keyboard_history_file: None,
+        usage_ledger_file: None,
+        transcription_price_table: None,
+        corrections_log_file: None,
+        silence_trimming: None,
+        wake_word_model: None,
+        wake_word_threshold: None,
+        barge_in: false,
+        models: ModelRoles::default(),
+        anthropic_key: None,
+        anthropic_url: None,
+        chat_completions_url: None,
+        chat_completions_key: None,
+        image_detail: ImageDetail::Auto,
+        model_http: HttpOptions::default(),
+        prewarm_connections: false,
+    }
+}
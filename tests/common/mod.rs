@@ -0,0 +1,166 @@
+//! A minimal HTTP server standing in for the APIs of model providers.
+
+// Each test crate uses only some of the helpers
+#![allow(dead_code)]
+
+pub mod config;
+
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+/// A request the stub has received
+#[derive(Debug)]
+pub struct Recorded {
+    /// e.g. "/v1/messages"
+    pub path: String,
+    /// With lowercase names
+    pub headers: Vec<(String, String)>,
+    pub body: serde_json::Value,
+}
+
+impl Recorded {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub struct Reply {
+    pub status: u16,
+    pub content_type: &'static str,
+    /// In addition to the content type and length
+    pub headers: Vec<(&'static str, String)>,
+    pub body: String,
+}
+
+impl Reply {
+    pub fn json(status: u16, body: &serde_json::Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            headers: Vec::new(),
+            body: body.to_string(),
+        }
+    }
+
+    /// A stream of server-sent events, each with a type and JSON data
+    pub fn sse(events: &[(&str, serde_json::Value)]) -> Self {
+        Self {
+            status: 200,
+            content_type: "text/event-stream",
+            headers: Vec::new(),
+            body: events
+                .iter()
+                .map(|(event, data)| format!("event: {event}\ndata: {data}\n\n"))
+                .collect(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+}
+
+/// Answers each request with the next scripted reply. Unless started with
+/// [`StubServer::start_keep_alive`], the connection is closed after each
+/// reply.
+pub struct StubServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<Recorded>>>,
+}
+
+impl StubServer {
+    pub async fn start(replies: Vec<Reply>) -> Self {
+        Self::serve(replies, false).await
+    }
+
+    /// Keeps connections open for further requests, so clients can reuse
+    /// them
+    pub async fn start_keep_alive(replies: Vec<Reply>) -> Self {
+        Self::serve(replies, true).await
+    }
+
+    async fn serve(replies: Vec<Reply>, keep_alive: bool) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = Arc::clone(&requests);
+        tokio::spawn(async move {
+            let mut replies = replies.into_iter().peekable();
+            while replies.peek().is_some() {
+                let Ok((stream, _)) = listener.accept().await else {
+                    return;
+                };
+                let (read, mut write) = stream.into_split();
+                let mut reader = BufReader::new(read);
+
+                loop {
+                    let mut request_line = String::new();
+                    // The client closed the connection
+                    if reader.read_line(&mut request_line).await.unwrap_or(0) == 0 {
+                        break;
+                    }
+                    let Some(reply) = replies.next() else {
+                        return;
+                    };
+                    let mut parts = request_line.split_whitespace();
+                    let method = parts.next().unwrap_or_default().to_owned();
+                    let path = parts.next().unwrap_or_default().to_owned();
+                    let mut headers = Vec::new();
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).await.unwrap();
+                        let Some((name, value)) = line.trim_end().split_once(':') else {
+                            break;
+                        };
+                        headers.push((name.to_lowercase(), value.trim().to_owned()));
+                    }
+                    let content_length = headers
+                        .iter()
+                        .find(|(name, _)| name == "content-length")
+                        .map_or(0, |(_, value)| value.parse().unwrap());
+                    let mut body = vec![0; content_length];
+                    reader.read_exact(&mut body).await.unwrap();
+                    recorded.lock().unwrap().push(Recorded {
+                        path,
+                        headers,
+                        body: serde_json::from_slice(&body).unwrap_or_default(),
+                    });
+
+                    let headers: String = reply
+                        .headers
+                        .iter()
+                        .map(|(name, value)| format!("{name}: {value}\r\n"))
+                        .collect();
+                    let connection = if keep_alive { "keep-alive" } else { "close" };
+                    let response = format!(
+                        "HTTP/1.1 {} Stub\r\ncontent-type: {}\r\ncontent-length: {}\r\n{headers}connection: {connection}\r\n\r\n{}",
+                        reply.status,
+                        reply.content_type,
+                        reply.body.len(),
+                        // Responses to HEAD requests have no body
+                        if method == "HEAD" { "" } else { &reply.body }
+                    );
+                    write.write_all(response.as_bytes()).await.unwrap();
+                    if !keep_alive {
+                        write.shutdown().await.unwrap();
+                        break;
+                    }
+                }
+            }
+        });
+
+        Self { url, requests }
+    }
+
+    pub fn requests<T>(&self, f: impl FnOnce(&[Recorded]) -> T) -> T {
+        f(&self.requests.lock().unwrap())
+    }
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use jarvis_code::config::Config;
+use jarvis_code::logger::Logger;
+use jarvis_code::speech::input::mock_server::{
+    MockRealtimeServer, SCRIPTED_SPEECH_MS, ScriptedTurn,
+};
+use jarvis_code::speech::input::{SpeechListener, Transcription};
+
+use common::config::test_config;
+
+/// Writes 200 ms of silence in the format expected by the listener
+fn silence_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("jarvis_code_{name}_{}.pcm", std::process::id()));
+    std::fs::write(&path, vec![0u8; 9600]).unwrap();
+    path
+}
+
+fn listener(server: &MockRealtimeServer, recording: &Path) -> SpeechListener {
+    let config = Config {
+        recording_file: Some(recording.to_path_buf()),
+        realtime_url: Some(server.url().to_owned()),
+        // The recording is all silence
+        silence_trimming: None,
+        ..test_config()
+    };
+    let logger = Logger::new();
+    SpeechListener::new(&config, logger).unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn transcribes_scripted_utterances() {
+    let server = MockRealtimeServer::start(vec![
+        ScriptedTurn::transcript("Hello Jarvis"),
+        ScriptedTurn::transcript("call begin code snake case get user id end code"),
+    ])
+    .await
+    .unwrap();
+    let recording = silence_file("transcribes_scripted_utterances");
+    let mut listener = listener(&server, &recording);
+
+    let first = listener.listen_to_input().await.unwrap();
+    let second = listener.listen_to_input().await.unwrap();
+
+    assert!(matches!(&first, Transcription::Some { text, .. } if text == "Hello Jarvis"));
+    assert!(matches!(
+        &first,
+        Transcription::Some { confidence: Some(c), .. } if c.average() == Some(1.0)
+    ));
+    let Transcription::Some {
+        timing: Some(timing),
+        ..
+    } = first
+    else {
+        panic!("The first transcription has no timing");
+    };
+    assert_eq!(timing.item_id.as_deref(), Some("item_1"));
+    assert_eq!(
+        timing.duration(),
+        Some(Duration::from_millis(SCRIPTED_SPEECH_MS.into()))
+    );
+    assert!(timing.latency.is_some());
+    assert!(matches!(second, Transcription::Some { text, .. } if text == "call get_user_id"));
+    server.received(|received| {
+        assert_eq!(received.session_updates.len(), 2);
+        assert_eq!(
+            received.session_updates[0]["session"]["input_audio_transcription"]["model"],
+            "gpt-4o-transcribe"
+        );
+        assert!(received.audio_bytes > 0);
+    });
+
+    std::fs::remove_file(recording).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_api_errors() {
+    let server = MockRealtimeServer::start(vec![ScriptedTurn::Error {
+        code: "invalid_api_key".to_owned(),
+        message: "Incorrect API key provided".to_owned(),
+    }])
+    .await
+    .unwrap();
+    let recording = silence_file("reports_api_errors");
+    let mut listener = listener(&server, &recording);
+
+    let err = listener.listen_to_input().await.unwrap_err();
+
+    assert!(err.to_string().contains("Incorrect API key provided"));
+
+    std::fs::remove_file(recording).unwrap();
+}
@@ -0,0 +1,590 @@
+mod common;
+
+use std::time::Duration;
+
+use jarvis_code::ai_providers::http::{
+    ApiError, ApiErrorKind, ConnectionReuse, HttpClient, HttpOptions, RetryPolicy,
+};
+use jarvis_code::ai_providers::openai::ResponsesModel;
+use jarvis_code::session::{
+    AIModel, Author, CancellationToken, ImageDetail, IncompleteReason, ModelInput, Outcome,
+    StreamingAIModel, TextImage, TextImageMessage, TokenUsage, Tool, Toolbox, send_structured,
+    send_with_tools,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use common::{Reply, StubServer};
+
+fn input() -> ModelInput<TextImageMessage> {
+    ModelInput {
+        instructions: "Answer briefly".to_owned(),
+        log: vec![TextImageMessage {
+            author: Author::User,
+            parts: vec![TextImage::Text("What is XYZ?".to_owned())],
+        }],
+        tools: Vec::new(),
+        tool_results: Vec::new(),
+        json_format: None,
+    }
+}
+
+#[tokio::test]
+async fn streams_text_deltas_and_the_final_output() {
+    let server = StubServer::start(vec![Reply::sse(&[
+        ("response.created", json!({"type": "response.created"})),
+        (
+            "response.output_text.delta",
+            json!({"type": "response.output_text.delta", "delta": "XYZ is "}),
+        ),
+        (
+            "response.output_text.delta",
+            json!({"type": "response.output_text.delta", "delta": "a library."}),
+        ),
+        (
+            "response.completed",
+            json!({
+                "type": "response.completed",
+                "response": {
+                    "id": "resp_1",
+                    "status": "completed",
+                    "output": [{
+                        "type": "message",
+                        "id": "msg_1",
+                        "role": "assistant",
+                        "status": "completed",
+                        "content": [{
+                            "type": "output_text",
+                            "text": "XYZ is a library.",
+                            "annotations": [],
+                        }],
+                    }],
+                    "usage": {
+                        "input_tokens": 15,
+                        "input_tokens_details": {"cached_tokens": 4},
+                        "output_tokens": 5,
+                        "output_tokens_details": {"reasoning_tokens": 0},
+                        "total_tokens": 20,
+                    },
+                },
+            }),
+        ),
+    ])])
+    .await;
+    let model = ResponsesModel::new("test-key".to_owned(), "test-model".to_owned())
+        .with_base_url(format!("{}/v1", server.url));
+
+    let mut deltas = Vec::new();
+    let output = model
+        .send_streaming(input())
+        .await
+        .unwrap()
+        .finish(|delta| deltas.push(delta.to_owned()))
+        .await
+        .unwrap();
+
+    assert_eq!(deltas, vec!["XYZ is ", "a library."]);
+    assert_eq!(output.items[0].parts, vec!["XYZ is a library.".to_owned()]);
+    assert_eq!(output.outcome, Outcome::Completed);
+    assert_eq!(
+        output.usage,
+        Some(TokenUsage {
+            input_tokens: 15,
+            output_tokens: 5,
+            cached_input_tokens: 4,
+        })
+    );
+    server.requests(|requests| {
+        assert_eq!(requests[0].path, "/v1/responses");
+        assert_eq!(requests[0].header("authorization"), Some("Bearer test-key"));
+        assert_eq!(requests[0].body["stream"], json!(true));
+    });
+}
+
+#[tokio::test]
+async fn reports_errors_in_the_stream() {
+    let server = StubServer::start(vec![Reply::sse(&[(
+        "error",
+        json!({"type": "error", "code": "server_error", "message": "overloaded"}),
+    )])])
+    .await;
+    let model = ResponsesModel::new("test-key".to_owned(), "test-model".to_owned())
+        .with_base_url(format!("{}/v1", server.url));
+
+    let err = model
+        .send_streaming(input())
+        .await
+        .unwrap()
+        .finish(|_| ())
+        .await
+        .err()
+        .unwrap();
+
+    let message = err.to_string();
+    assert!(message.contains("overloaded"), "{message}");
+}
+
+#[tokio::test]
+async fn streams_failed_responses_as_an_outcome() {
+    let server = StubServer::start(vec![Reply::sse(&[
+        (
+            "response.output_text.delta",
+            json!({"type": "response.output_text.delta", "delta": "XYZ"}),
+        ),
+        (
+            "response.failed",
+            json!({
+                "type": "response.failed",
+                "response": {
+                    "id": "resp_1",
+                    "status": "failed",
+                    "error": {"code": "server_error", "message": "Something went wrong"},
+                    "output": [],
+                    "usage": null,
+                },
+            }),
+        ),
+    ])])
+    .await;
+    let model = ResponsesModel::new("test-key".to_owned(), "test-model".to_owned())
+        .with_base_url(format!("{}/v1", server.url));
+
+    let mut deltas = Vec::new();
+    let output = model
+        .send_streaming(input())
+        .await
+        .unwrap()
+        .finish(|delta| deltas.push(delta.to_owned()))
+        .await
+        .unwrap();
+
+    assert_eq!(deltas, vec!["XYZ"]);
+    assert_eq!(
+        output.outcome,
+        Outcome::Failed {
+            error: "Something went wrong".to_owned(),
+        }
+    );
+    assert!(output.items.is_empty());
+    assert_eq!(output.usage, None);
+}
+
+#[tokio::test]
+async fn runs_called_tools_until_the_model_answers() {
+    let usage = json!({
+        "input_tokens": 10,
+        "input_tokens_details": {"cached_tokens": 0},
+        "output_tokens": 2,
+        "output_tokens_details": {"reasoning_tokens": 0},
+        "total_tokens": 12,
+    });
+    let server = StubServer::start(vec![
+        Reply::json(
+            200,
+            &json!({
+                "id": "resp_1",
+                "status": "completed",
+                "output": [{
+                    "type": "function_call",
+                    "id": "fc_1",
+                    "call_id": "call_1",
+                    "name": "read_file",
+                    "arguments": "{\"path\":\"src/xyz.rs\"}",
+                    "status": "completed",
+                }],
+                "usage": usage,
+            }),
+        ),
+        Reply::json(
+            200,
+            &json!({
+                "id": "resp_2",
+                "status": "completed",
+                "output": [{
+                    "type": "message",
+                    "id": "msg_1",
+                    "role": "assistant",
+                    "status": "completed",
+                    "content": [{
+                        "type": "output_text",
+                        "text": "XYZ is a library.",
+                        "annotations": [],
+                    }],
+                }],
+                "usage": usage,
+            }),
+        ),
+    ])
+    .await;
+    let model = ResponsesModel::new("test-key".to_owned(), "test-model".to_owned())
+        .with_base_url(format!("{}/v1", server.url));
+    let toolbox = Toolbox::new().with(Tool::new(
+        "read_file",
+        "Reads a file of the project",
+        json!({
+            "type": "object",
+            "properties": {"path": {"type": "string"}},
+            "required": ["path"],
+        }),
+        |arguments| async move { Ok(format!("// contents of {}", arguments["path"])) },
+    ));
+
+    let output = send_with_tools(&model, input(), &toolbox, &CancellationToken::new())
+        .await
+        .unwrap();
+
+    assert_eq!(output.items[0].parts, vec!["XYZ is a library.".to_owned()]);
+    assert_eq!(output.usage.unwrap().input_tokens, 20);
+    server.requests(|requests| {
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].body["tools"][0]["name"], json!("read_file"));
+        let input = requests[1].body["input"].as_array().unwrap();
+        assert_eq!(
+            input[1..],
+            [
+                json!({
+                    "type": "function_call",
+                    "call_id": "call_1",
+                    "name": "read_file",
+                    "arguments": "{\"path\":\"src/xyz.rs\"}",
+                }),
+                json!({
+                    "type": "function_call_output",
+                    "call_id": "call_1",
+                    "output": "// contents of \"src/xyz.rs\"",
+                }),
+            ]
+        );
+    });
+}
+
+#[tokio::test]
+async fn sends_images_as_input_images() {
+    let server = StubServer::start(vec![Reply::json(
+        200,
+        &json!({
+            "id": "resp_1",
+            "status": "completed",
+            "output": [],
+            "usage": {
+                "input_tokens": 100,
+                "input_tokens_details": {"cached_tokens": 0},
+                "output_tokens": 0,
+                "output_tokens_details": {"reasoning_tokens": 0},
+                "total_tokens": 100,
+            },
+        }),
+    )])
+    .await;
+    let model = ResponsesModel::new("test-key".to_owned(), "test-model".to_owned())
+        .with_base_url(format!("{}/v1", server.url))
+        .with_image_detail(ImageDetail::Low);
+    let mut input = input();
+    input.log[0].parts.extend([
+        TextImage::Base64Image("iVBORw0KGgo=".to_owned()),
+        TextImage::ImageUrl("https://example.com/diagram.jpg".to_owned()),
+    ]);
+
+    model.send(input).await.unwrap();
+
+    server.requests(|requests| {
+        assert_eq!(
+            requests[0].body["input"][0]["content"],
+            json!([
+                {"type": "input_text", "text": "What is XYZ?"},
+                {
+                    "type": "input_image",
+                    "detail": "low",
+                    "image_url": "data:image/png;base64,iVBORw0KGgo=",
+                },
+                {
+                    "type": "input_image",
+                    "detail": "low",
+                    "image_url": "https://example.com/diagram.jpg",
+                },
+            ])
+        );
+    });
+}
+
+#[tokio::test]
+async fn reports_how_the_response_ended() {
+    let cases = [
+        (
+            json!({
+                "status": "completed",
+                "output": [{
+                    "type": "message",
+                    "id": "msg_1",
+                    "role": "assistant",
+                    "status": "completed",
+                    "content": [{"type": "refusal", "refusal": "I can't help with that."}],
+                }],
+            }),
+            Outcome::Refused {
+                reason: "I can't help with that.".to_owned(),
+            },
+        ),
+        (
+            json!({
+                "status": "incomplete",
+                "incomplete_details": {"reason": "max_output_tokens"},
+                "output": [],
+            }),
+            Outcome::Incomplete(IncompleteReason::MaxTokens),
+        ),
+        (
+            json!({
+                "status": "failed",
+                "error": {"code": "server_error", "message": "Something went wrong"},
+                "output": [],
+                "usage": null,
+            }),
+            Outcome::Failed {
+                error: "Something went wrong".to_owned(),
+            },
+        ),
+    ];
+
+    for (mut body, expected) in cases {
+        body["id"] = json!("resp_1");
+        if body.get("usage").is_none() {
+            body["usage"] = json!({
+                "input_tokens": 10,
+                "input_tokens_details": {"cached_tokens": 0},
+                "output_tokens": 5,
+                "output_tokens_details": {"reasoning_tokens": 0},
+                "total_tokens": 15,
+            });
+        }
+        let server = StubServer::start(vec![Reply::json(200, &body)]).await;
+        let model = ResponsesModel::new("test-key".to_owned(), "test-model".to_owned())
+            .with_base_url(format!("{}/v1", server.url));
+
+        let output = model.send(input()).await.unwrap();
+
+        assert_eq!(output.outcome, expected, "body: {body}");
+        assert!(output.items.is_empty(), "body: {body}");
+    }
+}
+
+fn text_response(text: &str) -> serde_json::Value {
+    json!({
+        "id": "resp_1",
+        "status": "completed",
+        "output": [{
+            "type": "message",
+            "id": "msg_1",
+            "role": "assistant",
+            "status": "completed",
+            "content": [{"type": "output_text", "text": text, "annotations": []}],
+        }],
+        "usage": {
+            "input_tokens": 10,
+            "input_tokens_details": {"cached_tokens": 0},
+            "output_tokens": 5,
+            "output_tokens_details": {"reasoning_tokens": 0},
+            "total_tokens": 15,
+        },
+    })
+}
+
+#[tokio::test]
+async fn asks_again_for_json_matching_the_schema() {
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Definition {
+        term: String,
+        is_library: bool,
+    }
+
+    let server = StubServer::start(vec![
+        Reply::json(200, &text_response("{\"term\": \"XYZ\"}")),
+        Reply::json(
+            200,
+            &text_response("{\"term\": \"XYZ\", \"is_library\": true}"),
+        ),
+    ])
+    .await;
+    let model = ResponsesModel::new("test-key".to_owned(), "test-model".to_owned())
+        .with_base_url(format!("{}/v1", server.url));
+
+    let structured =
+        send_structured::<Definition, _, _>(&model, input(), &CancellationToken::new())
+            .await
+            .unwrap();
+
+    assert_eq!(
+        structured.value,
+        Some(Definition {
+            term: "XYZ".to_owned(),
+            is_library: true,
+        })
+    );
+    assert_eq!(structured.output.usage.unwrap().input_tokens, 20);
+    server.requests(|requests| {
+        let format = &requests[0].body["text"]["format"];
+        assert_eq!(format["type"], json!("json_schema"));
+        assert_eq!(format["name"], json!("Definition"));
+        assert_eq!(format["schema"]["required"], json!(["term", "is_library"]));
+        let input = requests[1].body["input"].as_array().unwrap();
+        assert_eq!(input.len(), 3);
+        assert_eq!(input[1]["role"], json!("assistant"));
+        let feedback = input[2]["content"][0]["text"].as_str().unwrap();
+        assert!(
+            feedback.contains("missing field `is_library`"),
+            "{feedback}"
+        );
+    });
+}
+
+fn error_body(type_: &str, code: &str, message: &str) -> serde_json::Value {
+    json!({"error": {"type": type_, "code": code, "message": message, "param": null}})
+}
+
+/// Retrying without waiting long
+fn fast_retries() -> HttpClient {
+    HttpClient::new(&HttpOptions {
+        retry: RetryPolicy {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_secs(1),
+        },
+        ..HttpOptions::default()
+    })
+    .unwrap()
+}
+
+#[tokio::test]
+async fn retries_rate_limited_requests() {
+    let server = StubServer::start(vec![
+        Reply::json(
+            429,
+            &error_body("requests", "rate_limit_exceeded", "Rate limit reached"),
+        )
+        .with_header("retry-after-ms", "20"),
+        Reply::json(500, &error_body("server_error", "server_error", "Oops")),
+        Reply::json(200, &text_response("XYZ is a library")),
+    ])
+    .await;
+    let model = ResponsesModel::new("test-key".to_owned(), "test-model".to_owned())
+        .with_base_url(format!("{}/v1", server.url))
+        .with_http_client(fast_retries());
+
+    let output = model.send(input()).await.unwrap();
+
+    assert_eq!(output.items[0].parts, vec!["XYZ is a library".to_owned()]);
+    server.requests(|requests| assert_eq!(requests.len(), 3));
+}
+
+#[tokio::test]
+async fn reports_api_errors_by_kind() {
+    let cases = [
+        (
+            vec![Reply::json(
+                401,
+                &error_body(
+                    "invalid_request_error",
+                    "invalid_api_key",
+                    "Incorrect API key provided",
+                ),
+            )],
+            ApiErrorKind::Authentication,
+        ),
+        (
+            vec![Reply::json(
+                429,
+                &error_body(
+                    "insufficient_quota",
+                    "insufficient_quota",
+                    "You exceeded your current quota",
+                ),
+            )],
+            ApiErrorKind::QuotaExceeded,
+        ),
+        (
+            vec![Reply::json(
+                404,
+                &error_body(
+                    "invalid_request_error",
+                    "model_not_found",
+                    "The model does not exist",
+                ),
+            )],
+            ApiErrorKind::InvalidRequest,
+        ),
+        (
+            (0..3)
+                .map(|_| {
+                    Reply::json(
+                        429,
+                        &error_body("requests", "rate_limit_exceeded", "Rate limit reached"),
+                    )
+                })
+                .collect(),
+            ApiErrorKind::RateLimited,
+        ),
+        // Asked to wait longer than the longest backoff
+        (
+            vec![
+                Reply::json(
+                    503,
+                    &error_body("server_error", "server_error", "Overloaded"),
+                )
+                .with_header("retry-after", "60"),
+            ],
+            ApiErrorKind::Server,
+        ),
+    ];
+
+    for (replies, expected) in cases {
+        let expected_requests = replies.len();
+        let server = StubServer::start(replies).await;
+        let model = ResponsesModel::new("test-key".to_owned(), "test-model".to_owned())
+            .with_base_url(format!("{}/v1", server.url))
+            .with_http_client(fast_retries());
+
+        let Err(err) = model.send(input()).await else {
+            panic!("expected {expected:?}");
+        };
+
+        let api_error = err.downcast_ref::<ApiError>().unwrap();
+        assert_eq!(api_error.kind, expected, "{api_error}");
+        server.requests(|requests| assert_eq!(requests.len(), expected_requests, "{api_error}"));
+    }
+}
+
+#[tokio::test]
+async fn shares_the_client_between_models() {
+    let server = StubServer::start_keep_alive(vec![
+        Reply::json(404, &json!({})),
+        Reply::json(200, &text_response("XYZ is a library")),
+        Reply::json(200, &text_response("XYZ is a library")),
+    ])
+    .await;
+    let http = HttpClient::default();
+    let models = [1, 2].map(|_| {
+        ResponsesModel::new("test-key".to_owned(), "test-model".to_owned())
+            .with_base_url(format!("{}/v1", server.url))
+            .with_http_client(http.clone())
+    });
+
+    http.prewarm(models[0].base_url()).await.unwrap();
+    for model in &models {
+        model.send(input()).await.unwrap();
+    }
+
+    // Both models use the connection opened by the pre-warming
+    let stats = http.stats();
+    assert_eq!(
+        stats,
+        ConnectionReuse {
+            requests: 3,
+            connections: 1,
+        }
+    );
+    assert_eq!(stats.reused(), 2);
+    server.requests(|requests| {
+        let paths: Vec<_> = requests.iter().map(|r| r.path.as_str()).collect();
+        assert_eq!(paths, ["/v1", "/v1/responses", "/v1/responses"]);
+    });
+}